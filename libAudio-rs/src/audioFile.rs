@@ -4,7 +4,7 @@ use std::{ffi::CString, os::{raw::c_void, unix::ffi::OsStrExt}, path::Path, ptr:
 use crate::{fileInfo::FileInfo, AudioType};
 use crate::bindings::
 {
-	audioCloseFile, audioGetFileInfo, audioOpenR, audioOpenW, audioPause, audioPlay, audioStop, isAudio
+	audioCloseFile, audioFillBuffer, audioGetFileInfo, audioOpenR, audioOpenW, audioPause, audioPlay, audioStop, isAudio
 };
 
 pub struct AudioFile
@@ -62,6 +62,14 @@ impl AudioFile
 		)
 	}
 
+	/// Decode audio from the file into the buffer given, returning how many bytes were decoded.
+	/// A return value of zero or less indicates the end of the file or an error
+	pub fn fillBuffer(&self, buffer: &mut [u8]) -> i64
+	{
+		let length = u32::try_from(buffer.len()).unwrap_or(u32::MAX);
+		unsafe { audioFillBuffer(self.inner.as_ptr(), buffer.as_mut_ptr().cast(), length) }
+	}
+
 	/// Play the file back (resumes playback if previously played and returned from)
 	pub fn play(&self)
 	{
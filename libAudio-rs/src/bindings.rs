@@ -40,7 +40,6 @@ extern "C"
 	// Read (decode) API functions
 	pub fn audioOpenR(fileName: *const c_char) -> *mut c_void;
 	pub fn audioGetFileInfo(audioFile: *mut c_void) -> *const FileInfo;
-	#[allow(dead_code)]
 	pub fn audioFillBuffer(audioFile: *mut c_void, buffer: *mut c_void, length: u32) -> i64;
 
 	// Playback API functions
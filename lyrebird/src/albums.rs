@@ -0,0 +1,349 @@
+// SPDX-License-Identifier: BSD-3-Clause
+use std::cmp::Ordering;
+use std::fmt::Display;
+use std::path::PathBuf;
+use std::sync::{Arc, RwLock};
+
+use crossterm::event::{KeyCode, KeyEvent, KeyEventKind};
+use ratatui::buffer::Buffer;
+use ratatui::layout::{Alignment, Constraint, Layout, Rect, Size};
+use ratatui::style::{Style, Stylize};
+use ratatui::text::{Line, Span};
+use ratatui::widgets::{Block, BorderType, List, ListItem, ListState, Padding, ScrollbarState, Widget};
+
+use crate::library::{Album, MusicLibrary};
+use crate::metadata::formatDuration;
+use crate::naturalOrder::naturalCmp;
+use crate::ratings::RatingChange;
+use crate::widgets::marks::Marks;
+use crate::widgets::renderScrolledList;
+use crate::window::Operation;
+
+/// View of the library by album, listing the tracks on the selected one
+pub struct Albums
+{
+	activeEntry: Style,
+	activePane: Pane,
+	order: AlbumOrder,
+	albumsState: ListState,
+	albumsScrollbar: ScrollbarState,
+	tracksState: ListState,
+	tracksScrollbar: ScrollbarState,
+	trackMarks: Marks,
+	viewportSize: Size,
+
+	library: Arc<RwLock<MusicLibrary>>,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Pane
+{
+	Albums,
+	Tracks,
+}
+
+/// What order the albums are listed in
+#[derive(Clone, Copy, Default)]
+enum AlbumOrder
+{
+	#[default]
+	Artist,
+	Title,
+	Year,
+	/// Most recently added first
+	DateAdded,
+}
+
+impl AlbumOrder
+{
+	/// Get the next order along, for cycling through them
+	const fn next(self) -> Self
+	{
+		match self
+		{
+			Self::Artist => Self::Title,
+			Self::Title => Self::Year,
+			Self::Year => Self::DateAdded,
+			Self::DateAdded => Self::Artist,
+		}
+	}
+
+	fn compare(self, lhs: &Album, rhs: &Album) -> Ordering
+	{
+		let byArtist = || naturalCmp(&lhs.artist, &rhs.artist).then_with(|| naturalCmp(&lhs.title, &rhs.title));
+		match self
+		{
+			Self::Artist => byArtist(),
+			Self::Title => naturalCmp(&lhs.title, &rhs.title).then_with(|| naturalCmp(&lhs.artist, &rhs.artist)),
+			// Albums with no year go after all those that have one
+			Self::Year => match (lhs.year, rhs.year)
+			{
+				(Some(lhsYear), Some(rhsYear)) => lhsYear.cmp(&rhsYear),
+				(Some(_), None) => Ordering::Less,
+				(None, Some(_)) => Ordering::Greater,
+				(None, None) => Ordering::Equal,
+			}.then_with(byArtist),
+			Self::DateAdded => rhs.dateAdded.cmp(&lhs.dateAdded).then_with(byArtist),
+		}
+	}
+}
+
+impl Display for AlbumOrder
+{
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result
+	{
+		match self
+		{
+			Self::Artist => write!(f, "artist"),
+			Self::Title => write!(f, "title"),
+			Self::Year => write!(f, "year"),
+			Self::DateAdded => write!(f, "date added"),
+		}
+	}
+}
+
+impl Albums
+{
+	pub fn new(activeEntry: Style, library: Arc<RwLock<MusicLibrary>>, viewportSize: Size) -> Self
+	{
+		Self
+		{
+			activeEntry,
+			activePane: Pane::Albums,
+			order: AlbumOrder::default(),
+			albumsState: ListState::default().with_selected(Some(0)),
+			albumsScrollbar: ScrollbarState::default(),
+			tracksState: ListState::default(),
+			tracksScrollbar: ScrollbarState::default(),
+			trackMarks: Marks::default(),
+			viewportSize,
+
+			library,
+		}
+	}
+
+	pub fn handleKeyEvent(&mut self, key: &KeyEvent) -> Operation
+	{
+		if key.kind == KeyEventKind::Press || key.kind == KeyEventKind::Repeat
+		{
+			// Marking tracks for acting on several at once, keeping the marks on the same tracks if the library's
+			// changed since they were made
+			let tracks = self.currentTracks();
+			self.trackMarks.followItems(&tracks);
+			if self.activePane == Pane::Tracks &&
+				self.trackMarks.handleKeyEvent(key, &mut self.tracksState, tracks.len())
+			{
+				return Operation::None;
+			}
+
+			// Rating a whole artist or album, or the chosen tracks
+			if let Some(change) = RatingChange::forKey(key.code)
+			{
+				return Operation::rate(self.makeSelection(), change);
+			}
+
+			match (self.activePane, key.code)
+			{
+				(_, KeyCode::Left) => self.activePane = Pane::Albums,
+				(_, KeyCode::Right) | (Pane::Albums, KeyCode::Enter) => self.moveRight(),
+				(_, KeyCode::Up) => self.moveBy(ListState::select_previous),
+				(_, KeyCode::Down) => self.moveBy(ListState::select_next),
+				(_, KeyCode::PageUp) =>
+				{
+					let height = self.viewportSize.height;
+					self.moveBy(|state| state.scroll_up_by(height));
+				},
+				(_, KeyCode::PageDown) =>
+				{
+					let height = self.viewportSize.height;
+					self.moveBy(|state| state.scroll_down_by(height));
+				},
+				(_, KeyCode::Char('o')) =>
+				{
+					// Changing the order moves everything about, so start back at the top
+					self.order = self.order.next();
+					self.albumsState.select_first();
+					self.resetTracks();
+				},
+				// Acting on a whole album, or the chosen tracks, in the same way as the Tree tab
+				(Pane::Tracks, KeyCode::Enter) | (Pane::Albums, KeyCode::Char('p')) =>
+					{ return Operation::play(self.makeSelection()); },
+				(_, KeyCode::Char('+')) => { return Operation::playlist(self.makeSelection()); },
+				(_, KeyCode::Char('i')) => { return Operation::insertNext(self.makeSelection()); },
+				(_, KeyCode::Char('a')) => { return Operation::addToPlaylist(self.makeSelection()); },
+				_ => {},
+			}
+		}
+		Operation::None
+	}
+
+	pub fn handleResize(&mut self, newSize: Size)
+		{ self.viewportSize = newSize; }
+
+	fn moveRight(&mut self)
+	{
+		self.activePane = Pane::Tracks;
+		if self.tracksState.selected().is_none()
+		{
+			self.tracksState.select_first();
+		}
+	}
+
+	/// Move the selection in the active pane, forgetting what was selected in the tracks pane if the album changes
+	fn moveBy(&mut self, movement: impl FnOnce(&mut ListState))
+	{
+		match self.activePane
+		{
+			Pane::Albums =>
+			{
+				movement(&mut self.albumsState);
+				self.resetTracks();
+			},
+			Pane::Tracks => movement(&mut self.tracksState),
+		}
+	}
+
+	fn resetTracks(&mut self)
+	{
+		self.tracksState = ListState::default();
+		self.trackMarks.clear();
+	}
+
+	/// Get the albums in the library in the order they're to be listed
+	fn sortedAlbums<'a>(&self, library: &'a MusicLibrary) -> Vec<&'a Album>
+	{
+		let mut albums: Vec<_> = library.albums().collect();
+		albums.sort_by(|lhs, rhs| self.order.compare(lhs, rhs));
+		albums
+	}
+
+	/// Get the tracks on the selected album, in track order
+	fn currentTracks(&self) -> Vec<PathBuf>
+	{
+		let Ok(library) = self.library.read()
+			else { return Vec::new(); };
+		self.albumsState.selected()
+			.and_then(|index| self.sortedAlbums(&library).get(index).map(|album| library.tracksOn(album)))
+			.unwrap_or_default()
+	}
+
+	/// Figure out which tracks the active pane's selection means - everything on the selected album, or the
+	/// marked (or failing that, selected) tracks
+	fn makeSelection(&self) -> Vec<PathBuf>
+	{
+		match self.activePane
+		{
+			Pane::Albums => self.currentTracks(),
+			Pane::Tracks =>
+			{
+				let mut tracks: Vec<_> = self.currentTracks().into_iter().map(Some).collect();
+				self.trackMarks.orSelected(self.tracksState.selected())
+					.into_iter()
+					.filter_map(|index| tracks.get_mut(index).and_then(Option::take))
+					.collect()
+			},
+		}
+	}
+
+	/// Build the block a pane is displayed in, highlighting its title if it's the active one
+	fn paneBlock(&self, title: String, pane: Pane) -> Block<'static>
+	{
+		Block::bordered()
+			.title(title)
+			.title_alignment(Alignment::Left)
+			.title_style(if self.activePane == pane { self.activeEntry } else { Style::default() })
+			.border_type(BorderType::Rounded)
+			// Make sure the contents are padded one space on the sides for presentation
+			.padding(Padding::horizontal(1))
+	}
+}
+
+impl Widget for &mut Albums
+{
+	fn render(self, area: Rect, buf: &mut Buffer)
+		where Self: Sized
+	{
+		// Split the display area up into the album listing and the tracks on the selected album
+		let layout = Layout::horizontal([Constraint::Fill(3), Constraint::Fill(2)])
+			.split(area);
+
+		// Get a lock on the library so we get a consistent view of it for rendering
+		let library = self.library.read().expect("Library lock in bad state");
+		let albums = self.sortedAlbums(&library);
+		let album = self.albumsState.selected().and_then(|index| albums.get(index));
+		let tracks = album.map(|album| library.tracksOn(album)).unwrap_or_default();
+		self.trackMarks.followItems(&tracks);
+
+		// Build the album listing, with each album's details after its title and artist
+		let albumItems = albums
+			.iter()
+			.map
+			(
+				|album|
+				{
+					let year = album.year.map(|year| format!("{year}, ")).unwrap_or_default();
+					let details = format!(" ({year}{} tracks, {})", album.trackCount(), formatDuration(album.duration));
+					// If the library's roots are being kept apart, say which one the album's in
+					let library = album.library.as_ref().map(|library| format!(" [{library}]")).unwrap_or_default();
+					ListItem::new
+					(
+						Line::from_iter
+						([
+							Span::from(format!("{} - {}", album.artist, album.title)),
+							Span::from(details).dim(),
+							Span::from(library).dim(),
+						])
+					)
+				}
+			);
+		let albumsList = List::new(albumItems)
+			.block(self.paneBlock(format!(" Albums (by {}) ", self.order), Pane::Albums))
+			.highlight_style(self.activeEntry);
+
+		// Build the track listing, numbering the tracks by disc too if the album's spread over several
+		let multiDisc = tracks.iter()
+			.any(|track| library.trackMetadata(track).and_then(|metadata| metadata.discNumber).unwrap_or(1) > 1);
+		let trackItems = tracks
+			.iter()
+			.map
+			(
+				|track|
+				{
+					let metadata = library.trackMetadata(track);
+					let trackNumber = metadata.and_then(|metadata| metadata.trackNumber);
+					let discNumber = metadata.and_then(|metadata| metadata.discNumber).unwrap_or(1);
+					let number = match trackNumber
+					{
+						Some(trackNumber) if multiDisc => format!("{discNumber}-{trackNumber:02} "),
+						Some(trackNumber) => format!("{trackNumber:02} "),
+						None => String::new(),
+					};
+					ListItem::new(format!("{number}{}", library.trackName(track)))
+				}
+			);
+		let tracksList = List::new(self.trackMarks.apply(trackItems))
+			.block(self.paneBlock(" Tracks ".to_string(), Pane::Tracks))
+			.highlight_style(self.activeEntry);
+
+		let viewportHeight = self.viewportSize.height.into();
+		renderScrolledList
+		(
+			albumsList,
+			albums.len(),
+			viewportHeight,
+			layout[0],
+			buf,
+			(&mut self.albumsState, &mut self.albumsScrollbar),
+		);
+		renderScrolledList
+		(
+			tracksList,
+			tracks.len(),
+			viewportHeight,
+			layout[1],
+			buf,
+			(&mut self.tracksState, &mut self.tracksScrollbar),
+		);
+	}
+}
+
@@ -0,0 +1,277 @@
+// SPDX-License-Identifier: BSD-3-Clause
+use std::path::PathBuf;
+use std::sync::{Arc, RwLock};
+
+use crossterm::event::{KeyCode, KeyEvent, KeyEventKind};
+use ratatui::buffer::Buffer;
+use ratatui::layout::{Alignment, Constraint, Layout, Rect, Size};
+use ratatui::style::Style;
+use ratatui::widgets::{Block, BorderType, List, ListItem, ListState, Padding, ScrollbarState, Widget};
+
+use crate::library::MusicLibrary;
+use crate::ratings::RatingChange;
+use crate::widgets::marks::Marks;
+use crate::widgets::renderScrolledList;
+use crate::window::Operation;
+
+/// View of the library by artist, drilling down through their albums to the tracks on each
+pub struct Artists
+{
+	activeEntry: Style,
+	activePane: Pane,
+	artistsState: ListState,
+	artistsScrollbar: ScrollbarState,
+	albumsState: ListState,
+	albumsScrollbar: ScrollbarState,
+	tracksState: ListState,
+	tracksScrollbar: ScrollbarState,
+	trackMarks: Marks,
+	viewportSize: Size,
+
+	library: Arc<RwLock<MusicLibrary>>,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Pane
+{
+	Artists,
+	Albums,
+	Tracks,
+}
+
+impl Artists
+{
+	pub fn new(activeEntry: Style, library: Arc<RwLock<MusicLibrary>>, viewportSize: Size) -> Self
+	{
+		Self
+		{
+			activeEntry,
+			activePane: Pane::Artists,
+			artistsState: ListState::default().with_selected(Some(0)),
+			artistsScrollbar: ScrollbarState::default(),
+			albumsState: ListState::default(),
+			albumsScrollbar: ScrollbarState::default(),
+			tracksState: ListState::default(),
+			tracksScrollbar: ScrollbarState::default(),
+			trackMarks: Marks::default(),
+			viewportSize,
+
+			library,
+		}
+	}
+
+	pub fn handleKeyEvent(&mut self, key: &KeyEvent) -> Operation
+	{
+		if key.kind == KeyEventKind::Press || key.kind == KeyEventKind::Repeat
+		{
+			// Marking tracks for acting on several at once, keeping the marks on the same tracks if the library's
+			// changed since they were made
+			let tracks = self.currentTracks();
+			self.trackMarks.followItems(&tracks);
+			if self.activePane == Pane::Tracks &&
+				self.trackMarks.handleKeyEvent(key, &mut self.tracksState, tracks.len())
+			{
+				return Operation::None;
+			}
+
+			// Rating a whole artist or album, or the chosen tracks
+			if let Some(change) = RatingChange::forKey(key.code)
+			{
+				return Operation::rate(self.makeSelection(), change);
+			}
+
+			match (self.activePane, key.code)
+			{
+				(_, KeyCode::Left) => self.moveLeft(),
+				(_, KeyCode::Right) | (Pane::Artists | Pane::Albums, KeyCode::Enter) => self.moveRight(),
+				(_, KeyCode::Up) => self.moveBy(ListState::select_previous),
+				(_, KeyCode::Down) => self.moveBy(ListState::select_next),
+				(_, KeyCode::PageUp) =>
+				{
+					let height = self.viewportSize.height;
+					self.moveBy(|state| state.scroll_up_by(height));
+				},
+				(_, KeyCode::PageDown) =>
+				{
+					let height = self.viewportSize.height;
+					self.moveBy(|state| state.scroll_down_by(height));
+				},
+				// Acting on a whole artist or album, or the chosen tracks, in the same way as the Tree tab
+				(Pane::Tracks, KeyCode::Enter) | (Pane::Artists | Pane::Albums, KeyCode::Char('p')) =>
+					{ return Operation::play(self.makeSelection()); },
+				(_, KeyCode::Char('+')) => { return Operation::playlist(self.makeSelection()); },
+				(_, KeyCode::Char('i')) => { return Operation::insertNext(self.makeSelection()); },
+				(_, KeyCode::Char('a')) => { return Operation::addToPlaylist(self.makeSelection()); },
+				_ => {},
+			}
+		}
+		Operation::None
+	}
+
+	pub fn handleResize(&mut self, newSize: Size)
+		{ self.viewportSize = newSize; }
+
+	const fn moveLeft(&mut self)
+	{
+		self.activePane = match self.activePane
+		{
+			Pane::Artists | Pane::Albums => Pane::Artists,
+			Pane::Tracks => Pane::Albums,
+		};
+	}
+
+	fn moveRight(&mut self)
+	{
+		self.activePane = match self.activePane
+		{
+			Pane::Artists => Pane::Albums,
+			Pane::Albums | Pane::Tracks => Pane::Tracks,
+		};
+		// Make sure there's something selected in the pane just moved into
+		let state = match self.activePane
+		{
+			Pane::Artists => &mut self.artistsState,
+			Pane::Albums => &mut self.albumsState,
+			Pane::Tracks => &mut self.tracksState,
+		};
+		if state.selected().is_none()
+		{
+			state.select_first();
+		}
+	}
+
+	/// Move the selection in the active pane, forgetting what was selected in the panes to its right
+	fn moveBy(&mut self, movement: impl FnOnce(&mut ListState))
+	{
+		match self.activePane
+		{
+			Pane::Artists =>
+			{
+				movement(&mut self.artistsState);
+				self.albumsState = ListState::default();
+				self.resetTracks();
+			},
+			Pane::Albums =>
+			{
+				movement(&mut self.albumsState);
+				self.resetTracks();
+			},
+			Pane::Tracks => movement(&mut self.tracksState),
+		}
+	}
+
+	fn resetTracks(&mut self)
+	{
+		self.tracksState = ListState::default();
+		self.trackMarks.clear();
+	}
+
+	/// Get the tracks on the selected album in the order they're listed
+	fn currentTracks(&self) -> Vec<PathBuf>
+	{
+		let Ok(library) = self.library.read()
+			else { return Vec::new(); };
+		match (self.artistsState.selected(), self.albumsState.selected())
+		{
+			(Some(artist), Some(album)) => library.albumTracks(artist, album),
+			_ => Vec::new(),
+		}
+	}
+
+	/// Figure out which tracks the active pane's selection means - everything by the selected artist, everything
+	/// on the selected album, or the marked (or failing that, selected) tracks
+	fn makeSelection(&self) -> Vec<PathBuf>
+	{
+		match self.activePane
+		{
+			Pane::Artists =>
+			{
+				let Ok(library) = self.library.read()
+					else { return Vec::new(); };
+				self.artistsState.selected()
+					.map(|artist| library.artistTracks(artist))
+					.unwrap_or_default()
+			},
+			Pane::Albums => self.currentTracks(),
+			Pane::Tracks =>
+			{
+				let mut tracks: Vec<_> = self.currentTracks().into_iter().map(Some).collect();
+				self.trackMarks.orSelected(self.tracksState.selected())
+					.into_iter()
+					.filter_map(|index| tracks.get_mut(index).and_then(Option::take))
+					.collect()
+			},
+		}
+	}
+
+	/// Build the block a pane is displayed in, highlighting its title if it's the active one
+	fn paneBlock(&self, title: &'static str, pane: Pane) -> Block<'static>
+	{
+		Block::bordered()
+			.title(title)
+			.title_alignment(Alignment::Left)
+			.title_style(if self.activePane == pane { self.activeEntry } else { Style::default() })
+			.border_type(BorderType::Rounded)
+			// Make sure the contents are padded one space on the sides for presentation
+			.padding(Padding::horizontal(1))
+	}
+}
+
+impl Widget for &mut Artists
+{
+	fn render(self, area: Rect, buf: &mut Buffer)
+		where Self: Sized
+	{
+		// Split the display area up into the artist listing, their albums, and the tracks on the selected album
+		let layout = Layout::horizontal([Constraint::Fill(1), Constraint::Fill(1), Constraint::Fill(2)])
+			.split(area);
+
+		// Get a lock on the library so we get a consistent view of it for rendering
+		let library = self.library.read().expect("Library lock in bad state");
+		let artist = self.artistsState.selected();
+		let tracks = match (artist, self.albumsState.selected())
+		{
+			(Some(artist), Some(album)) => library.albumTracks(artist, album),
+			_ => Vec::new(),
+		};
+		self.trackMarks.followItems(&tracks);
+
+		let artistsList = List::new(library.artists())
+			.block(self.paneBlock(" Artists ", Pane::Artists));
+		let albumsList = List::new(library.albumsBy(artist))
+			.block(self.paneBlock(" Albums ", Pane::Albums));
+		let trackNames = tracks.iter().map(|track| ListItem::new(library.trackName(track)));
+		let tracksList = List::new(self.trackMarks.apply(trackNames))
+			.block(self.paneBlock(" Tracks ", Pane::Tracks));
+
+		let viewportHeight = self.viewportSize.height.into();
+		renderScrolledList
+		(
+			artistsList.highlight_style(self.activeEntry),
+			library.artistCount(),
+			viewportHeight,
+			layout[0],
+			buf,
+			(&mut self.artistsState, &mut self.artistsScrollbar),
+		);
+		renderScrolledList
+		(
+			albumsList.highlight_style(self.activeEntry),
+			library.albumCount(artist),
+			viewportHeight,
+			layout[1],
+			buf,
+			(&mut self.albumsState, &mut self.albumsScrollbar),
+		);
+		renderScrolledList
+		(
+			tracksList.highlight_style(self.activeEntry),
+			tracks.len(),
+			viewportHeight,
+			layout[2],
+			buf,
+			(&mut self.tracksState, &mut self.tracksScrollbar),
+		);
+	}
+}
+
@@ -0,0 +1,130 @@
+// SPDX-License-Identifier: BSD-3-Clause
+use std::path::PathBuf;
+
+use clap::{Parser, Subcommand};
+use color_eyre::eyre::{eyre, Result};
+use directories::ProjectDirs;
+use ratatui::style::Style;
+
+use crate::config::Config;
+use crate::history::History;
+use crate::library::MusicLibrary;
+use crate::playlistFile::PathStyle;
+use crate::playlists::Playlists;
+use crate::query::Query;
+use crate::ratings::Ratings;
+
+/// Music player written in Rust based on libAudio. Run without a command to start the player itself
+#[derive(Parser)]
+#[command(version)]
+pub struct Arguments
+{
+	#[command(subcommand)]
+	pub command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+pub enum Command
+{
+	/// Import playlist files (M3U, M3U8, XSPF, PLS) as new saved playlists. Entries that can't be found where the
+	/// playlist says are looked for in the library
+	Import
+	{
+		#[arg(required = true)]
+		files: Vec<PathBuf>,
+	},
+	/// Export a saved playlist (or Now Playing) to a playlist file, its format picked by the file's extension
+	Export
+	{
+		/// Name of the playlist to export
+		name: String,
+		/// File to write the playlist to
+		file: PathBuf,
+		/// Write the absolute paths to entries rather than paths relative to the playlist file
+		#[arg(long)]
+		absolute: bool,
+	},
+	/// List the tracks in the library matching a query, such as `artist:rammstein year:>1999 -genre:live`. Fields
+	/// can be title, artist, album, albumartist, genre, format, filename, path, year, track, disc, channels, bits,
+	/// samplerate (in Hz), bitrate (in kb/s), duration and lossless; terms can be combined with AND, OR and NOT, and
+	/// saved queries used with @name
+	Query
+	{
+		#[arg(required = true)]
+		query: Vec<String>,
+		/// Save the query under this name, for using again later
+		#[arg(long)]
+		save: Option<String>,
+	},
+	/// List the saved queries
+	Queries,
+}
+
+impl Command
+{
+	/// Run the command against the user's stored state, rather than bringing up the player
+	pub fn run(self, paths: &ProjectDirs) -> Result<()>
+	{
+		let mut playlists = Playlists::read(paths, Style::default())?;
+		match self
+		{
+			Self::Import { files } =>
+			{
+				// Importing into playlists that can't be saved would only throw the imported playlists away again
+				if playlists.readOnly()
+				{
+					return Err(eyre!("Playlists are from a newer version of Lyrebird, not importing into them"));
+				}
+				// Use what's known of the library from the last time the player ran to find missing entries in
+				let config = Config::read(paths)?;
+				let library = MusicLibrary::fromCache(paths.cache_dir(), &config);
+				for file in files
+				{
+					let name = playlists.importFile(&file, Some(&library))?;
+					println!("Imported {} as playlist {name}", file.display());
+				}
+				playlists.write(paths)
+			},
+			Self::Export { name, file, absolute } =>
+			{
+				let style = if absolute { PathStyle::Absolute } else { PathStyle::Relative };
+				// What's known of the library from the last time the player ran gives the metadata for the entries
+				let config = Config::read(paths)?;
+				let library = MusicLibrary::fromCache(paths.cache_dir(), &config);
+				playlists.exportNamed(&name, &file, style, &library)?;
+				println!("Exported playlist {name} to {}", file.display());
+				Ok(())
+			},
+			Self::Query { query, save } =>
+			{
+				let mut config = Config::read(paths)?;
+				let text = query.join(" ");
+				let parsed = Query::parse(&text, &config.savedQueries)?;
+				let library = MusicLibrary::fromCache(paths.cache_dir(), &config);
+				let history = History::read(paths)?;
+				let ratings = Ratings::read(paths)?;
+				for track in library.tracksMatching(&parsed, &history.statistics(), &ratings.ratings())
+				{
+					println!("{}", track.display());
+				}
+				// The tracks go to stdout for handing on to other programs, so say the query's been saved elsewhere
+				if let Some(name) = save
+				{
+					config.saveQuery(&name, &text)?;
+					config.write(paths)?;
+					eprintln!("Saved query as {name}");
+				}
+				Ok(())
+			},
+			Self::Queries =>
+			{
+				let config = Config::read(paths)?;
+				for (name, query) in &config.savedQueries
+				{
+					println!("{name}: {query}");
+				}
+				Ok(())
+			},
+		}
+	}
+}
@@ -1,15 +1,112 @@
-use std::{fs::{create_dir_all, File}, path::PathBuf};
+use std::collections::{BTreeMap, BTreeSet};
+use std::{fs::File, path::PathBuf};
 
-use color_eyre::eyre::Result;
+use color_eyre::eyre::{eyre, Result};
 use directories::{ProjectDirs, UserDirs};
 use serde::{Deserialize, Serialize};
 use serde_repr::{Deserialize_repr, Serialize_repr};
 
+use crate::library::cacheName;
+use crate::persistence::writeJsonAtomically;
+
 #[derive(Serialize, Deserialize)]
 pub struct Config
 {
 	version: ConfigVersion,
-	pub libraryPath: PathBuf,
+	/// Where the library lived before it could be spread over several roots, only read to upgrade old configs
+	#[serde(default, skip_serializing)]
+	libraryPath: Option<PathBuf>,
+	/// The directories making up the user's music library, each discovered and cached on its own
+	#[serde(default)]
+	pub libraryRoots: Vec<LibraryRoot>,
+	/// Whether to show the tracks from all the library roots together in the Artists and Albums tabs
+	#[serde(default)]
+	pub mergeLibraries: bool,
+	/// Whether to pick playback back up where it was left off when Lyrebird is started
+	#[serde(default)]
+	pub resumePlayback: bool,
+	/// What to leave out when looking through the library for music
+	#[serde(default)]
+	pub discovery: DiscoveryOptions,
+	/// Library queries the user has saved, by name
+	#[serde(default)]
+	pub savedQueries: BTreeMap<String, String>,
+}
+
+/// Options controlling which files and directories library discovery looks at
+#[derive(Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DiscoveryOptions
+{
+	/// Gitignore-style patterns for what to leave out of every library root, on top of any `.lyrebirdignore`
+	/// files found in the roots themselves
+	#[serde(default)]
+	pub ignorePatterns: Vec<String>,
+	/// Whether to leave out hidden files and directories (those whose names start with a `.`)
+	#[serde(default = "defaultSkipHidden")]
+	pub skipHidden: bool,
+	/// Smallest a file can be, in bytes, for it to be looked at - anything smaller can't hold useful audio
+	#[serde(default)]
+	pub minimumFileSize: u64,
+	/// How many directories down into each root to look, if that's to be limited
+	#[serde(default)]
+	pub maximumDepth: Option<usize>,
+	/// Whether to follow symbolic links to files and directories - loops and files reached by more than one path
+	/// are taken care of either way
+	#[serde(default = "defaultFollowSymlinks")]
+	pub followSymlinks: bool,
+}
+
+const fn defaultSkipHidden() -> bool
+{
+	true
+}
+
+const fn defaultFollowSymlinks() -> bool
+{
+	true
+}
+
+impl Default for DiscoveryOptions
+{
+	fn default() -> Self
+	{
+		Self
+		{
+			ignorePatterns: Vec::new(),
+			skipHidden: defaultSkipHidden(),
+			minimumFileSize: 0,
+			maximumDepth: None,
+			followSymlinks: defaultFollowSymlinks(),
+		}
+	}
+}
+
+/// A directory making up part of the user's music library, and the name to show it by
+#[derive(Clone, Serialize, Deserialize)]
+pub struct LibraryRoot
+{
+	pub name: String,
+	pub path: PathBuf,
+	/// Whether to watch the root for changes as they happen - this can be unreliable on network filesystems
+	#[serde(default = "defaultWatch")]
+	pub watch: bool,
+}
+
+const fn defaultWatch() -> bool
+{
+	true
+}
+
+impl LibraryRoot
+{
+	/// Make a root for a directory, naming it after the directory
+	fn named(path: PathBuf) -> Self
+	{
+		let name = path.file_name()
+			.map_or_else(|| path.to_string_lossy(), |name| name.to_string_lossy())
+			.to_string();
+		Self { name, path, watch: defaultWatch() }
+	}
 }
 
 #[derive(Serialize_repr, Deserialize_repr, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
@@ -17,6 +114,13 @@ pub struct Config
 pub enum ConfigVersion
 {
 	Version1 = 1,
+	/// The library is made up of several named roots rather than a single path
+	Version2 = 2,
+}
+
+impl ConfigVersion
+{
+	const CURRENT: Self = Self::Version2;
 }
 
 impl Config
@@ -28,7 +132,9 @@ impl Config
 		if configPath.exists()
 		{
 			let configFile = File::open(configPath)?;
-			let config = serde_json::from_reader(configFile)?;
+			let mut config: Self = serde_json::from_reader(configFile)?;
+			config.upgrade();
+			config.validate()?;
 
 			return Ok(config);
 		}
@@ -36,13 +142,46 @@ impl Config
 		Ok(Self::default())
 	}
 
+	/// Bring a configuration read in from an older version of Lyrebird up to date
+	fn upgrade(&mut self)
+	{
+		// The single library path becomes the one and only library root
+		if self.version < ConfigVersion::Version2
+		{
+			self.libraryRoots.extend(self.libraryPath.take().map(LibraryRoot::named));
+		}
+		self.version = ConfigVersion::CURRENT;
+	}
+
+	fn validate(&self) -> Result<()>
+	{
+		// The roots are cached by name, so two whose names come out the same once made safe for a file name would
+		// trample each other's caches - and that includes by case, for filesystems that don't tell case apart
+		let mut names = BTreeSet::new();
+		match self.libraryRoots.iter().find(|root| !names.insert(cacheName(&root.name).to_lowercase()))
+		{
+			Some(root) => Err(eyre!("Library root name '{}' is used more than once", root.name)),
+			None => Ok(()),
+		}
+	}
+
+	/// Save a library query under the name given, replacing any already saved under it. Names are used as `@name`
+	/// in other queries, so can only be made of letters, numbers, `-` and `_`
+	pub fn saveQuery(&mut self, name: &str, query: &str) -> Result<()>
+	{
+		let validName = !name.is_empty() &&
+			name.chars().all(|character| character.is_alphanumeric() || character == '-' || character == '_');
+		if !validName
+		{
+			return Err(eyre!("'{}' can't be used as a query name", name));
+		}
+		self.savedQueries.insert(name.to_string(), query.to_string());
+		Ok(())
+	}
+
 	pub fn write(&self, paths: &ProjectDirs) -> Result<()>
 	{
-		let configPath = paths.config_dir();
-		create_dir_all(configPath)?;
-		let configPath = configPath.join("config.json");
-		let configFile = File::create(configPath)?;
-		Ok(serde_json::to_writer(configFile, self)?)
+		writeJsonAtomically(&paths.config_dir().join("config.json"), self)
 	}
 }
 
@@ -58,8 +197,13 @@ impl Default for Config
 		// Generate a configuration with this data
 		Self
 		{
-			version: ConfigVersion::Version1,
-			libraryPath: musicDir.to_path_buf(),
+			version: ConfigVersion::CURRENT,
+			libraryPath: None,
+			libraryRoots: vec![LibraryRoot::named(musicDir.to_path_buf())],
+			mergeLibraries: false,
+			resumePlayback: false,
+			discovery: DiscoveryOptions::default(),
+			savedQueries: BTreeMap::new(),
 		}
 	}
 }
@@ -0,0 +1,87 @@
+// SPDX-License-Identifier: BSD-3-Clause
+
+/// Bonus for a pattern character matching at the start of a word in the text
+const WORD_START_BONUS: usize = 8;
+/// Bonus for a pattern character matching straight after the one before it
+const CONSECUTIVE_BONUS: usize = 4;
+
+/// How well a pattern fuzzily matched some text, and which characters of the text it matched
+pub struct FuzzyMatch
+{
+	pub score: usize,
+	/// Indices of the characters (not bytes) of the text that the pattern matched, in order
+	pub positions: Vec<usize>,
+}
+
+/// Fuzzily match a pattern against some text, ignoring case. The pattern matches if all its characters appear in
+/// the text in order, though not necessarily next to each other - matches at the start of words and runs of
+/// consecutive characters score higher, and gaps between matched characters score lower
+pub fn fuzzyMatch(pattern: &str, text: &str) -> Option<FuzzyMatch>
+{
+	let pattern: Vec<_> = pattern.chars().map(lowercase).collect();
+	let text: Vec<_> = text.chars().collect();
+	let lowered: Vec<_> = text.iter().copied().map(lowercase).collect();
+	let first = *pattern.first()?;
+
+	// Try matching from each place the first character of the pattern appears, keeping whichever scores best
+	lowered
+		.iter()
+		.enumerate()
+		.filter(|&(_, &character)| character == first)
+		.filter_map(|(start, _)| matchFrom(&pattern, &text, &lowered, start))
+		.max_by_key(|fuzzyMatch| fuzzyMatch.score)
+}
+
+/// Match the pattern against the text starting from the given character, taking the first of each character of
+/// the pattern that appears after that
+fn matchFrom(pattern: &[char], text: &[char], lowered: &[char], start: usize) -> Option<FuzzyMatch>
+{
+	let mut positions = Vec::with_capacity(pattern.len());
+	let mut index = start;
+	for &character in pattern
+	{
+		index += lowered.get(index..)?.iter().position(|&candidate| candidate == character)?;
+		positions.push(index);
+		index += 1;
+	}
+
+	let mut bonus = 0;
+	let mut gaps = 0;
+	let mut previous: Option<usize> = None;
+	for &position in &positions
+	{
+		bonus += 1;
+		if isWordStart(text, position)
+		{
+			bonus += WORD_START_BONUS;
+		}
+		match previous
+		{
+			Some(previous) if position == previous + 1 => bonus += CONSECUTIVE_BONUS,
+			Some(previous) => gaps += position - previous - 1,
+			None => {},
+		}
+		previous = Some(position);
+	}
+	Some(FuzzyMatch { score: bonus.saturating_sub(gaps), positions })
+}
+
+/// Check if the character at the given index is the start of a word - either the first character, one after a
+/// non-alphanumeric character, or an upper case one after a lower case one
+fn isWordStart(text: &[char], index: usize) -> bool
+{
+	let Some(previous) = index.checked_sub(1).map(|index| text[index])
+		else { return true; };
+	!previous.is_alphanumeric() || (previous.is_lowercase() && text[index].is_uppercase())
+}
+
+/// Get the lower case version of a character, leaving it as-is if that'd take more than one character
+fn lowercase(character: char) -> char
+{
+	let mut lowered = character.to_lowercase();
+	match (lowered.next(), lowered.next())
+	{
+		(Some(lowered), None) => lowered,
+		_ => character,
+	}
+}
@@ -0,0 +1,195 @@
+// SPDX-License-Identifier: BSD-3-Clause
+use std::collections::BTreeMap;
+use std::fs::{create_dir_all, read, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+use color_eyre::eyre::{OptionExt, Result};
+use directories::ProjectDirs;
+use serde::{Deserialize, Serialize};
+use tracing::error;
+
+/// Record of everything that's been played, kept in the data directory as a log with one event per line so
+/// recording something new only ever needs it adding to the end
+pub struct History
+{
+	path: PathBuf,
+	events: Vec<PlayEvent>,
+	/// Play counts and the like for each track, kept up to date as events are recorded
+	statistics: Arc<TrackStatistics>,
+	/// Count of events recorded since the history was read in, so anything worked out from it can tell when it
+	/// needs working out again
+	generation: u64,
+	/// Whether the log was cut off part way through a line, so the next event needs to start on a new one
+	unterminated: bool,
+}
+
+/// Something that happened to the playback of a track
+#[derive(Serialize, Deserialize)]
+pub struct PlayEvent
+{
+	pub file: PathBuf,
+	pub kind: PlayEventKind,
+	/// How far into the track playback was when this happened
+	pub position: Duration,
+	pub time: SystemTime,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum PlayEventKind
+{
+	Started,
+	/// Played right through to the end
+	Completed,
+	/// Moved on to another track before this one finished
+	Skipped,
+	/// Playback stopped (or Lyrebird was closed) before the track finished
+	Stopped,
+}
+
+/// What the history says about one track
+#[derive(Clone, Copy, Default)]
+pub struct TrackStats
+{
+	/// How many times the track has been played right through
+	pub plays: u32,
+	pub skips: u32,
+	pub lastPlayed: Option<SystemTime>,
+	/// How long has been spent listening to the track in total
+	pub listened: Duration,
+}
+
+/// What the history says about each track that's been played
+#[derive(Clone, Default)]
+pub struct TrackStatistics(BTreeMap<PathBuf, TrackStats>);
+
+impl History
+{
+	/// Read the play history back in from the data directory, or start afresh if nothing's been played yet
+	pub fn read(paths: &ProjectDirs) -> Result<Self>
+	{
+		let path = paths.data_dir().join("history.jsonl");
+		let mut history = Self
+		{
+			path,
+			events: Vec::new(),
+			statistics: Arc::default(),
+			generation: 0,
+			unterminated: false,
+		};
+
+		if history.path.exists()
+		{
+			// Read the log as bytes and decode it a line at a time, so a line cut off part way through a character
+			// only costs that line rather than the whole log failing to be read
+			let contents = read(&history.path)?;
+			history.unterminated = contents.last().is_some_and(|&byte| byte != b'\n');
+			for line in contents.split(|&byte| byte == b'\n')
+			{
+				let line = String::from_utf8_lossy(line);
+				let line = line.trim_end_matches('\r');
+				if line.is_empty()
+				{
+					continue;
+				}
+				// A line that can't be understood (eg, cut short by a crash while it was being written) only loses
+				// that one event, so skip over it rather than losing the whole history
+				match serde_json::from_str(line)
+				{
+					Ok(event) => history.add(event),
+					Err(error) => error!("Skipping unreadable play history entry: {}", error),
+				}
+			}
+		}
+		Ok(history)
+	}
+
+	/// Record something happening to the playback of a track, writing it out to the log straight away
+	pub fn record(&mut self, file: &Path, kind: PlayEventKind, position: Duration) -> Result<()>
+	{
+		let event = PlayEvent { file: file.to_path_buf(), kind, position, time: SystemTime::now() };
+
+		// Make sure the data directory exists, and then add the event on the end of the log
+		create_dir_all
+		(
+			self.path.parent()
+				.ok_or_eyre("Failed to extract the directory to write the play history into")?
+		)?;
+		let mut historyFile = OpenOptions::new().create(true).append(true).open(&self.path)?;
+		let mut line = if self.unterminated { vec![b'\n'] } else { Vec::new() };
+		serde_json::to_writer(&mut line, &event)?;
+		line.push(b'\n');
+		historyFile.write_all(&line)?;
+		self.unterminated = false;
+
+		self.add(event);
+		self.generation += 1;
+		Ok(())
+	}
+
+	/// Add an event to the history, bringing the statistics for its track up to date
+	fn add(&mut self, event: PlayEvent)
+	{
+		let listened = listenedFor(self.events.last(), &event);
+		let stats = Arc::make_mut(&mut self.statistics).0
+			.entry(event.file.clone())
+			.or_default();
+		match event.kind
+		{
+			PlayEventKind::Started => stats.lastPlayed = Some(event.time),
+			PlayEventKind::Completed => stats.plays += 1,
+			PlayEventKind::Skipped => stats.skips += 1,
+			PlayEventKind::Stopped => {},
+		}
+		stats.listened += listened;
+		self.events.push(event);
+	}
+
+	/// Go through the events recorded, along with how much of the track was listened to leading up to each
+	pub fn listening(&self) -> impl Iterator<Item = (&PlayEvent, Duration)>
+	{
+		self.events
+			.iter()
+			.enumerate()
+			.map
+			(
+				|(index, event)|
+					(event, listenedFor(index.checked_sub(1).map(|previous| &self.events[previous]), event))
+			)
+	}
+
+	/// Get the statistics for each track, as a handle that can be held on to while more gets recorded
+	pub fn statistics(&self) -> Arc<TrackStatistics>
+	{
+		self.statistics.clone()
+	}
+
+	/// Get how many events have been recorded since the history was read in, which only ever goes up
+	pub const fn generation(&self) -> u64
+	{
+		self.generation
+	}
+}
+
+impl TrackStatistics
+{
+	pub fn get(&self, file: &Path) -> Option<&TrackStats>
+	{
+		self.0.get(file)
+	}
+}
+
+/// Work out how much of a track was listened to leading up to an event - if the event before it was the track
+/// starting, that's how far it got from where it started, otherwise there's nothing to go on
+fn listenedFor(previous: Option<&PlayEvent>, event: &PlayEvent) -> Duration
+{
+	match previous
+	{
+		Some(previous) if previous.kind == PlayEventKind::Started && previous.file == event.file &&
+			event.kind != PlayEventKind::Started =>
+			event.position.saturating_sub(previous.position),
+		_ => Duration::ZERO,
+	}
+}
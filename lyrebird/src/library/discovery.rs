@@ -0,0 +1,509 @@
+// SPDX-License-Identifier: BSD-3-Clause
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Display;
+use std::num::NonZero;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{sync_channel, Receiver, RecvTimeoutError, SyncSender};
+use std::sync::{Mutex, PoisonError, RwLock};
+use std::thread::{self, available_parallelism};
+use std::time::{Duration, Instant, SystemTime};
+
+use color_eyre::eyre::{eyre, Result};
+use libAudio::audioFile::AudioFile;
+use tokio_util::sync::CancellationToken;
+use tracing::error;
+
+use super::rules::{IgnoreFiles, IgnoreRules};
+use super::{FileIdentity, FileStamp, MusicLibrary, Root};
+use crate::metadata::{formatDuration, TrackMetadata};
+
+/// Most threads to probe files on at once - past this, the disk is the bottleneck rather than the probing
+const MAX_WORKERS: usize = 8;
+/// How many files can be waiting to be probed per worker before the walker has to wait for them to catch up
+const QUEUE_DEPTH: usize = 64;
+/// How many probed files to fold into the library under a single write lock
+const BATCH_SIZE: usize = 256;
+/// Longest to hold on to probed files before folding them in, so the UI keeps up on slow disks
+const BATCH_INTERVAL: Duration = Duration::from_millis(250);
+
+/// How far through discovering the library we are, updated as the discovery runs
+pub struct DiscoveryProgress
+{
+	started: Instant,
+	directoriesVisited: AtomicUsize,
+	/// Files that are new or have changed, and so need probing
+	filesQueued: AtomicUsize,
+	filesProbed: AtomicUsize,
+	audioFound: AtomicUsize,
+}
+
+/// A file the walker has found that needs probing to see if it's audio
+struct Probe
+{
+	file: PathBuf,
+	stamp: Option<FileStamp>,
+	identity: Option<FileIdentity>,
+}
+
+/// A file found in a directory, along with its stamp and what it is on disk
+type FileEntry = (PathBuf, Option<FileStamp>, Option<FileIdentity>);
+/// The subdirectories and files found in a directory
+type DirectoryContents = (Vec<PathBuf>, Vec<FileEntry>);
+
+/// A directory the walker's yet to look through, along with the ignore files that apply in it
+struct PendingDirectory
+{
+	path: PathBuf,
+	/// How many levels below the base of the library root the directory is
+	depth: usize,
+	ignoreFiles: IgnoreFiles,
+}
+
+/// What the walker's come across so far, so it doesn't go round in circles following links back up the tree,
+/// and so files reached by more than one path only get into the library the once
+#[derive(Default)]
+struct Seen
+{
+	directories: BTreeSet<FileIdentity>,
+	files: BTreeMap<FileIdentity, PathBuf>,
+}
+
+/// The result of probing a file - its metadata if it turned out to be audio
+struct Probed
+{
+	file: PathBuf,
+	stamp: Option<FileStamp>,
+	identity: Option<FileIdentity>,
+	metadata: Option<TrackMetadata>,
+}
+
+impl Seen
+{
+	/// Leave out any files that we've already got by another path, either earlier in this walk or in the library
+	/// from before - so long as that other path still leads somewhere
+	fn dropDuplicates(&mut self, files: &mut Vec<FileEntry>, libraryRoot: &Root)
+	{
+		files.retain
+		(
+			|(file, _, identity)|
+			{
+				let Some(identity) = identity
+					else { return true; };
+				let owner = self.files.get(identity)
+					.map(PathBuf::as_path)
+					.or_else(|| libraryRoot.ownerOf(identity));
+				if owner.is_some_and(|owner| owner != file)
+				{
+					return false;
+				}
+				self.files.insert(identity.clone(), file.clone());
+				true
+			}
+		);
+	}
+}
+
+impl Default for DiscoveryProgress
+{
+	fn default() -> Self
+	{
+		Self
+		{
+			started: Instant::now(),
+			directoriesVisited: AtomicUsize::new(0),
+			filesQueued: AtomicUsize::new(0),
+			filesProbed: AtomicUsize::new(0),
+			audioFound: AtomicUsize::new(0),
+		}
+	}
+}
+
+impl DiscoveryProgress
+{
+	/// Estimate how long is left to go going by how quickly files have been probed so far. This only knows about
+	/// the files found so far, so will creep up while the walker's still finding more
+	fn remaining(&self) -> Option<Duration>
+	{
+		let probed = self.filesProbed.load(Ordering::Relaxed);
+		let queued = self.filesQueued.load(Ordering::Relaxed);
+		if probed == 0
+		{
+			return None;
+		}
+		let remaining = queued.saturating_sub(probed) as u128;
+		let millis = self.started.elapsed().as_millis() * remaining / probed as u128;
+		Some(Duration::from_millis(u64::try_from(millis).unwrap_or(u64::MAX)))
+	}
+}
+
+impl Display for DiscoveryProgress
+{
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result
+	{
+		write!
+		(
+			f,
+			"{} directories, {}/{} files probed, {} audio",
+			self.directoriesVisited.load(Ordering::Relaxed),
+			self.filesProbed.load(Ordering::Relaxed),
+			self.filesQueued.load(Ordering::Relaxed),
+			self.audioFound.load(Ordering::Relaxed),
+		)?;
+		match self.remaining()
+		{
+			Some(remaining) => write!(f, ", ETA {}", formatDuration(remaining)),
+			None => Ok(()),
+		}
+	}
+}
+
+impl MusicLibrary
+{
+	/// Discover the music in a directory and all its subdirectories within one of the library's roots, bringing
+	/// what the library knows about them up to date. Only files that are new or have changed since they were last
+	/// looked at get probed, and anything that's gone is dropped, so this works for both the initial discovery and
+	/// rescanning.
+	///
+	/// The directories are walked on the calling thread, handing the files that need probing off to a pool of
+	/// workers, with what they find folded into the library in batches so the write lock isn't taken per file
+	pub(super) fn discover(library: &RwLock<Self>, root: usize, directory: &Path, progress: &DiscoveryProgress)
+		-> Result<()>
+	{
+		let (cancellation, rules, basePath) =
+		{
+			let library = Self::readLock(library)?;
+			let libraryRoot = &library.roots[root];
+			(libraryRoot.discoveryCancellation.clone(), libraryRoot.ignoreRules.clone(), libraryRoot.basePath.clone())
+		};
+		// If we're starting from a symbolic link within the root and aren't to follow them, there's nothing to discover
+		if directory != basePath && !rules.followsSymlinks() && directory.is_symlink()
+		{
+			return Ok(());
+		}
+		// Find the ignore files that apply where we're starting from - if it's somewhere that's to be left out,
+		// there's nothing to discover
+		let Some((ignoreFiles, depth)) = rules.walkTo(&basePath, directory)
+			else { return Ok(()); };
+		let start = PendingDirectory { path: directory.to_path_buf(), depth, ignoreFiles };
+		let workers = available_parallelism().map_or(1, NonZero::get).min(MAX_WORKERS);
+		let (probeSender, probeReceiver) = sync_channel(workers * QUEUE_DEPTH);
+		let (resultSender, resultReceiver) = sync_channel(BATCH_SIZE);
+		let probeReceiver = Mutex::new(probeReceiver);
+
+		let (dirStamps, collected) = thread::scope
+		(
+			|scope|
+			{
+				// Start up the workers, and the collector to gather up what they find
+				for _ in 0..workers
+				{
+					let (probes, results, cancellation) = (&probeReceiver, resultSender.clone(), &cancellation);
+					scope.spawn(move || Self::probeFiles(probes, &results, cancellation, progress));
+				}
+				drop(resultSender);
+				let collector = scope.spawn(move || Self::collectProbed(library, root, &resultReceiver));
+
+				// Walk the directories, then let the workers know there's nothing more coming once that's done
+				let dirStamps = Self::walk(library, root, &rules, start, (&probeSender, &cancellation, progress));
+				drop(probeSender);
+				let collected = collector.join()
+					.unwrap_or_else(|_| Err(eyre!("Library discovery collector panicked")));
+				(dirStamps, collected)
+			}
+		);
+		collected?;
+
+		let mut library = Self::writeLock(library)?;
+		let root = &mut library.roots[root];
+		// Only note down when the directories were modified if we saw everything in them, otherwise the next
+		// discovery would skip whatever we didn't get to
+		if !cancellation.is_cancelled()
+		{
+			root.dirStamps.extend(dirStamps);
+		}
+		// Tidy away any directories that've been left with no music in them
+		root.pruneEmptyDirectories();
+
+		// We done? good!
+		Ok(())
+	}
+
+	/// Walk a directory tree, queueing up any files that need probing and dropping anything that's gone since we
+	/// last looked. Returns the modification times of the directories walked
+	fn walk(library: &RwLock<Self>, root: usize, rules: &IgnoreRules, start: PendingDirectory,
+		(probes, cancellation, progress): (&SyncSender<Probe>, &CancellationToken, &DiscoveryProgress))
+		-> BTreeMap<PathBuf, SystemTime>
+	{
+		let mut dirStamps = BTreeMap::new();
+		let mut seen = Seen::default();
+		if let Some(identity) = start.path.metadata().ok().and_then(|metadata| FileIdentity::of(&start.path, &metadata))
+		{
+			seen.directories.insert(identity);
+		}
+		let mut pending = vec![start];
+
+		while let Some(directory) = pending.pop()
+		{
+			// If we're being asked to stop, stop - without tidying up, as we've not seen everything
+			if cancellation.is_cancelled()
+			{
+				break;
+			}
+
+			match Self::walkDirectory(library, root, rules, &directory, &mut seen, (probes, progress))
+			{
+				Ok((subdirectories, modified)) =>
+				{
+					pending.extend(subdirectories);
+					if let Some(modified) = modified
+					{
+						dirStamps.insert(directory.path, modified);
+					}
+				},
+				// Leave whatever we knew about a directory we can't read alone, and carry on with the rest
+				Err(report) =>
+					error!("Skipping {} while discovering library: {}", directory.path.display(), report),
+			}
+		}
+
+		dirStamps
+	}
+
+	/// Look through a single directory, returning its subdirectories for walking next, and its modification time
+	fn walkDirectory(library: &RwLock<Self>, root: usize, rules: &IgnoreRules, pending: &PendingDirectory,
+		seen: &mut Seen, (probes, progress): (&SyncSender<Probe>, &DiscoveryProgress))
+		-> Result<(Vec<PendingDirectory>, Option<SystemTime>)>
+	{
+		let PendingDirectory { path: directory, depth, ignoreFiles } = pending;
+		progress.directoriesVisited.fetch_add(1, Ordering::Relaxed);
+		let modified = directory.metadata()?.modified().ok();
+
+		let (subdirectories, mut files) = Self::readDirectory(rules, pending, seen)?;
+
+		// Work out what's changed since we last looked, all against the same view of the library
+		let (changed, unclaimed, goneFiles, goneDirs) =
+		{
+			let library = Self::readLock(library)?;
+			let libraryRoot = &library.roots[root];
+			// If the directory hasn't been modified since we last looked at it, nothing's been added to or removed
+			// from it - so any file in it we don't know about has already been found not to be audio
+			let unchanged = modified.is_some() && libraryRoot.dirStamps.get(directory) == modified.as_ref();
+			let known = libraryRoot.files.get(directory);
+			let isKnown = |file: &PathBuf| known.is_some_and(|known| known.contains(file));
+
+			seen.dropDuplicates(&mut files, libraryRoot);
+
+			let changed: Vec<_> = files
+				.iter()
+				.filter
+				(
+					|(file, stamp, _)|
+					{
+						let upToDate = stamp.is_some() && libraryRoot.stamps.get(file) == stamp.as_ref() &&
+							libraryRoot.metadata.contains_key(file);
+						if isKnown(file) { !upToDate } else { !unchanged }
+					}
+				)
+				.map(|(file, stamp, identity)| Probe { file: file.clone(), stamp: *stamp, identity: identity.clone() })
+				.collect();
+
+			// Files that don't need probing again may still need what they are on disk noting down, such as when
+			// the library was cached before that was kept track of
+			let unclaimed: Vec<_> = files
+				.iter()
+				.filter
+				(
+					|(file, _, identity)|
+						isKnown(file) && identity.is_some() && libraryRoot.identities.get(file) != identity.as_ref() &&
+							!changed.iter().any(|probe| &probe.file == file)
+				)
+				.map(|(file, _, identity)| (file.clone(), identity.clone()))
+				.collect();
+
+			let present: BTreeSet<_> = files.iter().map(|(file, _, _)| file).collect();
+			let goneFiles: Vec<_> = known
+				.into_iter()
+				.flatten()
+				.filter(|file| !present.contains(file))
+				.cloned()
+				.collect();
+
+			let relativeDirectory = directory.strip_prefix(&libraryRoot.basePath)?;
+			let goneDirs: Vec<_> = libraryRoot.dirs
+				.iter()
+				.filter
+				(
+					|dir| dir.parent() == Some(relativeDirectory) &&
+						!subdirectories.contains(&libraryRoot.basePath.join(dir))
+				)
+				.cloned()
+				.collect();
+			(changed, unclaimed, goneFiles, goneDirs)
+		};
+
+		// Drop anything that's gone from the directory since we last looked
+		if !unclaimed.is_empty() || !goneFiles.is_empty() || !goneDirs.is_empty()
+		{
+			let mut library = Self::writeLock(library)?;
+			for (file, identity) in unclaimed
+			{
+				library.roots[root].claim(&file, identity);
+			}
+			for file in goneFiles
+			{
+				library.removeFile(root, &file);
+			}
+			for dir in goneDirs
+			{
+				library.removeDirectory(root, &dir);
+			}
+		}
+
+		// And hand whatever's new or changed off to be probed
+		progress.filesQueued.fetch_add(changed.len(), Ordering::Relaxed);
+		for probe in changed
+		{
+			if probes.send(probe).is_err()
+			{
+				break;
+			}
+		}
+
+		// Pick up any ignore files in the subdirectories, ready for walking them
+		let subdirectories = subdirectories
+			.into_iter()
+			.map
+			(
+				|path|
+				{
+					let ignoreFiles = IgnoreRules::enter(ignoreFiles, &path);
+					PendingDirectory { path, depth: depth + 1, ignoreFiles }
+				}
+			)
+			.collect();
+		Ok((subdirectories, modified))
+	}
+
+	/// Sort a directory's contents into subdirectories and files, leaving out anything the ignore rules say to
+	/// and anything already come across by another path
+	fn readDirectory(rules: &IgnoreRules, pending: &PendingDirectory, seen: &mut Seen) -> Result<DirectoryContents>
+	{
+		// Get the stamps for the files before taking the library lock so the collector isn't kept waiting
+		// on the disk. Anything left out that we knew about gets dropped below, same as if it'd gone
+		let PendingDirectory { path: directory, depth, ignoreFiles } = pending;
+		let mut subdirectories = Vec::new();
+		let mut files = Vec::new();
+		// Go through the contents in order, so which of several paths to the same thing wins out doesn't change
+		// from one discovery to the next
+		let mut entries = directory.read_dir()?
+			.map(|entry| entry.map(|entry| entry.path()))
+			.collect::<Result<Vec<_>, _>>()?;
+		entries.sort();
+		for path in entries
+		{
+			// Symbolic links are left out entirely if we're not to follow them
+			if !rules.followsSymlinks() && path.is_symlink()
+			{
+				continue;
+			}
+			// As is anything we can't look at, such as a link to something that's not there any more
+			let Ok(metadata) = path.metadata()
+				else { continue; };
+			let identity = FileIdentity::of(&path, &metadata);
+			if metadata.is_dir()
+			{
+				// Only walk directories we've not already been through by another path - this is what stops
+				// links and bind mounts pointing back up the tree from sending us round in circles
+				if !rules.ignoresDirectory(ignoreFiles, &path, depth + 1) &&
+					identity.is_none_or(|identity| seen.directories.insert(identity))
+				{
+					subdirectories.push(path);
+				}
+			}
+			else
+			{
+				let stamp = FileStamp::of(&metadata);
+				if !rules.ignoresFile(ignoreFiles, &path, stamp.map(|stamp| stamp.size))
+				{
+					files.push((path, stamp, identity));
+				}
+			}
+		}
+		Ok((subdirectories, files))
+	}
+
+	/// Worker for probing files, taking them off the queue until the walker's done
+	fn probeFiles(probes: &Mutex<Receiver<Probe>>, results: &SyncSender<Probed>, cancellation: &CancellationToken,
+		progress: &DiscoveryProgress)
+	{
+		let mut collecting = true;
+		loop
+		{
+			// Only hold the queue lock for long enough to take the next file off it
+			let probe = probes.lock().unwrap_or_else(PoisonError::into_inner).recv();
+			let Ok(Probe { file, stamp, identity }) = probe
+				else { break; };
+			// If we've been asked to stop, or there's no longer anyone to hand results to, keep draining the queue
+			// so the walker isn't left waiting on us - but don't bother probing anything more
+			if !collecting || cancellation.is_cancelled()
+			{
+				continue;
+			}
+
+			// Check if this file is an audio file, and if it is read its tags in so it can be found by artist and
+			// album too
+			let metadata = AudioFile::isAudio(&file)
+				.then(|| TrackMetadata::read(&file).unwrap_or_default());
+			progress.filesProbed.fetch_add(1, Ordering::Relaxed);
+			if metadata.is_some()
+			{
+				progress.audioFound.fetch_add(1, Ordering::Relaxed);
+			}
+			collecting = results.send(Probed { file, stamp, identity, metadata }).is_ok();
+		}
+	}
+
+	/// Gather up the results of probing files, folding them into the library a batch at a time
+	fn collectProbed(library: &RwLock<Self>, root: usize, results: &Receiver<Probed>) -> Result<()>
+	{
+		let mut batch = Vec::with_capacity(BATCH_SIZE);
+		let mut lastApplied = Instant::now();
+		loop
+		{
+			let finished = match results.recv_timeout(BATCH_INTERVAL)
+			{
+				Ok(probed) =>
+				{
+					batch.push(probed);
+					false
+				},
+				Err(RecvTimeoutError::Timeout) => false,
+				Err(RecvTimeoutError::Disconnected) => true,
+			};
+
+			if finished || batch.len() >= BATCH_SIZE || lastApplied.elapsed() >= BATCH_INTERVAL
+			{
+				if !batch.is_empty()
+				{
+					let mut library = Self::writeLock(library)?;
+					for Probed { file, stamp, identity, metadata } in batch.drain(..)
+					{
+						match metadata
+						{
+							Some(metadata) => library.addFile(root, file, metadata, stamp, identity)?,
+							// If it's not audio (any more), make sure we don't still have it
+							None => library.removeFile(root, &file),
+						}
+					}
+				}
+				lastApplied = Instant::now();
+			}
+
+			if finished
+			{
+				return Ok(());
+			}
+		}
+	}
+}
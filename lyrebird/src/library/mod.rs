@@ -0,0 +1,1280 @@
+// SPDX-License-Identifier: BSD-3-Clause
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs::{File, Metadata};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock, RwLockReadGuard, RwLockWriteGuard};
+use std::time::{Duration, SystemTime};
+use std::{ffi::OsStr, iter};
+#[cfg(unix)]
+use std::os::unix::fs::MetadataExt;
+
+use color_eyre::eyre::{self, OptionExt, Result};
+use libAudio::audioFile::AudioFile;
+use ratatui::style::Stylize;
+use ratatui::text::{Line, Span};
+use ratatui::widgets::ListItem;
+use serde::{Deserialize, Serialize};
+use serde_repr::{Deserialize_repr, Serialize_repr};
+use tokio::task::spawn_blocking;
+use tokio::task::JoinHandle;
+use tokio_util::sync::CancellationToken;
+use tracing::error;
+
+use crate::config::{Config, DiscoveryOptions};
+use crate::history::TrackStatistics;
+use crate::metadata::{formatDuration, trackNumberFromFileName, TrackMetadata};
+use crate::naturalOrder::{naturalCmp, naturalPathCmp, NaturalString};
+use crate::persistence::writeJsonAtomically;
+use crate::query::{Query, Track};
+use crate::ratings::TrackRatings;
+
+pub use discovery::DiscoveryProgress;
+use rules::{IgnoreRules, IGNORE_FILE};
+
+mod discovery;
+mod rules;
+
+/// What to file tracks with no artist or album tags under
+pub const UNKNOWN_ARTIST: &str = "Unknown Artist";
+pub const UNKNOWN_ALBUM: &str = "Unknown Album";
+/// Where the library was cached before it could be made up of several roots
+const LEGACY_CACHE_FILE: &str = "library.json";
+
+/// The user's music library, made up of one or more roots that are each discovered and cached on their own
+pub struct MusicLibrary
+{
+	roots: Vec<Root>,
+	/// Whether the tracks from all the roots are indexed together, or each root's kept apart
+	mergeRoots: bool,
+	/// Index of the music files by the root (if they're kept apart), then the artist and album they're filed under
+	artists: BTreeMap<(Option<usize>, NaturalString), BTreeMap<NaturalString, BTreeSet<PathBuf>>>,
+	/// Index of the music files by the root (if they're kept apart), then the album and album artist they're on
+	albums: BTreeMap<(Option<usize>, NaturalString, NaturalString), Album>,
+	/// Count of changes made to the tracks in the library, so anything worked out from them can tell when it needs
+	/// working out again
+	generation: u64,
+
+	treeNodeIcon: String,
+	treeLeafIcon: String,
+}
+
+/// One root of the music library, and everything that's been discovered in it
+#[derive(Serialize, Deserialize)]
+struct Root
+{
+	/// Caches from before the library was versioned only held paths, which is what version 1 is
+	#[serde(default)]
+	version: LibraryVersion,
+	/// Name the user gave this root, from the configuration
+	#[serde(skip)]
+	name: String,
+	/// Where this root of the music library is
+	basePath: PathBuf,
+	/// Path to where to cache this root
+	#[serde(skip)]
+	cacheFile: PathBuf,
+	/// Whether to watch this root for changes as they happen, from the configuration
+	#[serde(skip)]
+	watch: bool,
+	/// Paths to directories containing music relative to the root
+	dirs: BTreeSet<PathBuf>,
+	/// Map of directories to a list of files in that directory which are music
+	files: BTreeMap<PathBuf, BTreeSet<PathBuf>>,
+	/// Map of music files to the metadata read from their tags
+	#[serde(default)]
+	metadata: BTreeMap<PathBuf, TrackMetadata>,
+	/// Map of music files to their modification times and sizes when their metadata was read
+	#[serde(default)]
+	stamps: BTreeMap<PathBuf, FileStamp>,
+	/// Map of directories to their modification times when they were last looked through
+	#[serde(default)]
+	dirStamps: BTreeMap<PathBuf, SystemTime>,
+	/// Map of music files to what they are on disk, for spotting the same file being reached by several paths
+	#[serde(default)]
+	identities: BTreeMap<PathBuf, FileIdentity>,
+	/// Map of files on disk to the path they're in the library under - the reverse of `identities`
+	#[serde(skip)]
+	owners: BTreeMap<FileIdentity, PathBuf>,
+	/// The discovery options the root was last looked through with
+	#[serde(default)]
+	discoveryOptions: Option<DiscoveryOptions>,
+	/// What discovery is to leave out of the root
+	#[serde(skip)]
+	ignoreRules: Arc<IgnoreRules>,
+
+	#[serde(skip)]
+	discoveryThread: Option<JoinHandle<Result<()>>>,
+	#[serde(skip)]
+	discoveryCancellation: CancellationToken,
+	/// How far the current discovery has got
+	#[serde(skip)]
+	discoveryProgress: Arc<DiscoveryProgress>,
+}
+
+#[derive(Serialize_repr, Deserialize_repr, Copy, Clone, Default, PartialEq, Eq, PartialOrd, Ord)]
+#[repr(u8)]
+pub enum LibraryVersion
+{
+	/// Directories and files only
+	#[default]
+	Version1 = 1,
+	/// Metadata for each file, as read from its tags
+	Version2 = 2,
+	/// Modification times for files and directories, so the library can be rescanned for changes
+	Version3 = 3,
+	/// Genre in the metadata for each file
+	Version4 = 4,
+}
+
+impl LibraryVersion
+{
+	const CURRENT: Self = Self::Version4;
+}
+
+/// What a file looked like when we last looked at it, for spotting when it's changed
+#[derive(Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FileStamp
+{
+	modified: SystemTime,
+	size: u64,
+}
+
+impl FileStamp
+{
+	fn of(metadata: &Metadata) -> Option<Self>
+	{
+		Some(Self
+		{
+			modified: metadata.modified().ok()?,
+			size: metadata.len(),
+		})
+	}
+}
+
+/// What a file or directory is on disk, whatever path it was reached by
+#[derive(Clone, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub enum FileIdentity
+{
+	/// The device the file's on and its inode number on that device
+	#[cfg(unix)]
+	Inode
+	{
+		device: u64,
+		inode: u64,
+	},
+	/// Where the file is once all links to it have been followed, on platforms without inode numbers
+	#[cfg(not(unix))]
+	Path(PathBuf),
+}
+
+impl FileIdentity
+{
+	#[cfg(unix)]
+	#[expect(clippy::unnecessary_wraps, reason = "not every platform can always work out a file's identity")]
+	fn of(_path: &Path, metadata: &Metadata) -> Option<Self>
+	{
+		Some(Self::Inode { device: metadata.dev(), inode: metadata.ino() })
+	}
+
+	#[cfg(not(unix))]
+	fn of(path: &Path, _metadata: &Metadata) -> Option<Self>
+	{
+		path.canonicalize().ok().map(Self::Path)
+	}
+}
+
+/// An album in the library, and a summary of the tracks on it
+pub struct Album
+{
+	pub title: String,
+	pub artist: String,
+	/// The name of the library root the album's in, if the roots are being kept apart
+	pub library: Option<String>,
+	/// The earliest year given for any of the album's tracks
+	pub year: Option<u32>,
+	/// Total running time of the album's tracks
+	pub duration: Duration,
+	/// When the most recent of the album's tracks was added
+	pub dateAdded: Option<SystemTime>,
+	tracks: BTreeSet<PathBuf>,
+}
+
+impl Album
+{
+	pub fn trackCount(&self) -> usize
+		{ self.tracks.len() }
+
+	/// Fold a track into the album's summary
+	fn add(&mut self, file: PathBuf, metadata: &TrackMetadata)
+	{
+		self.year = self.year.into_iter().chain(metadata.year).min();
+		self.duration += metadata.duration.unwrap_or_default();
+		self.dateAdded = self.dateAdded.max(metadata.dateAdded);
+		self.tracks.insert(file);
+	}
+}
+
+/// Work out the artist and album a track is to be filed under in the indices
+fn indexKeys(metadata: &TrackMetadata) -> (NaturalString, NaturalString)
+{
+	let artist = metadata.filedArtist().unwrap_or(UNKNOWN_ARTIST).to_string();
+	let album = metadata.album.clone().unwrap_or_else(|| UNKNOWN_ALBUM.to_string());
+	(NaturalString(artist), NaturalString(album))
+}
+
+/// Get the full path of a directory in a root's part of the tree, the root itself being the empty path
+fn treePath(root: &Root, directory: &Path) -> PathBuf
+{
+	if directory.as_os_str().is_empty() { root.basePath.clone() } else { root.basePath.join(directory) }
+}
+
+/// Look a file's metadata up in whichever root it's in
+fn findMetadata<'a>(roots: &'a [Root], file: &Path) -> Option<&'a TrackMetadata>
+{
+	roots.iter().find_map(|root| root.metadata.get(file))
+}
+
+/// Turn a library root's name into something safe to use in the name of its cache file
+pub fn cacheName(name: &str) -> String
+{
+	name
+		.chars()
+		.map(|character| if character.is_alphanumeric() || "-_ ".contains(character) { character } else { '_' })
+		.collect()
+}
+
+fn defaultTreeIcon() -> String
+{
+	"╰ ".to_string()
+}
+
+fn defaultLeafIcon() -> String
+{
+	"├ ".to_string()
+}
+
+impl Root
+{
+	/// Set up a root that's not been discovered yet
+	fn new(name: &str, cacheFile: PathBuf, basePath: &Path) -> Result<Self>
+	{
+		if !basePath.is_dir()
+		{
+			return Err(eyre::eyre!("Library path must be a valid directory"));
+		}
+
+		Ok(Self
+		{
+			version: LibraryVersion::CURRENT,
+			name: name.to_string(),
+			basePath: basePath.to_path_buf(),
+			cacheFile,
+			watch: false,
+			dirs: BTreeSet::new(),
+			files: BTreeMap::new(),
+			metadata: BTreeMap::new(),
+			stamps: BTreeMap::new(),
+			dirStamps: BTreeMap::new(),
+			identities: BTreeMap::new(),
+			owners: BTreeMap::new(),
+			discoveryOptions: None,
+			ignoreRules: Arc::default(),
+
+			discoveryThread: None,
+			discoveryCancellation: CancellationToken::new(),
+			discoveryProgress: Arc::default(),
+		})
+	}
+
+	/// Read a root in from its cache, if it's got one. Caches from before the library could have several roots
+	/// were all written to the same file, so that's used if it's for the same directory as this root
+	fn fromCache(name: &str, cacheDir: &Path, basePath: &Path) -> Option<Self>
+	{
+		let cacheFile = Self::cacheFileFor(cacheDir, name);
+		let legacyCacheFile = cacheDir.join(LEGACY_CACHE_FILE);
+		let mut root = [cacheFile.as_path(), legacyCacheFile.as_path()]
+			.into_iter()
+			.filter(|candidate| candidate.exists())
+			.find_map
+			(
+				|candidate| match Self::readCache(candidate)
+				{
+					Ok(root) => (root.basePath == basePath).then_some(root),
+					Err(report) =>
+					{
+						error!("Reading library cache {} failed: {}", candidate.display(), report);
+						None
+					},
+				}
+			)?;
+		root.name = name.to_string();
+		root.cacheFile = cacheFile;
+		Some(root)
+	}
+
+	fn readCache(cacheFile: &Path) -> Result<Self>
+	{
+		let cache = File::open(cacheFile)?;
+		let mut root: Self = serde_json::from_reader(cache)?;
+		// If the cache was written before we had complete metadata, throw what's there away to be read in again
+		if root.version < LibraryVersion::Version2
+		{
+			root.metadata.clear();
+		}
+		// If it was written before we read the genre, forget when the files were last looked at so they get read
+		// in again - keeping the rest of what we know about them in the meantime
+		else if root.version < LibraryVersion::Version4
+		{
+			root.stamps.clear();
+		}
+		root.version = LibraryVersion::CURRENT;
+		root.owners = root.identities
+			.iter()
+			.map(|(file, identity)| (identity.clone(), file.clone()))
+			.collect();
+		Ok(root)
+	}
+
+	/// Work out where a root is cached from its name, keeping only the characters that are safe in file names
+	fn cacheFileFor(cacheDir: &Path, name: &str) -> PathBuf
+	{
+		cacheDir.join(format!("library-{}.json", cacheName(name)))
+	}
+
+	/// Set up what discovery is to leave out of the root
+	fn applyOptions(&mut self, options: &DiscoveryOptions)
+	{
+		// If that's changed since the root was last looked through, things that were skipped over might not be any
+		// more - so every directory needs looking through properly again, rather than being taken as unchanged
+		if self.discoveryOptions.as_ref() != Some(options)
+		{
+			self.dirStamps.clear();
+			self.discoveryOptions = Some(options.clone());
+		}
+		self.ignoreRules = Arc::new(IgnoreRules::new(&self.basePath, options));
+	}
+
+	fn writeCache(&self) -> Result<()>
+	{
+		// Ask our discovery task to stop if it didn't already
+		self.discoveryCancellation.cancel();
+		// Ask serde to serialise out the library cache, swapping it in for the old one only once it's all written
+		writeJsonAtomically(&self.cacheFile, self)
+	}
+
+	fn isDiscovering(&self) -> bool
+	{
+		match &self.discoveryThread
+		{
+			Some(thread) => !thread.is_finished(),
+			None => false,
+		}
+	}
+
+	/// Make sure a directory, and all those leading up to it, are in the directories set
+	fn addDirectory(&mut self, relativePath: &Path)
+	{
+		for directory in relativePath.ancestors().filter(|directory| !directory.as_os_str().is_empty())
+		{
+			self.dirs.insert(directory.to_path_buf());
+		}
+	}
+
+	/// Remove all the directories that no longer have any music in them or their subdirectories
+	fn pruneEmptyDirectories(&mut self)
+	{
+		let needed: BTreeSet<_> = self.files
+			.keys()
+			.filter_map(|directory| directory.strip_prefix(&self.basePath).ok())
+			.flat_map(Path::ancestors)
+			.filter(|directory| !directory.as_os_str().is_empty())
+			.map(Path::to_path_buf)
+			.collect();
+		self.dirs.retain(|directory| needed.contains(directory));
+	}
+
+	/// Remove any directories from the given one on up that no longer have any music in them or their subdirectories
+	fn pruneDirectories(&mut self, relativePath: &Path)
+	{
+		for directory in relativePath.ancestors()
+		{
+			if !self.dirs.contains(directory)
+			{
+				continue;
+			}
+			let hasFiles = self.files.contains_key(&self.basePath.join(directory));
+			let hasSubdirectories = self.dirs.iter().any(|dir| dir.starts_with(directory) && dir != directory);
+			// If this one still has music in it, so do all those above it
+			if hasFiles || hasSubdirectories
+			{
+				break;
+			}
+			self.dirs.remove(directory);
+		}
+	}
+
+	/// Note down what a file in the library is on disk, so other paths to it can be spotted
+	fn claim(&mut self, file: &Path, identity: Option<FileIdentity>)
+	{
+		self.release(file);
+		if let Some(identity) = identity
+		{
+			self.owners.insert(identity.clone(), file.to_path_buf());
+			self.identities.insert(file.to_path_buf(), identity);
+		}
+	}
+
+	/// Forget what a file in the library is on disk, as it's no longer in the library under that path
+	fn release(&mut self, file: &Path)
+	{
+		if let Some(identity) = self.identities.remove(file)
+		{
+			if self.owners.get(&identity).is_some_and(|owner| owner == file)
+			{
+				self.owners.remove(&identity);
+			}
+		}
+	}
+
+	/// Find which path a file on disk is in the library under, if it's still there to be found
+	fn ownerOf(&self, identity: &FileIdentity) -> Option<&Path>
+	{
+		self.owners.get(identity)
+			.map(PathBuf::as_path)
+			.filter(|owner| owner.exists())
+	}
+
+	/// Iterate over the directories in this root for the tree, starting with the root itself as an empty path
+	fn treeEntries(&self) -> impl Iterator<Item = &Path>
+	{
+		iter::once(Path::new("")).chain(self.dirs.iter().map(PathBuf::as_path))
+	}
+}
+
+impl MusicLibrary
+{
+	/// Set the library up from the roots in the configuration, reading in what's cached for each and then going
+	/// looking for anything that's changed since (or everything, for roots that've not been cached yet)
+	pub fn new(cacheDir: &Path, config: &Config) -> Result<Arc<RwLock<Self>>>
+	{
+		let roots: Vec<_> = config.libraryRoots
+			.iter()
+			.filter_map
+			(
+				|root|
+				{
+					Root::fromCache(&root.name, cacheDir, &root.path)
+						.map_or_else(|| Root::new(&root.name, Root::cacheFileFor(cacheDir, &root.name), &root.path), Ok)
+						// A root that can't be found (eg, a network mount that's not up) shouldn't stop the rest
+						// of the library from being used
+						.inspect_err(|report| error!("Skipping library root {}: {}", root.name, report))
+						.ok()
+						.map
+						(
+							|mut built|
+							{
+								built.watch = root.watch;
+								built.applyOptions(&config.discovery);
+								built
+							}
+						)
+				}
+			)
+			.collect();
+		let rootCount = roots.len();
+		let library = Arc::new(RwLock::new(Self::fromRoots(roots, config.mergeLibraries)));
+
+		for root in 0..rootCount
+		{
+			Self::backgroundDiscover(&library, root)?;
+		}
+		Ok(library)
+	}
+
+	/// Construct a library from only what's in the caches for the configuration's roots, without looking for
+	/// anything new
+	pub fn fromCache(cacheDir: &Path, config: &Config) -> Self
+	{
+		let roots = config.libraryRoots
+			.iter()
+			.filter_map(|root| Root::fromCache(&root.name, cacheDir, &root.path))
+			.collect();
+		Self::fromRoots(roots, config.mergeLibraries)
+	}
+
+	fn fromRoots(roots: Vec<Root>, mergeRoots: bool) -> Self
+	{
+		let mut library = Self
+		{
+			roots,
+			mergeRoots,
+			artists: BTreeMap::new(),
+			albums: BTreeMap::new(),
+			generation: 0,
+
+			treeNodeIcon: defaultTreeIcon(),
+			treeLeafIcon: defaultLeafIcon(),
+		};
+		library.rebuildIndex();
+		library
+	}
+
+	/// Write out the caches for all the roots, attempting every one even if some fail
+	pub fn writeCache(&self) -> Result<()>
+	{
+		self.roots
+			.iter()
+			.map(Root::writeCache)
+			.fold(Ok(()), Result::and)
+	}
+
+	/// Iterate over the directories at the base of each of the library's roots that are to be watched for changes
+	pub fn watchedPaths(&self) -> impl Iterator<Item = &Path>
+	{
+		self.roots
+			.iter()
+			.filter(|root| root.watch)
+			.map(|root| root.basePath.as_path())
+	}
+
+	pub fn isDiscovering(&self) -> bool
+	{
+		self.roots.iter().any(Root::isDiscovering)
+	}
+
+	pub async fn maybeJoinDiscoveryThread(library: &Arc<RwLock<Self>>) -> Result<()>
+	{
+		let threads: Vec<_> = Self::writeLock(library)?
+			.roots
+			.iter_mut()
+			.filter_map(|root| root.discoveryThread.take())
+			.collect();
+		for thread in threads
+		{
+			thread.await??;
+		}
+		Ok(())
+	}
+
+	/// Go looking through the library for anything that's changed, skipping any roots that are already being
+	/// looked through
+	pub fn rescan(library: &Arc<RwLock<Self>>) -> Result<()>
+	{
+		let roots: Vec<_> =
+		{
+			let mut library = Self::writeLock(library)?;
+			library.roots
+				.iter_mut()
+				.enumerate()
+				.filter(|(_, root)| !root.isDiscovering())
+				.map
+				(
+					|(index, root)|
+					{
+						// If a previous discovery was stopped, make sure this one doesn't stop straight away
+						if root.discoveryCancellation.is_cancelled()
+						{
+							root.discoveryCancellation = CancellationToken::new();
+						}
+						index
+					}
+				)
+				.collect()
+		};
+		for root in roots
+		{
+			Self::backgroundDiscover(library, root)?;
+		}
+		Ok(())
+	}
+
+	fn backgroundDiscover(library: &Arc<RwLock<Self>>, root: usize) -> Result<()>
+	{
+		// Discovery spends all its time blocked on the filesystem, so give it a thread of its own to do that on
+		let progress = Arc::new(DiscoveryProgress::default());
+		let mut lockedLibrary = Self::writeLock(library)?;
+		let libraryRoot = &mut lockedLibrary.roots[root];
+		let task =
+		{
+			let (library, progress, basePath) = (library.clone(), progress.clone(), libraryRoot.basePath.clone());
+			move || Self::discover(library.as_ref(), root, &basePath, &progress)
+		};
+
+		libraryRoot.discoveryProgress = progress;
+		libraryRoot.discoveryThread = Some(spawn_blocking(task));
+		Ok(())
+	}
+
+	/// Find out how far discovery has got for each of the roots it's running on, along with their names
+	pub fn discoveryProgress(&self) -> impl Iterator<Item = (&str, &DiscoveryProgress)>
+	{
+		self.roots
+			.iter()
+			.filter(|root| root.isDiscovering())
+			.map(|root| (root.name.as_str(), root.discoveryProgress.as_ref()))
+	}
+
+	fn writeLock(library: &RwLock<Self>) -> Result<RwLockWriteGuard<'_, Self>>
+	{
+		library.write()
+			.map_err
+			(
+				|error| eyre::eyre!("While discovering library: {}", error)
+			)
+	}
+
+	fn readLock(library: &RwLock<Self>) -> Result<RwLockReadGuard<'_, Self>>
+	{
+		library.read()
+			.map_err
+			(
+				|error| eyre::eyre!("While discovering library: {}", error)
+			)
+	}
+
+	/// Bring the library up to date with changes to the paths given, as reported by the filesystem watcher
+	pub fn applyChanges(library: &RwLock<Self>, paths: impl IntoIterator<Item = PathBuf>) -> Result<()>
+	{
+		for path in paths
+		{
+			// Changes outside the library's roots aren't ours
+			let Some(root) = Self::readLock(library)?.rootFor(&path)
+				else { continue; };
+			Self::applyChange(library, root, &path)?;
+		}
+		Ok(())
+	}
+
+	fn applyChange(library: &RwLock<Self>, root: usize, path: &Path) -> Result<()>
+	{
+		// Changes to the root of the library itself don't tell us anything
+		let (basePath, rules) =
+		{
+			let library = Self::readLock(library)?;
+			let libraryRoot = &library.roots[root];
+			(libraryRoot.basePath.clone(), libraryRoot.ignoreRules.clone())
+		};
+		let relativePath = path.strip_prefix(&basePath)?;
+		if relativePath.as_os_str().is_empty()
+		{
+			return Ok(());
+		}
+
+		// Symbolic links are treated as though they're not there if we're not to follow them
+		if !rules.followsSymlinks() && path.is_symlink()
+		{
+			let mut library = Self::writeLock(library)?;
+			library.removeFile(root, path);
+			if library.roots[root].dirs.contains(relativePath)
+			{
+				library.removeDirectory(root, relativePath);
+			}
+		}
+		// If it's a directory, go see what's in it
+		else if path.is_dir()
+		{
+			Self::discover(library, root, path, &DiscoveryProgress::default())?;
+		}
+		// If it's a file, see whether it's new or changed and if it's (still) audio
+		else if path.is_file()
+		{
+			// If it's an ignore file, what's left out of its directory might have changed, so look through it again
+			if path.file_name().is_some_and(|name| name == IGNORE_FILE)
+			{
+				if let Some(directory) = path.parent()
+				{
+					Self::discover(library, root, directory, &DiscoveryProgress::default())?;
+				}
+				return Ok(());
+			}
+
+			// Files that are to be left out, or that are already in the library by another path, are treated the
+			// same as ones that aren't audio
+			let fileMetadata = path.metadata().ok();
+			let stamp = fileMetadata.as_ref().and_then(FileStamp::of);
+			let identity = fileMetadata.as_ref().and_then(|fileMetadata| FileIdentity::of(path, fileMetadata));
+			let ignored = path.parent()
+				.and_then(|directory| rules.walkTo(&basePath, directory))
+				.is_none_or
+				(
+					|(ignoreFiles, _)| rules.ignoresFile(&ignoreFiles, path, stamp.map(|stamp| stamp.size))
+				);
+			let (known, upToDate, duplicate) =
+			{
+				let library = Self::readLock(library)?;
+				let root = &library.roots[root];
+				(
+					root.metadata.contains_key(path),
+					stamp.is_some() && root.stamps.get(path) == stamp.as_ref(),
+					identity.as_ref()
+						.and_then(|identity| root.ownerOf(identity))
+						.is_some_and(|owner| owner != path),
+				)
+			};
+			let ignored = ignored || duplicate;
+			if known && upToDate && !ignored
+			{
+				return Ok(());
+			}
+			if !ignored && AudioFile::isAudio(path)
+			{
+				let metadata = TrackMetadata::read(path).unwrap_or_default();
+				Self::writeLock(library)?.addFile(root, path.to_path_buf(), metadata, stamp, identity)?;
+			}
+			else if known
+			{
+				Self::writeLock(library)?.removeFile(root, path);
+			}
+		}
+		// Otherwise it's gone, so drop whatever it was
+		else
+		{
+			let mut library = Self::writeLock(library)?;
+			library.removeFile(root, path);
+			if library.roots[root].dirs.contains(relativePath)
+			{
+				library.removeDirectory(root, relativePath);
+			}
+		}
+
+		// Tidy away any directories that've been left with no music in them
+		Self::writeLock(library)?.roots[root].pruneDirectories(relativePath);
+		Ok(())
+	}
+
+	/// Find which root a path is in, picking the innermost if the roots overlap
+	fn rootFor(&self, path: &Path) -> Option<usize>
+	{
+		self.roots
+			.iter()
+			.enumerate()
+			.filter(|(_, root)| path.starts_with(&root.basePath))
+			.max_by_key(|(_, root)| root.basePath.components().count())
+			.map(|(index, _)| index)
+	}
+
+	/// Work out which root a track from the given one is indexed under - roots are only kept apart in the indices
+	/// if there's more than one and the user hasn't asked for them to be merged
+	fn indexRoot(&self, root: usize) -> Option<usize>
+	{
+		(!self.mergeRoots && self.roots.len() > 1).then_some(root)
+	}
+
+	/// Add an audio file to the library, or update it if it's already there
+	fn addFile(&mut self, root: usize, file: PathBuf, metadata: TrackMetadata, stamp: Option<FileStamp>,
+		identity: Option<FileIdentity>) -> Result<()>
+	{
+		let directory = file.parent()
+			.ok_or_eyre("File does not have a valid path parent")?;
+		let libraryRoot = &mut self.roots[root];
+		// The directory the file's in now has music in it, so make sure it's in the tree
+		let relativeDirectory = directory.strip_prefix(&libraryRoot.basePath)?.to_path_buf();
+		libraryRoot.addDirectory(&relativeDirectory);
+		libraryRoot.files.entry(directory.to_path_buf())
+			.or_default()
+			.insert(file.clone());
+		match stamp
+		{
+			Some(stamp) => libraryRoot.stamps.insert(file.clone(), stamp),
+			None => libraryRoot.stamps.remove(&file),
+		};
+		libraryRoot.claim(&file, identity);
+		self.addMetadata(root, file, metadata);
+		Ok(())
+	}
+
+	/// Drop a file that no longer exists from the library
+	fn removeFile(&mut self, root: usize, file: &Path)
+	{
+		let libraryRoot = &mut self.roots[root];
+		if let Some(directory) = file.parent()
+		{
+			if let Some(files) = libraryRoot.files.get_mut(directory)
+			{
+				files.remove(file);
+				if files.is_empty()
+				{
+					libraryRoot.files.remove(directory);
+				}
+			}
+		}
+		libraryRoot.stamps.remove(file);
+		libraryRoot.release(file);
+		self.removeMetadata(root, file);
+	}
+
+	/// Drop a directory that no longer exists, and everything in it, from the library
+	fn removeDirectory(&mut self, root: usize, relativePath: &Path)
+	{
+		let libraryRoot = &mut self.roots[root];
+		let path = libraryRoot.basePath.join(relativePath);
+		libraryRoot.dirs.retain(|dir| !dir.starts_with(relativePath));
+		libraryRoot.dirStamps.retain(|dir, _| !dir.starts_with(&path));
+		let files: Vec<_> = libraryRoot.files
+			.iter()
+			.filter(|(dir, _)| dir.starts_with(&path))
+			.flat_map(|(_, files)| files.iter().cloned())
+			.collect();
+		for file in files
+		{
+			self.removeFile(root, &file);
+		}
+	}
+
+	/// Record the metadata for a file, filing it in the artist and album indices
+	fn addMetadata(&mut self, root: usize, file: PathBuf, metadata: TrackMetadata)
+	{
+		// If we already had metadata for the file, it might have been filed elsewhere so take it out first
+		self.removeMetadata(root, &file);
+		let (artist, album) = indexKeys(&metadata);
+		let indexRoot = self.indexRoot(root);
+		self.artists
+			.entry((indexRoot, artist.clone()))
+			.or_default()
+			.entry(album.clone())
+			.or_default()
+			.insert(file.clone());
+		let library = indexRoot.map(|root| self.roots[root].name.clone());
+		self.albums
+			.entry((indexRoot, artist.clone(), album.clone()))
+			.or_insert_with
+			(
+				|| Album
+				{
+					title: album.0,
+					artist: artist.0,
+					library,
+					year: None,
+					duration: Duration::ZERO,
+					dateAdded: None,
+					tracks: BTreeSet::new(),
+				}
+			)
+			.add(file.clone(), &metadata);
+		self.roots[root].metadata.insert(file, metadata);
+		self.generation += 1;
+	}
+
+	/// Forget the metadata for a file, taking it out of the artist and album indices
+	fn removeMetadata(&mut self, root: usize, file: &Path)
+	{
+		let Some(metadata) = self.roots[root].metadata.remove(file)
+			else { return; };
+		self.generation += 1;
+		let (artist, album) = indexKeys(&metadata);
+		let indexRoot = self.indexRoot(root);
+
+		let artistKey = (indexRoot, artist);
+		if let Some(albums) = self.artists.get_mut(&artistKey)
+		{
+			if let Some(files) = albums.get_mut(&album)
+			{
+				files.remove(file);
+				if files.is_empty()
+				{
+					albums.remove(&album);
+				}
+			}
+			if albums.is_empty()
+			{
+				self.artists.remove(&artistKey);
+			}
+		}
+
+		let (indexRoot, artist) = artistKey;
+		let key = (indexRoot, artist, album);
+		if let Some(entry) = self.albums.get_mut(&key)
+		{
+			entry.tracks.remove(file);
+			if entry.tracks.is_empty()
+			{
+				self.albums.remove(&key);
+				return;
+			}
+			// The album's summary can't have the track simply taken back out of it, so rebuild it from what's left
+			let tracks = std::mem::take(&mut entry.tracks);
+			entry.year = None;
+			entry.duration = Duration::ZERO;
+			entry.dateAdded = None;
+			for track in tracks
+			{
+				if let Some(metadata) = findMetadata(&self.roots, &track)
+				{
+					entry.add(track, metadata);
+				}
+			}
+		}
+	}
+
+	/// Rebuild the artist and album indices from the metadata, as the indices aren't cached
+	fn rebuildIndex(&mut self)
+	{
+		self.artists.clear();
+		self.albums.clear();
+		for root in 0..self.roots.len()
+		{
+			for (file, metadata) in std::mem::take(&mut self.roots[root].metadata)
+			{
+				self.addMetadata(root, file, metadata);
+			}
+		}
+	}
+
+	/// Iterate over the entries in the directory tree - each root, followed by the directories in it
+	fn treeEntries(&self) -> impl Iterator<Item = (&Root, &Path)>
+	{
+		self.roots
+			.iter()
+			.flat_map(|root| root.treeEntries().map(move |directory| (root, directory)))
+	}
+
+	pub fn directories(&self) -> impl Iterator<Item = ListItem>
+	{
+		self.treeEntries()
+			.map
+			(
+				// Turn the directories into ListItem's
+				|(root, directory)|
+				{
+					// If the directory is empty, it's the root itself
+					if directory.as_os_str().is_empty()
+					{
+						// Display that with the tree node icon, its name and where it is, and be done
+						let text =
+						[
+							Span::from(self.treeNodeIcon.clone()),
+							Span::from(root.name.clone()),
+							Span::from(format!(" {}", root.basePath.to_string_lossy())).dim(),
+						];
+						ListItem::new(Line::from_iter(text))
+					}
+					else
+					{
+						// Otherwise, figure out how deep this entry is in the tree
+						let indentLevel = directory.iter().count();
+						// Build the prefix of pipes from that
+						let mut prefix = "│ ".repeat(indentLevel - 1);
+						prefix.insert(0, ' ');
+						// Turn the resulting prefix, icon and directory name into a nice ListItem
+						let text =
+						[
+							prefix,
+							self.treeLeafIcon.clone(),
+							directory.file_name().unwrap_or_else(|| OsStr::new("")).to_string_lossy().to_string(),
+						];
+						ListItem::new(Line::from_iter(text))
+					}
+				}
+			)
+	}
+
+	pub fn directoryCount(&self) -> usize
+		{ self.roots.iter().map(|root| root.dirs.len() + 1).sum() }
+
+	pub fn filesFor<'a>(&'a self, dirIndex: Option<usize>, ratings: &'a TrackRatings)
+		-> Option<impl Iterator<Item = ListItem<'a>>>
+	{
+		// Find the entry from the directories that describes the requested index
+		dirIndex
+			.and_then(|index| self.directoryAt(index))
+			// Extract what files are in that directory
+			.and_then(|dir| self.filesIn(&dir))
+			.map
+			(
+				|files|
+				{
+					files
+						.iter()
+						.map
+						(
+							|file|
+							{
+								// Show the track's title along with how long it is, if we know, and its rating if it has one
+								let duration = self.trackMetadata(file)
+									.and_then(|metadata| metadata.duration)
+									.map(|duration| format!(" {}", formatDuration(duration)))
+									.unwrap_or_default();
+								let rating = ratings.get(file)
+									.map(|rating| format!(" {}", rating.describe()))
+									.unwrap_or_default();
+								ListItem::new
+								(
+									Line::from_iter
+									([
+										Span::from(self.trackName(file)),
+										Span::from(duration).dim(),
+										Span::from(rating),
+									])
+								)
+							}
+						)
+				}
+			)
+	}
+
+	/// Get the files in the directory at the given index, in the order they're listed
+	pub fn filesListed(&self, dirIndex: Option<usize>) -> Vec<PathBuf>
+	{
+		dirIndex
+			.and_then(|index| self.directoryAt(index))
+			.and_then(|dir| self.filesIn(&dir))
+			.map(|files| files.iter().cloned().collect())
+			.unwrap_or_default()
+	}
+
+	pub fn filesCount(&self, dirIndex: Option<usize>) -> usize
+	{
+		dirIndex
+			.and_then(|index| self.directoryAt(index))
+			.and_then(|dir| self.filesIn(&dir))
+			.map(BTreeSet::len)
+			.unwrap_or_default()
+	}
+
+	/// Get the audio files in a directory (and optionally all those in its subdirectories) in the order they'd be
+	/// played - directories in natural order, and within each, by track number then natural file name order
+	pub fn tracksIn(&self, directory: &Path, recursive: bool) -> Vec<PathBuf>
+	{
+		let mut directories: Vec<_> = self.roots
+			.iter()
+			.flat_map(|root| root.files.iter())
+			.filter(|(dir, _)| if recursive { dir.starts_with(directory) } else { *dir == directory })
+			.collect();
+		directories.sort_by(|(lhs, _), (rhs, _)| naturalPathCmp(lhs, rhs));
+		directories
+			.into_iter()
+			.flat_map(|(_, files)| self.orderTracks(files))
+			.collect()
+	}
+
+	/// Iterate over the names of all the artists in the library in order, along with the root they're in if the
+	/// roots are being kept apart
+	pub fn artists(&self) -> impl Iterator<Item = Line>
+	{
+		self.artists
+			.keys()
+			.map
+			(
+				|(root, artist)|
+				{
+					let mut line = Line::from(artist.0.as_str());
+					if let Some(root) = root
+					{
+						line.push_span(Span::from(format!(" [{}]", self.roots[*root].name)).dim());
+					}
+					line
+				}
+			)
+	}
+
+	pub fn artistCount(&self) -> usize
+		{ self.artists.len() }
+
+	/// Iterate over the names of the albums by the artist at the given index, in order
+	pub fn albumsBy(&self, artistIndex: Option<usize>) -> impl Iterator<Item = &str>
+	{
+		self.albumsFor(artistIndex)
+			.into_iter()
+			.flat_map(BTreeMap::keys)
+			.map(|album| album.0.as_str())
+	}
+
+	pub fn albumCount(&self, artistIndex: Option<usize>) -> usize
+	{
+		self.albumsFor(artistIndex)
+			.map(BTreeMap::len)
+			.unwrap_or_default()
+	}
+
+	/// Get all the tracks by the artist at the given index in the order they'd be played - album by album, and
+	/// within each in track order
+	pub fn artistTracks(&self, artistIndex: usize) -> Vec<PathBuf>
+	{
+		self.albumsFor(Some(artistIndex))
+			.into_iter()
+			.flat_map(BTreeMap::values)
+			.flat_map(|files| self.orderTracks(files))
+			.collect()
+	}
+
+	/// Get the tracks on the given album by the artist at the given index, in track order
+	pub fn albumTracks(&self, artistIndex: usize, albumIndex: usize) -> Vec<PathBuf>
+	{
+		self.albumsFor(Some(artistIndex))
+			.and_then(|albums| albums.values().nth(albumIndex))
+			.map(|files| self.orderTracks(files))
+			.unwrap_or_default()
+	}
+
+	/// Iterate over all the albums in the library, ordered by root (if they're kept apart), album artist then title
+	pub fn albums(&self) -> impl Iterator<Item = &Album>
+	{
+		self.albums.values()
+	}
+
+	/// Get the tracks on an album, in track order
+	pub fn tracksOn(&self, album: &Album) -> Vec<PathBuf>
+	{
+		self.orderTracks(&album.tracks)
+	}
+
+	pub fn trackMetadata(&self, file: &Path) -> Option<&TrackMetadata>
+	{
+		findMetadata(&self.roots, file)
+	}
+
+	/// Get the name to display for a track - its title if it has one, otherwise its file name
+	pub fn trackName(&self, file: &Path) -> String
+	{
+		self.trackMetadata(file)
+			.and_then(|metadata| metadata.title.clone())
+			.unwrap_or_else
+			(
+				|| file.file_name().unwrap_or_else(|| OsStr::new("")).to_string_lossy().to_string()
+			)
+	}
+
+	/// Iterate over every file in the library
+	pub fn allFiles(&self) -> impl Iterator<Item = &Path>
+	{
+		self.roots
+			.iter()
+			.flat_map(|root| root.files.values())
+			.flatten()
+			.map(PathBuf::as_path)
+	}
+
+	/// Get the full path to the directory at the given index in the tree
+	pub fn directoryAt(&self, index: usize) -> Option<PathBuf>
+	{
+		self.treeEntries()
+			.nth(index)
+			.map(|(root, directory)| treePath(root, directory))
+	}
+
+	/// Get the full paths of all the directories in the tree, in the order they're listed
+	pub fn directoryPaths(&self) -> Vec<PathBuf>
+	{
+		self.treeEntries()
+			.map(|(root, directory)| treePath(root, directory))
+			.collect()
+	}
+
+	/// Get the size of a file in bytes, as of when its metadata was last read
+	pub fn fileSize(&self, file: &Path) -> Option<u64>
+	{
+		self.roots.iter().find_map(|root| root.stamps.get(file)).map(|stamp| stamp.size)
+	}
+
+	/// Get what a query needs to know about a track to check it against the track
+	pub fn track<'a>(&'a self, file: &'a Path, statistics: &'a TrackStatistics, ratings: &'a TrackRatings)
+		-> Track<'a>
+	{
+		Track
+		{
+			file,
+			metadata: self.trackMetadata(file),
+			size: self.fileSize(file),
+			stats: statistics.get(file),
+			rating: ratings.get(file),
+		}
+	}
+
+	/// Get how many changes have been made to the tracks in the library, which only ever goes up
+	pub const fn generation(&self) -> u64
+	{
+		self.generation
+	}
+
+	/// Get the tracks in the library that match a query, in the order they'd be played
+	pub fn tracksMatching(&self, query: &Query, statistics: &TrackStatistics, ratings: &TrackRatings)
+		-> Vec<PathBuf>
+	{
+		let mut directories: Vec<_> = self.roots
+			.iter()
+			.flat_map(|root| root.files.iter())
+			.collect();
+		directories.sort_by(|(lhs, _), (rhs, _)| naturalPathCmp(lhs, rhs));
+		directories
+			.into_iter()
+			.flat_map(|(_, files)| self.orderTracks(files))
+			.filter(|file| query.matches(&self.track(file, statistics, ratings)))
+			.collect()
+	}
+
+	/// Find where the directory with the given full path is in the tree
+	pub fn directoryIndex(&self, directory: &Path) -> Option<usize>
+	{
+		self.treeEntries().position(|(root, entry)| root.basePath.join(entry) == directory)
+	}
+
+	pub fn fileIn(&self, dir: &Path, index: usize) -> Option<&PathBuf>
+	{
+		let files = self.filesIn(dir)?;
+		files.iter().nth(index)
+	}
+
+	/// Find where a file is in the listing of the directory it's in
+	pub fn fileIndex(&self, dir: &Path, file: &Path) -> Option<usize>
+	{
+		self.filesIn(dir)?.iter().position(|entry| entry == file)
+	}
+
+	fn albumsFor(&self, artistIndex: Option<usize>) -> Option<&BTreeMap<NaturalString, BTreeSet<PathBuf>>>
+	{
+		artistIndex.and_then(|index| self.artists.values().nth(index))
+	}
+
+	fn filesIn(&self, dir: &Path) -> Option<&BTreeSet<PathBuf>>
+	{
+		self.roots.iter().find_map(|root| root.files.get(dir))
+	}
+
+	/// Put a set of files in track order, using the disc and track numbers from their tags (or failing that, the
+	/// track number from their file names), then natural file name order for any that have no track number or
+	/// share one
+	fn orderTracks(&self, files: &BTreeSet<PathBuf>) -> Vec<PathBuf>
+	{
+		let mut tracks: Vec<_> = files
+			.iter()
+			.map
+			(
+				|file|
+				{
+					let metadata = self.trackMetadata(file);
+					let discNumber = metadata.and_then(|metadata| metadata.discNumber).unwrap_or_default();
+					let trackNumber = metadata
+						.and_then(|metadata| metadata.trackNumber)
+						.or_else(|| trackNumberFromFileName(file));
+					(discNumber, trackNumber, file)
+				}
+			)
+			.collect();
+		tracks.sort_by
+		(
+			|(lhsDisc, lhsNumber, lhsFile), (rhsDisc, rhsNumber, rhsFile)|
+			{
+				// Files that have a track number come before those that don't
+				let byNumber = match (lhsNumber, rhsNumber)
+				{
+					(Some(lhs), Some(rhs)) => lhs.cmp(rhs),
+					(Some(_), None) => Ordering::Less,
+					(None, Some(_)) => Ordering::Greater,
+					(None, None) => Ordering::Equal,
+				};
+				lhsDisc.cmp(rhsDisc)
+					.then(byNumber)
+					.then_with
+					(
+						|| naturalCmp
+						(
+							&lhsFile.file_name().unwrap_or_default().to_string_lossy(),
+							&rhsFile.file_name().unwrap_or_default().to_string_lossy()
+						)
+					)
+			}
+		);
+		tracks.into_iter().map(|(_, _, file)| file.clone()).collect()
+	}
+}
@@ -0,0 +1,145 @@
+// SPDX-License-Identifier: BSD-3-Clause
+use std::iter;
+use std::path::Path;
+use std::sync::Arc;
+
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
+use tracing::error;
+
+use crate::config::DiscoveryOptions;
+
+/// Name of the files that can be put in the library to leave things in that directory, and those below it, out
+pub const IGNORE_FILE: &str = ".lyrebirdignore";
+
+/// The rules for what discovery leaves out of a library root
+pub struct IgnoreRules
+{
+	options: DiscoveryOptions,
+	/// The ignore patterns from the configuration, rooted at the base of the library root
+	patterns: Gitignore,
+}
+
+/// The ignore files that apply in a directory, from the base of the library root on down
+#[derive(Clone, Default)]
+pub struct IgnoreFiles(Vec<Arc<Gitignore>>);
+
+impl Default for IgnoreRules
+{
+	fn default() -> Self
+	{
+		Self
+		{
+			options: DiscoveryOptions::default(),
+			patterns: Gitignore::empty(),
+		}
+	}
+}
+
+impl IgnoreRules
+{
+	pub fn new(basePath: &Path, options: &DiscoveryOptions) -> Self
+	{
+		let mut builder = GitignoreBuilder::new(basePath);
+		for pattern in &options.ignorePatterns
+		{
+			// A bad pattern shouldn't stop the rest from applying
+			if let Err(error) = builder.add_line(None, pattern)
+			{
+				error!("Skipping invalid library ignore pattern '{}': {}", pattern, error);
+			}
+		}
+		let patterns = builder.build()
+			.unwrap_or_else
+			(
+				|error|
+				{
+					error!("Failed to build library ignore patterns: {}", error);
+					Gitignore::empty()
+				}
+			);
+
+		Self
+		{
+			options: options.clone(),
+			patterns,
+		}
+	}
+
+	pub const fn followsSymlinks(&self) -> bool
+		{ self.options.followSymlinks }
+
+	/// Pick up the ignore file in a directory if it has one, adding it to those that apply in the directory above
+	pub fn enter(parent: &IgnoreFiles, directory: &Path) -> IgnoreFiles
+	{
+		let ignoreFile = directory.join(IGNORE_FILE);
+		if !ignoreFile.is_file()
+		{
+			return parent.clone();
+		}
+
+		// Any bad lines in the file get reported, with the rest of it still being used
+		let (gitignore, error) = Gitignore::new(&ignoreFile);
+		if let Some(error) = error
+		{
+			error!("While reading {}: {}", ignoreFile.display(), error);
+		}
+		let mut ignoreFiles = parent.clone();
+		ignoreFiles.0.push(Arc::new(gitignore));
+		ignoreFiles
+	}
+
+	/// Work out the ignore files that apply in a directory within the library root, along with how deep it is.
+	/// If the directory, or any of those above it, are to be left out then there's nothing to work out
+	pub fn walkTo(&self, basePath: &Path, directory: &Path) -> Option<(IgnoreFiles, usize)>
+	{
+		let relativePath = directory.strip_prefix(basePath).ok()?;
+		let mut ignoreFiles = Self::enter(&IgnoreFiles::default(), basePath);
+		let mut currentDirectory = basePath.to_path_buf();
+		let mut depth = 0;
+		for component in relativePath.components()
+		{
+			currentDirectory.push(component);
+			depth += 1;
+			if self.ignoresDirectory(&ignoreFiles, &currentDirectory, depth)
+			{
+				return None;
+			}
+			ignoreFiles = Self::enter(&ignoreFiles, &currentDirectory);
+		}
+		Some((ignoreFiles, depth))
+	}
+
+	/// Check whether a directory the given number of levels below the base of the library root is to be left out
+	pub fn ignoresDirectory(&self, ignoreFiles: &IgnoreFiles, directory: &Path, depth: usize) -> bool
+	{
+		self.options.maximumDepth.is_some_and(|maximumDepth| depth > maximumDepth) ||
+			self.ignores(ignoreFiles, directory, true)
+	}
+
+	/// Check whether a file is to be left out, going by its size too if that's known
+	pub fn ignoresFile(&self, ignoreFiles: &IgnoreFiles, file: &Path, size: Option<u64>) -> bool
+	{
+		file.file_name().is_some_and(|name| name == IGNORE_FILE) ||
+			size.is_some_and(|size| size < self.options.minimumFileSize) ||
+			self.ignores(ignoreFiles, file, false)
+	}
+
+	fn ignores(&self, ignoreFiles: &IgnoreFiles, path: &Path, isDirectory: bool) -> bool
+	{
+		let hidden = path.file_name().is_some_and(|name| name.as_encoded_bytes().starts_with(b"."));
+		if self.options.skipHidden && hidden
+		{
+			return true;
+		}
+
+		// The ignore file closest to the path gets the final say, then those above it in turn, and then the
+		// patterns from the configuration - so a `!pattern` in a directory can bring back something left out above
+		ignoreFiles.0
+			.iter()
+			.rev()
+			.map(|ignoreFile| ignoreFile.matched(path, isDirectory))
+			.chain(iter::once(self.patterns.matched(path, isDirectory)))
+			.find(|matched| !matched.is_none())
+			.is_some_and(|matched| matched.is_ignore())
+	}
+}
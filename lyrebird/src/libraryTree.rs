@@ -1,6 +1,7 @@
 // SPDX-License-Identifier: BSD-3-Clause
+use std::collections::BTreeSet;
 use std::path::{Path, PathBuf};
-use std::sync::{Arc, RwLock};
+use std::sync::{Arc, RwLock, RwLockReadGuard};
 
 use color_eyre::eyre::{self, Result};
 use crossterm::event::{KeyCode, KeyEvent, KeyEventKind};
@@ -11,6 +12,8 @@ use ratatui::symbols::scrollbar;
 use ratatui::widgets::{Block, BorderType, List, ListDirection, ListState, Padding, Scrollbar, ScrollbarOrientation, ScrollbarState, StatefulWidget, Widget};
 
 use crate::library::MusicLibrary;
+use crate::ratings::{RatingChange, TrackRatings};
+use crate::widgets::marks::Marks;
 use crate::window::Operation;
 
 pub struct LibraryTree
@@ -21,9 +24,13 @@ pub struct LibraryTree
 	dirListScrollbar: ScrollbarState,
 	filesListState: ListState,
 	filesListScrollbar: ScrollbarState,
+	dirMarks: Marks,
+	fileMarks: Marks,
 	viewportSize: Size,
 
 	library: Arc<RwLock<MusicLibrary>>,
+	/// The user's ratings, for showing alongside the files
+	ratings: Arc<TrackRatings>,
 }
 
 #[derive(Clone, Copy)]
@@ -35,9 +42,10 @@ enum Side
 
 impl LibraryTree
 {
-	pub fn new(activeEntry: Style, cacheFile: &Path, libraryPath: &Path, viewportSize: Size) -> Result<Self>
+	pub fn new(activeEntry: Style, library: Arc<RwLock<MusicLibrary>>, ratings: Arc<TrackRatings>,
+		viewportSize: Size) -> Self
 	{
-		Ok(Self
+		Self
 		{
 			activeEntry,
 			activeSide: Side::DirectoryTree,
@@ -45,12 +53,19 @@ impl LibraryTree
 			dirListScrollbar: ScrollbarState::default(),
 			filesListState: ListState::default(),
 			filesListScrollbar: ScrollbarState::default(),
+			dirMarks: Marks::default(),
+			fileMarks: Marks::default(),
 			viewportSize,
 
-			library: MusicLibrary::new(cacheFile, libraryPath)?,
-		})
+			library,
+			ratings,
+		}
 	}
 
+	/// Show the ratings given alongside the files from now on, as they've changed
+	pub fn useRatings(&mut self, ratings: Arc<TrackRatings>)
+		{ self.ratings = ratings; }
+
 	pub fn writeCache(&self) -> Result<()>
 	{
 		self.library.read()
@@ -62,6 +77,33 @@ impl LibraryTree
 			.writeCache()
 	}
 
+	/// Get a read lock on the library so its contents can be looked through
+	pub fn library(&self) -> Result<RwLockReadGuard<'_, MusicLibrary>>
+	{
+		self.library.read()
+			.map_err
+			(
+				|error|
+					eyre::eyre!("While reading library: {}", error.to_string())
+			)
+	}
+
+	/// Get a handle on the library for sharing with something else that needs to look through it
+	pub fn libraryHandle(&self) -> Arc<RwLock<MusicLibrary>>
+	{
+		self.library.clone()
+	}
+
+	/// Go looking through the library for anything that's been added, removed or changed
+	fn rescan(&self) -> Operation
+	{
+		match MusicLibrary::rescan(&self.library)
+		{
+			Ok(()) => Operation::None,
+			Err(error) => Operation::Error(error.to_string()),
+		}
+	}
+
 	pub fn isDiscovering(&self) -> bool
 	{
 		self.library.read().expect("Library lock in bad state").isDiscovering()
@@ -76,16 +118,84 @@ impl LibraryTree
 	{
 		if key.kind == KeyEventKind::Press || key.kind == KeyEventKind::Repeat
 		{
-			match key.code
+			// Marking directories for acting on several at once, keeping the marks on the same directories if the
+			// library's changed since they were made
+			if let Side::DirectoryTree = self.activeSide
+			{
+				let directories = self.library.read()
+					.map(|library| library.directoryPaths())
+					.unwrap_or_default();
+				self.dirMarks.followItems(&directories);
+				if self.dirMarks.handleKeyEvent(key, &mut self.dirListState, directories.len())
+				{
+					// Marking moves the selection on, so the files shown are for another directory now
+					self.resetFiles();
+					return Operation::None;
+				}
+			}
+
+			// Marking files for acting on several at once
+			if let Side::Files = self.activeSide
+			{
+				let count = self.library.read()
+					.map
+					(
+						|library|
+						{
+							// The directory might have changed since the marks were made, so keep them on the
+							// same files
+							let files = library.filesListed(self.dirListState.selected());
+							self.fileMarks.followItems(&files);
+							files.len()
+						}
+					)
+					.unwrap_or_default();
+				if self.fileMarks.handleKeyEvent(key, &mut self.filesListState, count)
+				{
+					return Operation::None;
+				}
+			}
+
+			// Rating the whole directory, or the chosen files
+			if let Some(change) = RatingChange::forKey(key.code)
 			{
-				KeyCode::Left => self.moveLeft(),
-				KeyCode::Right => self.moveRight(),
-				KeyCode::Up => self.moveUp(),
-				KeyCode::Down => self.moveDown(),
-				KeyCode::PageUp => self.movePageUp(),
-				KeyCode::PageDown => self.movePageDown(),
-				KeyCode::Enter => { return self.playSelection(); },
-				KeyCode::Char('+') => { return Operation::playlist(self.makeSelection()); },
+				let selection = match self.activeSide
+				{
+					Side::DirectoryTree => self.directorySelection(false),
+					Side::Files => self.makeSelection(),
+				};
+				return Operation::rate(selection, change);
+			}
+
+			match (self.activeSide, key.code)
+			{
+				(_, KeyCode::Left) => self.moveLeft(),
+				(_, KeyCode::Right) => self.moveRight(),
+				(_, KeyCode::Up) => self.moveUp(),
+				(_, KeyCode::Down) => self.moveDown(),
+				(_, KeyCode::PageUp) => self.movePageUp(),
+				(_, KeyCode::PageDown) => self.movePageDown(),
+				(_, KeyCode::Enter) => { return self.playSelection(); },
+				(_, KeyCode::Char('r')) => { return self.rescan(); },
+				// Acting on a whole directory, with the shifted keys also taking in all its subdirectories
+				(Side::DirectoryTree, KeyCode::Char('p')) => { return Operation::play(self.directorySelection(false)); },
+				(Side::DirectoryTree, KeyCode::Char('P')) => { return Operation::play(self.directorySelection(true)); },
+				(Side::DirectoryTree, KeyCode::Char('+')) =>
+					{ return Operation::playlist(self.directorySelection(false)); },
+				(Side::DirectoryTree, KeyCode::Char('*')) =>
+					{ return Operation::playlist(self.directorySelection(true)); },
+				(Side::DirectoryTree, KeyCode::Char('i')) =>
+					{ return Operation::insertNext(self.directorySelection(false)); },
+				(Side::DirectoryTree, KeyCode::Char('I')) =>
+					{ return Operation::insertNext(self.directorySelection(true)); },
+				(Side::DirectoryTree, KeyCode::Char('a')) =>
+					{ return Operation::addToPlaylist(self.directorySelection(false)); },
+				(Side::DirectoryTree, KeyCode::Char('A')) =>
+					{ return Operation::addToPlaylist(self.directorySelection(true)); },
+				// Acting on a single file
+				(Side::Files, KeyCode::Char('+')) => { return Operation::playlist(self.makeSelection()); },
+				(Side::Files, KeyCode::Char('i')) => { return Operation::insertNext(self.makeSelection()); },
+				(Side::Files, KeyCode::Char('a')) => { return Operation::addToPlaylist(self.makeSelection()); },
 				_ => {},
 			}
 		}
@@ -108,7 +218,7 @@ impl LibraryTree
 			Side::DirectoryTree =>
 			{
 				self.dirListState.select_previous();
-				self.filesListState = ListState::default();
+				self.resetFiles();
 			}
 			Side::Files =>
 			{
@@ -124,7 +234,7 @@ impl LibraryTree
 			Side::DirectoryTree =>
 			{
 				self.dirListState.select_next();
-				self.filesListState = ListState::default();
+				self.resetFiles();
 			}
 			Side::Files =>
 			{
@@ -140,7 +250,7 @@ impl LibraryTree
 			Side::DirectoryTree =>
 			{
 				self.dirListState.scroll_up_by(self.viewportSize.height);
-				self.filesListState = ListState::default();
+				self.resetFiles();
 			}
 			Side::Files =>
 			{
@@ -156,7 +266,7 @@ impl LibraryTree
 			Side::DirectoryTree =>
 			{
 				self.dirListState.scroll_down_by(self.viewportSize.height);
-				self.filesListState = ListState::default();
+				self.resetFiles();
 			}
 			Side::Files =>
 			{
@@ -165,36 +275,83 @@ impl LibraryTree
 		}
 	}
 
+	/// Select a file in the tree, moving to the directory it's in and over to the file listing
+	pub fn reveal(&mut self, file: &Path)
+	{
+		let indices = self.library.read()
+			.ok()
+			.and_then
+			(
+				|library|
+				{
+					let directory = file.parent()?;
+					Some((library.directoryIndex(directory)?, library.fileIndex(directory, file)))
+				}
+			);
+		let Some((dirIndex, fileIndex)) = indices
+			else { return; };
+
+		self.dirListState.select(Some(dirIndex));
+		self.resetFiles();
+		self.filesListState.select(fileIndex);
+		self.activeSide = Side::Files;
+	}
+
 	/// If the currently sellected side is the directory listing, switch to that directory's file listing
-	/// otherwise, if it's the file listing, figure out which one and make a `SongState` for it
-	fn makeSelection(&mut self) -> Option<PathBuf>
+	/// otherwise, if it's the file listing, figure out which files are marked (or failing that, selected)
+	fn makeSelection(&mut self) -> Vec<PathBuf>
 	{
 		match self.activeSide
 		{
-			Side::DirectoryTree => self.activeSide = Side::Files,
+			Side::DirectoryTree =>
+			{
+				self.activeSide = Side::Files;
+				Vec::new()
+			},
 			Side::Files =>
 			{
 				// Lock open access to the library
-				let library = self.library.read().ok()?;
+				let Ok(library) = self.library.read()
+					else { return Vec::new(); };
 				// Extract the current directory selection
-				let dir = library.directoryAt(self.dirListState.selected()?)?;
-				// Extract the current file selection
-				let file = library.fileIn(dir, self.filesListState.selected()?)?;
-				// Now make a new SongState object for that file if possible
-				return Some(dir.join(file));
+				let Some(dir) = self.dirListState.selected().and_then(|index| library.directoryAt(index))
+					else { return Vec::new(); };
+				// Now look up each of the files chosen
+				self.fileMarks.orSelected(self.filesListState.selected())
+					.into_iter()
+					.filter_map(|index| library.fileIn(&dir, index))
+					.map(|file| dir.join(file))
+					.collect()
 			}
 		}
-		None
+	}
+
+	/// The selected directory has changed, so forget about what was selected and marked in the old one
+	fn resetFiles(&mut self)
+	{
+		self.filesListState = ListState::default();
+		self.fileMarks.clear();
 	}
 
 	fn playSelection(&mut self) -> Operation
 	{
-		let selection = self.makeSelection();
-		match selection
-		{
-			Some(selection) => Operation::Play(selection),
-			None => Operation::None,
-		}
+		Operation::play(self.makeSelection())
+	}
+
+	/// Get the tracks in the marked directories (or failing that, the selected one), and those in their
+	/// subdirectories if asked, in the order they'd play
+	fn directorySelection(&self, recursive: bool) -> Vec<PathBuf>
+	{
+		let Ok(library) = self.library.read()
+			else { return Vec::new(); };
+		// A marked directory might be inside another, so make sure its tracks only get picked up the once
+		let mut seen = BTreeSet::new();
+		self.dirMarks.orSelected(self.dirListState.selected())
+			.into_iter()
+			.filter_map(|index| library.directoryAt(index))
+			.flat_map(|directory| library.tracksIn(&directory, recursive))
+			.filter(|track| seen.insert(track.clone()))
+			.collect()
 	}
 }
 
@@ -210,17 +367,31 @@ impl Widget for &mut LibraryTree
 
 		// Get a lock on the library so we get a consistent view of it for rendering
 		let libraryLock = self.library.read().expect("Library lock in bad state");
+		self.dirMarks.followItems(&libraryLock.directoryPaths());
+		self.fileMarks.followItems(&libraryLock.filesListed(self.dirListState.selected()));
+		// While the library's being discovered, show how far that's got for each root in the tree's title
+		let scanning: Vec<_> = libraryLock.discoveryProgress()
+			.map(|(root, progress)| format!("{root}: {progress}"))
+			.collect();
+		let treeTitle = if scanning.is_empty()
+		{
+			" Directory Tree ".to_string()
+		}
+		else
+		{
+			format!(" Directory Tree - scanning {} ", scanning.join("; "))
+		};
 
 		// Render the directory list using the internal state object
 		StatefulWidget::render
 		(
 			// Build a list of directories currently in the library
-			List::new(libraryLock.directories())
+			List::new(self.dirMarks.apply(libraryLock.directories()))
 				// Put it in a bordered block for presentation
 				.block
 				(
 					Block::bordered()
-						.title(" Directory Tree ")
+						.title(treeTitle)
 						.title_alignment(Alignment::Left)
 						.title_style
 						(
@@ -259,8 +430,8 @@ impl Widget for &mut LibraryTree
 		);
 
 		// Build a list of files in the current directory being displayed
-		let filesList = libraryLock.filesFor(self.dirListState.selected())
-			.map(List::new)
+		let filesList = libraryLock.filesFor(self.dirListState.selected(), &self.ratings)
+			.map(|files| List::new(self.fileMarks.apply(files)))
 			.unwrap_or_default()
 			// Put it in a bordered block for presentation
 			.block
@@ -0,0 +1,86 @@
+// SPDX-License-Identifier: BSD-3-Clause
+use std::collections::BTreeSet;
+use std::path::Path;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+use color_eyre::eyre::{eyre, Result};
+use notify_debouncer_mini::notify::{RecommendedWatcher, RecursiveMode};
+use notify_debouncer_mini::{new_debouncer, DebounceEventResult, Debouncer};
+use tokio::sync::mpsc::{channel, Receiver};
+use tracing::error;
+
+use crate::library::MusicLibrary;
+
+/// How long to wait for a burst of changes (eg, an album being copied in) to settle before acting on them
+const DEBOUNCE_TIME: Duration = Duration::from_secs(2);
+
+/// Watches the library's roots for changes, feeding them into the library as they happen
+pub struct LibraryWatcher
+{
+	/// The debouncer owns the underlying watcher, and stops watching when dropped
+	_debouncer: Debouncer<RecommendedWatcher>,
+	changes: Receiver<()>,
+}
+
+impl LibraryWatcher
+{
+	/// Start watching the library's roots that are to be watched, if there are any
+	pub fn new(library: Arc<RwLock<MusicLibrary>>) -> Result<Option<Self>>
+	{
+		let basePaths: Vec<_> = library.read()
+			.map_err(|error| eyre!("While watching library: {}", error))?
+			.watchedPaths()
+			.map(Path::to_path_buf)
+			.collect();
+		if basePaths.is_empty()
+		{
+			return Ok(None);
+		}
+		let (sender, changes) = channel(1);
+
+		let mut debouncer = new_debouncer
+		(
+			DEBOUNCE_TIME,
+			move |result: DebounceEventResult|
+			{
+				match result
+				{
+					Ok(events) =>
+					{
+						// Several events can come in for the same path, so only deal with each once
+						let paths: BTreeSet<_> = events.into_iter().map(|event| event.path).collect();
+						if let Err(error) = MusicLibrary::applyChanges(&library, paths)
+						{
+							error!("Updating library from changes failed: {}", error);
+						}
+						// Let the main window know there's something new to draw - if it's already been told,
+						// there's no need to tell it again
+						let _ = sender.try_send(());
+					},
+					Err(error) => error!("While watching library: {}", error),
+				}
+			}
+		)?;
+		for basePath in basePaths
+		{
+			// One root not being watchable (eg, it's on a filesystem that can't be) shouldn't stop the rest being
+			if let Err(error) = debouncer.watcher().watch(&basePath, RecursiveMode::Recursive)
+			{
+				error!("Failed to watch {} for changes: {}", basePath.display(), error);
+			}
+		}
+
+		Ok(Some(Self
+		{
+			_debouncer: debouncer,
+			changes,
+		}))
+	}
+
+	/// Wait for the library to be changed by something the watcher saw
+	pub async fn changed(&mut self) -> Option<()>
+	{
+		self.changes.recv().await
+	}
+}
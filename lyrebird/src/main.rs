@@ -4,26 +4,47 @@
 #![allow(non_snake_case)]
 #![warn(clippy::pedantic)]
 
-use color_eyre::{eyre, Result};
+use clap::Parser;
+use cli::Arguments;
+use color_eyre::{config::HookBuilder, eyre, Result};
 use config::Config;
 use directories::ProjectDirs;
+use ratatui::DefaultTerminal;
+use tracing::error;
 use tracing::level_filters::LevelFilter;
 use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt, Layer};
 use window::MainWindow;
 
+mod albums;
+mod artists;
+mod cli;
 mod config;
+mod fuzzyMatch;
+mod history;
 mod library;
 mod libraryTree;
+mod libraryWatcher;
+mod metadata;
+mod naturalOrder;
 mod options;
+mod persistence;
 mod playback;
 mod playlist;
+mod playlistFile;
 mod playlists;
+mod query;
+mod ratings;
+mod search;
+mod signals;
+mod smartPlaylist;
+mod statistics;
 mod widgets;
 mod window;
 
 #[tokio::main(flavor = "multi_thread")]
 async fn main() -> Result<()>
 {
+	let arguments = Arguments::parse();
 	tracing_subscriber::registry()
 		.with
 		(
@@ -31,21 +52,65 @@ async fn main() -> Result<()>
 				.with_filter(LevelFilter::INFO)
 		)
 		.init();
+	// Make sure that if anything goes wrong, the user gets their terminal back and a useful report
+	installHooks()?;
 
 	// Try to get the application paths available
 	let paths = ProjectDirs::from("com", "rachelmant", "Lyrebird").
 		ok_or_else(|| eyre::eyre!("Failed to get program working paths"))?;
+	// If we've been asked to run a command rather than the player, do that instead
+	if let Some(command) = arguments.command
+	{
+		return command.run(&paths);
+	}
 	// Now try to get a configuration object so we know where to find things and such
-	let mut config = Config::read(&paths)?;
+	let config = Config::read(&paths)?;
 
-	// Aquire the terminal to use and set up the main window w/ the configuration
+	// Aquire the terminal to use and run the main window of Lyrebird till the user exits the program
 	let mut terminal = ratatui::init();
-	let mut mainWindow = MainWindow::new(&paths, &mut config, terminal.size()?)?;
-	// Now run the main window of Lyrebird till the user exits the program
-	let result = mainWindow.run(&mut terminal).await;
+	let result = runMainWindow(&paths, config, &mut terminal).await;
 	// Give the terminal back and return the result of running the main window
 	ratatui::restore();
-	// Re-serialise the user's config as our last step
-	config.write(&paths)?;
 	result
 }
+
+async fn runMainWindow(paths: &ProjectDirs, config: Config, terminal: &mut DefaultTerminal) -> Result<()>
+{
+	// Set up the main window w/ the configuration
+	let mut mainWindow = MainWindow::new(paths, config, terminal.size()?)?;
+	// Now run the main window of Lyrebird till the user exits the program
+	let result = mainWindow.run(terminal).await;
+	// Re-serialise the user's state as our last step, even if the main window exited on an error
+	let saved = mainWindow.writeState();
+	result.and(saved)
+}
+
+fn installHooks() -> Result<()>
+{
+	let (panicHook, eyreHook) = HookBuilder::default().into_hooks();
+	eyreHook.install()?;
+	// This is called from the main thread, which is the one running the main window
+	let mainThread = std::thread::current().id();
+	// Restore the terminal before displaying the panic report, otherwise it gets lost on the alternate screen.
+	// Saving the user's state is handled as the main window is unwound
+	std::panic::set_hook
+	(
+		Box::new
+		(
+			move |panicInfo|
+			{
+				// A panic on any other thread (eg, a discovery worker) doesn't take the main window down with it,
+				// so leave the terminal be for the main window to carry on drawing to and just log what happened
+				let thread = std::thread::current();
+				if thread.id() != mainThread
+				{
+					error!("Thread {} panicked: {}", thread.name().unwrap_or("<unnamed>"), panicInfo);
+					return;
+				}
+				ratatui::restore();
+				eprintln!("{}", panicHook.panic_report(panicInfo));
+			}
+		)
+	);
+	Ok(())
+}
@@ -0,0 +1,128 @@
+// SPDX-License-Identifier: BSD-3-Clause
+use std::fs;
+use std::path::Path;
+use std::time::{Duration, SystemTime};
+
+use libAudio::audioFile::AudioFile;
+use serde::{Deserialize, Serialize};
+
+/// Metadata about a track, as read from its tags
+#[derive(Clone, Default, Serialize, Deserialize)]
+pub struct TrackMetadata
+{
+	pub title: Option<String>,
+	pub artist: Option<String>,
+	pub album: Option<String>,
+	/// The artist the album as a whole is by, for compilations and tracks featuring other artists
+	pub albumArtist: Option<String>,
+	pub genre: Option<String>,
+	pub trackNumber: Option<u32>,
+	pub discNumber: Option<u32>,
+	pub year: Option<u32>,
+	pub duration: Option<Duration>,
+	pub channels: Option<u8>,
+	/// Sample rate of the audio in Hz - libAudio reports this as the file's bit rate, which is what older library
+	/// caches called it
+	#[serde(alias = "bitRate")]
+	pub sampleRate: Option<u32>,
+	pub bitsPerSample: Option<u32>,
+	/// What kind of audio file this is, going by its extension
+	pub format: Option<String>,
+	/// When the file was added to the user's collection, going by when it was last modified
+	pub dateAdded: Option<SystemTime>,
+}
+
+impl TrackMetadata
+{
+	/// Read the metadata for a file in from its tags, if it's a file libAudio can open
+	pub fn read(fileName: &Path) -> Option<Self>
+	{
+		let audioFile = AudioFile::readFile(fileName)?;
+		let fileInfo = audioFile.fileInfo();
+		let totalTime = fileInfo.totalTime();
+		// libAudio hands any tags it doesn't know about to us as `KEY=value` comments
+		let comments = fileInfo.otherComments().unwrap_or_default();
+
+		Some(Self
+		{
+			title: fileInfo.title().ok().flatten(),
+			artist: fileInfo.artist().ok().flatten(),
+			album: fileInfo.album().ok().flatten(),
+			albumArtist: ["ALBUMARTIST", "ALBUM ARTIST", "ALBUM_ARTIST"]
+				.into_iter()
+				.find_map(|key| otherComment(&comments, key))
+				.filter(|value| !value.is_empty())
+				.map(ToString::to_string),
+			genre: otherComment(&comments, "GENRE")
+				.filter(|value| !value.is_empty())
+				.map(ToString::to_string),
+			trackNumber: otherComment(&comments, "TRACKNUMBER")
+				.or_else(|| otherComment(&comments, "TRACK"))
+				.and_then(parseNumber),
+			discNumber: otherComment(&comments, "DISCNUMBER")
+				.or_else(|| otherComment(&comments, "DISC"))
+				.and_then(parseNumber),
+			year: otherComment(&comments, "DATE")
+				.or_else(|| otherComment(&comments, "YEAR"))
+				.and_then(parseYear),
+			duration: if totalTime != 0 { Some(Duration::from_secs(totalTime)) } else { None },
+			channels: Some(fileInfo.channels()).filter(|&channels| channels != 0),
+			sampleRate: Some(fileInfo.bitRate()).filter(|&sampleRate| sampleRate != 0),
+			bitsPerSample: Some(fileInfo.bitsPerSample()).filter(|&bitsPerSample| bitsPerSample != 0),
+			format: fileName.extension().map(|extension| extension.to_string_lossy().to_uppercase()),
+			dateAdded: fs::metadata(fileName).and_then(|metadata| metadata.modified()).ok(),
+		})
+	}
+
+	/// Work out which artist to file this track under - the album artist if it has one, otherwise its own artist
+	pub fn filedArtist(&self) -> Option<&str>
+	{
+		self.albumArtist.as_deref().or(self.artist.as_deref())
+	}
+}
+
+/// Format a running time for display, including the hours only if there are any
+pub fn formatDuration(duration: Duration) -> String
+{
+	let seconds = duration.as_secs();
+	let (hours, minutes, seconds) = (seconds / 3600, (seconds / 60) % 60, seconds % 60);
+	if hours == 0
+	{
+		format!("{minutes}:{seconds:02}")
+	}
+	else
+	{
+		format!("{hours}:{minutes:02}:{seconds:02}")
+	}
+}
+
+/// Look a tag up in the other comments for a file by its key, ignoring the case of the key
+fn otherComment<'a>(comments: &'a [String], key: &str) -> Option<&'a str>
+{
+	comments
+		.iter()
+		.filter_map(|comment| comment.split_once('='))
+		.find(|(commentKey, _)| commentKey.trim().eq_ignore_ascii_case(key))
+		.map(|(_, value)| value.trim())
+}
+
+/// Parse a number out of a tag value - these are often given as "number/total", so anything after a `/` is ignored
+fn parseNumber(value: &str) -> Option<u32>
+{
+	value.split('/').next()?.trim().parse().ok()
+}
+
+/// Parse the year out of a date tag value - these are usually either just the year, or a full ISO 8601 date
+fn parseYear(value: &str) -> Option<u32>
+{
+	value.get(..4)?.parse().ok()
+}
+
+/// Work out a track's number from its file name, for when its tags don't say - files are very often named with
+/// the track number first, eg "01 - Title.flac"
+pub fn trackNumberFromFileName(fileName: &Path) -> Option<u32>
+{
+	let name = fileName.file_stem()?.to_string_lossy();
+	let digits = name.find(|character: char| !character.is_ascii_digit()).unwrap_or(name.len());
+	name[..digits].parse().ok()
+}
@@ -0,0 +1,157 @@
+// SPDX-License-Identifier: BSD-3-Clause
+use std::cmp::Ordering;
+use std::iter::Peekable;
+use std::path::Path;
+use std::str::Chars;
+
+/// Compare two strings in natural order - that is, runs of digits are compared by their numeric value so "2"
+/// comes before "10", and the rest is compared ignoring case
+pub fn naturalCmp(lhs: &str, rhs: &str) -> Ordering
+{
+	let mut lhsChars = lhs.chars().peekable();
+	let mut rhsChars = rhs.chars().peekable();
+	loop
+	{
+		let ordering = match (lhsChars.peek(), rhsChars.peek())
+		{
+			(None, None) => return lhs.cmp(rhs),
+			(None, Some(_)) => return Ordering::Less,
+			(Some(_), None) => return Ordering::Greater,
+			(Some(lhsChar), Some(rhsChar)) if lhsChar.is_ascii_digit() && rhsChar.is_ascii_digit() =>
+				compareNumbers(&takeDigits(&mut lhsChars), &takeDigits(&mut rhsChars)),
+			(Some(lhsChar), Some(rhsChar)) =>
+			{
+				let ordering = lhsChar.to_lowercase().cmp(rhsChar.to_lowercase());
+				lhsChars.next();
+				rhsChars.next();
+				ordering
+			},
+		};
+		if ordering != Ordering::Equal
+		{
+			return ordering;
+		}
+	}
+}
+
+/// Pull the run of digits at the front of the characters out as a string
+fn takeDigits(chars: &mut Peekable<Chars<'_>>) -> String
+{
+	let mut digits = String::new();
+	while let Some(digit) = chars.next_if(char::is_ascii_digit)
+	{
+		digits.push(digit);
+	}
+	digits
+}
+
+/// Compare two runs of digits by the numbers they represent, without having to worry about them overflowing
+fn compareNumbers(lhs: &str, rhs: &str) -> Ordering
+{
+	let lhs = lhs.trim_start_matches('0');
+	let rhs = rhs.trim_start_matches('0');
+	lhs.len().cmp(&rhs.len()).then_with(|| lhs.cmp(rhs))
+}
+
+/// Compare two paths in natural order, a component at a time so that a directory's contents all sort together
+pub fn naturalPathCmp(lhs: &Path, rhs: &Path) -> Ordering
+{
+	let mut lhsComponents = lhs.iter();
+	let mut rhsComponents = rhs.iter();
+	loop
+	{
+		let ordering = match (lhsComponents.next(), rhsComponents.next())
+		{
+			(None, None) => return Ordering::Equal,
+			(None, Some(_)) => return Ordering::Less,
+			(Some(_), None) => return Ordering::Greater,
+			(Some(lhs), Some(rhs)) => naturalCmp(&lhs.to_string_lossy(), &rhs.to_string_lossy()),
+		};
+		if ordering != Ordering::Equal
+		{
+			return ordering;
+		}
+	}
+}
+
+/// A string that sorts in natural order, for keying collections that are to be listed the way a person would
+/// order them
+#[derive(Clone, PartialEq, Eq)]
+pub struct NaturalString(pub String);
+
+impl Ord for NaturalString
+{
+	fn cmp(&self, other: &Self) -> Ordering
+	{
+		naturalCmp(&self.0, &other.0)
+	}
+}
+
+impl PartialOrd for NaturalString
+{
+	fn partial_cmp(&self, other: &Self) -> Option<Ordering>
+	{
+		Some(self.cmp(other))
+	}
+}
+
+#[cfg(test)]
+mod tests
+{
+	use std::cmp::Ordering;
+	use std::collections::BTreeSet;
+	use std::path::Path;
+
+	use super::{naturalCmp, naturalPathCmp, NaturalString};
+
+	#[test]
+	fn numbersByValue()
+	{
+		assert_eq!(naturalCmp("Track 2", "Track 10"), Ordering::Less);
+		assert_eq!(naturalCmp("Track 10", "Track 9"), Ordering::Greater);
+		assert_eq!(naturalCmp("Disc 1 Track 10", "Disc 2 Track 1"), Ordering::Less);
+		// Numbers far too long for any integer type still compare properly
+		assert_eq!(naturalCmp("99999999999999999999999", "100000000000000000000000"), Ordering::Less);
+		// Numbers sort before letters, as they do in plain string order
+		assert_eq!(naturalCmp("1 Intro", "Intro"), Ordering::Less);
+	}
+
+	#[test]
+	fn ignoresCase()
+	{
+		assert_eq!(naturalCmp("abba", "Blondie"), Ordering::Less);
+		assert_eq!(naturalCmp("Élan", "éternel"), Ordering::Less);
+	}
+
+	#[test]
+	fn tiesAreBroken()
+	{
+		// Strings that are only the same ignoring case or leading zeros still have to order one way or the other,
+		// otherwise sets keyed by them would lose entries
+		assert_ne!(naturalCmp("abc", "ABC"), Ordering::Equal);
+		assert_ne!(naturalCmp("Track 01", "Track 1"), Ordering::Equal);
+		assert_eq!(naturalCmp("Track 01", "Track 01"), Ordering::Equal);
+		assert_eq!(naturalCmp("", ""), Ordering::Equal);
+		assert_eq!(naturalCmp("", "a"), Ordering::Less);
+
+		let names: BTreeSet<_> = ["abc", "ABC", "Abc"].map(|name| NaturalString(name.into())).into_iter().collect();
+		assert_eq!(names.len(), 3);
+	}
+
+	#[test]
+	fn pathsByComponent()
+	{
+		assert_eq!(naturalPathCmp(Path::new("Album 2/10.mp3"), Path::new("Album 10/1.mp3")), Ordering::Less);
+		// A directory's contents all come before the directory next to it, whatever they're called
+		assert_eq!(naturalPathCmp(Path::new("Album/z.mp3"), Path::new("Album 2/a.mp3")), Ordering::Less);
+		assert_eq!(naturalPathCmp(Path::new("Album"), Path::new("Album/a.mp3")), Ordering::Less);
+	}
+
+	#[test]
+	fn sortsNaturally()
+	{
+		let mut names = vec!["track10.flac", "Track2.flac", "track1.flac", "Track 3.flac"];
+		names.sort_by(|lhs, rhs| naturalCmp(lhs, rhs));
+		assert_eq!(names, ["Track 3.flac", "track1.flac", "Track2.flac", "track10.flac"]);
+	}
+}
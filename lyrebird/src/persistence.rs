@@ -0,0 +1,35 @@
+// SPDX-License-Identifier: BSD-3-Clause
+use std::fs::{create_dir_all, rename, File};
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+use color_eyre::eyre::{OptionExt, Result};
+use serde::{Deserialize, Serialize};
+
+/// Just enough of a versioned file to find out which version of Lyrebird wrote it
+#[derive(Deserialize)]
+pub struct StoredVersion
+{
+	pub version: u8,
+}
+
+/// Serialise a value out as JSON to the given path atomically. The data is first written to a temporary file
+/// alongside the target which is then renamed over it, so a crash part way through never leaves a truncated file
+pub fn writeJsonAtomically<T: Serialize>(path: &Path, value: &T) -> Result<()>
+{
+	// Make sure all the leading path elements exist
+	create_dir_all
+	(
+		path.parent()
+			.ok_or_eyre("Failed to extract the directory to write the file into")?
+	)?;
+	// Write the data out to the temporary file
+	let tempPath = path.with_extension("tmp");
+	let mut file = BufWriter::new(File::create(&tempPath)?);
+	serde_json::to_writer(&mut file, value)?;
+	file.flush()?;
+	// Make sure it's actually on disk before swapping it in for the old copy
+	file.get_ref().sync_all()?;
+	rename(tempPath, path)?;
+	Ok(())
+}
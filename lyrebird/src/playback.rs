@@ -1,8 +1,9 @@
 // SPDX-License-Identifier: BSD-3-Clause
-use std::path::Path;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::{Arc, Mutex};
 use std::thread::{spawn, JoinHandle};
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
 use color_eyre::eyre::{self, OptionExt, Result};
 use libAudio::audioFile::AudioFile;
@@ -10,9 +11,11 @@ use tokio::sync::mpsc::Sender;
 
 pub struct Song
 {
+	fileName: PathBuf,
 	description: String,
 	duration: Option<Duration>,
 	played: Duration,
+	playingSince: Option<Instant>,
 	playbackThread: Option<JoinHandle<()>>,
 	state: Arc<ThreadState>
 }
@@ -33,6 +36,8 @@ struct ThreadState
 	audioFile: AudioFile,
 	notification: Sender<PlaybackState>,
 	state: Mutex<PlaybackState>,
+	/// How many bytes of decoded audio are still to be thrown away to get to where playback is to start from
+	skipRemaining: AtomicU64,
 }
 
 impl Song
@@ -55,9 +60,11 @@ impl Song
 		(
 			Self
 			{
+				fileName: fileName.to_path_buf(),
 				description: Self::buildDescriptionFrom(fileName, title, album, artist),
 				duration: if totalTime != 0 { Some(Duration::from_secs(totalTime)) } else { None },
 				played: Duration::default(),
+				playingSince: None,
 				playbackThread: None,
 				state: Arc::new(ThreadState::from(audioFile, notificationChannel)),
 			}
@@ -95,6 +102,12 @@ impl Song
 		description
 	}
 
+	/// Get the path to the file being played
+	pub fn fileName(&self) -> &Path
+	{
+		&self.fileName
+	}
+
 	// Return a copy of the description of what this song is
 	pub fn description(&self) -> String
 	{
@@ -110,7 +123,29 @@ impl Song
 	// Extract how much we've played of this song
 	pub fn playedDuration(&self) -> Duration
 	{
-		self.played
+		// Add on however long we've been playing for since playback was last (re)started
+		let played = self.played + self.playingSince.map_or_else(Duration::default, |since| since.elapsed());
+		// Don't let that run past the end of the song if it completed and we've not yet moved on
+		match self.duration
+		{
+			Some(duration) => played.min(duration),
+			None => played,
+		}
+	}
+
+	// Skip forward through the song to the given position, which must be done before playback starts.
+	// libAudio does not provide seeking, so the playback thread decodes and throws away the audio up to that point
+	// when playback starts, as that can take a while
+	pub fn skipTo(&mut self, position: Duration)
+	{
+		let fileInfo = self.state.audioFile.fileInfo();
+		// Figure out how many bytes of decoded audio a second of playback is (libAudio reports the sample rate
+		// of the file as its bit rate), and from that how many bytes must be thrown away to get to the position
+		let bytesPerSecond = u128::from(fileInfo.bitRate()) * u128::from(fileInfo.channels()) *
+			u128::from(fileInfo.bitsPerSample() / 8);
+		let remaining = u64::try_from(bytesPerSecond * position.as_millis() / 1000).unwrap_or(u64::MAX);
+		self.state.skipRemaining.store(remaining, Ordering::Relaxed);
+		self.played = position;
 	}
 
 	// Launch playback of the song on a seperate thread
@@ -122,6 +157,7 @@ impl Song
 			let state = self.state.clone();
 			let task = move || { state.play(); };
 			self.playbackThread = Some(spawn(task));
+			self.playingSince = Some(Instant::now());
 		}
 	}
 
@@ -131,6 +167,7 @@ impl Song
 		// If we're in a playing state, pause playback
 		let result = self.state.pause(self.playbackThread.take());
 		self.playbackThread = None;
+		self.accumulatePlayed();
 		result
 	}
 
@@ -140,9 +177,19 @@ impl Song
 		// If we're in a playing state, stop playback
 		let result = self.state.stop(self.playbackThread.take());
 		self.playbackThread = None;
+		self.accumulatePlayed();
 		result
 	}
 
+	// Fold the time spent playing since playback was last (re)started into the played duration
+	fn accumulatePlayed(&mut self)
+	{
+		if let Some(since) = self.playingSince.take()
+		{
+			self.played += since.elapsed();
+		}
+	}
+
 	// Query the state playback is currently in for this song
 	pub fn state(&self) -> PlaybackState
 	{
@@ -164,6 +211,7 @@ impl ThreadState
 			audioFile,
 			notification,
 			state: Mutex::new(PlaybackState::NotStarted),
+			skipRemaining: AtomicU64::new(0),
 		}
 	}
 
@@ -172,8 +220,12 @@ impl ThreadState
 		// Switch into playing state if we're not already
 		if self.switchTo(PlaybackState::Playing)
 		{
-			// We weren't already, so have libAudio actually do playback (this is blocking!)
-			self.audioFile.play();
+			// We weren't already, so get to where playback's to start from, and then so long as we've not been
+			// asked to pause or stop in the meantime, have libAudio actually do playback (this is blocking!)
+			if self.skip()
+			{
+				self.audioFile.play();
+			}
 			// Now, check what playback state we're in.. if we're in Playing still, the file ended
 			// and we should notify the main window of this fact via a channel
 			let mut state = self.state.lock()
@@ -238,6 +290,36 @@ impl ThreadState
 		Ok(())
 	}
 
+	// Decode and throw away any audio still to be skipped, giving up early if playback is paused or stopped, and
+	// returning whether playback is still to go ahead. Whatever's left to skip is picked back up next time
+	fn skip(&self) -> bool
+	{
+		let mut buffer = vec![0_u8; 16384];
+		loop
+		{
+			if self.state.lock().map_or(true, |state| *state != PlaybackState::Playing)
+			{
+				return false;
+			}
+			let remaining = self.skipRemaining.load(Ordering::Relaxed);
+			if remaining == 0
+			{
+				return true;
+			}
+			let length = usize::try_from(remaining).map_or(buffer.len(), |remaining| remaining.min(buffer.len()));
+			// Decode the next chunk, stopping if we hit the end of the file
+			match u64::try_from(self.audioFile.fillBuffer(&mut buffer[..length]))
+			{
+				Ok(0) | Err(_) =>
+				{
+					self.skipRemaining.store(0, Ordering::Relaxed);
+					return true;
+				},
+				Ok(decoded) => self.skipRemaining.store(remaining.saturating_sub(decoded), Ordering::Relaxed),
+			}
+		}
+	}
+
 	/// This is essentially compare-exchange - if we are already in the state
 	/// being requested, then this fails by returning false. Otherwise, the state
 	/// is atomically updated and we return true
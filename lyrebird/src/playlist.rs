@@ -1,17 +1,150 @@
 // SPDX-License-Identifier: BSD-3-Clause
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::fmt::{self, Display, Formatter};
 use std::path::{Path, PathBuf};
+use std::time::Duration;
 
+use ratatui::style::Style;
+use ratatui::text::{Line, Span};
 use ratatui::widgets::ListItem;
 use serde::{Deserialize, Serialize};
 
+/// How many edits to a playlist can be undone
+const UNDO_DEPTH: usize = 64;
+
 #[derive(Clone, Serialize, Deserialize)]
 pub struct Playlist
 {
 	name: String,
 	entries: Vec<PathBuf>,
 
+	currentEntry: usize,
+	/// How far into the current entry playback had got when it was last stopped
+	position: Duration,
+
+	#[serde(default)]
+	shuffle: ShuffleMode,
+	#[serde(default)]
+	repeat: RepeatMode,
+	/// The order in which to play the entries while shuffling, as indices into `entries`
+	#[serde(default)]
+	playOrder: Vec<usize>,
+
+	/// Set when the current entry is removed from the playlist while it's playing - `currentEntry` then
+	/// refers to the entry that followed it, which is what should play next rather than the one after
+	#[serde(skip)]
+	currentRemoved: bool,
+	/// How many entries have been inserted to play after the current one that haven't been reached yet, so that
+	/// further insertions go after them rather than jumping the queue
 	#[serde(skip)]
+	playNextCount: usize,
+	/// The states of the playlist prior to each edit made to it, most recent last
+	#[serde(skip)]
+	undoHistory: Vec<Snapshot>,
+	/// Count of changes made to the entries, so anything keeping track of them by position can tell they've moved
+	#[serde(skip)]
+	generation: u64,
+}
+
+/// How the entries of a playlist get shuffled, if at all
+#[derive(Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ShuffleMode
+{
+	/// Play the entries in the order they appear in the playlist
+	#[default]
+	Off,
+	/// Play every entry once each in a random order
+	Tracks,
+	/// Play albums in a random order, keeping the tracks of each album in playlist order. An album is taken to be
+	/// the entries that share a directory
+	Albums,
+}
+
+/// What happens when an entry, or the playlist as a whole, finishes playing
+#[derive(Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum RepeatMode
+{
+	/// Stop once the last entry has played
+	#[default]
+	Off,
+	/// Go back around to the start once the last entry has played
+	All,
+	/// Play the current entry over and over
+	One,
+}
+
+/// The state of a playlist's entries prior to an edit, so that the edit can be undone
+#[derive(Clone)]
+struct Snapshot
+{
+	entries: Vec<PathBuf>,
 	currentEntry: usize,
+	currentRemoved: bool,
+	shuffle: ShuffleMode,
+	playOrder: Vec<usize>,
+}
+
+impl ShuffleMode
+{
+	/// Get the mode that comes after this one when cycling through them
+	#[must_use]
+	pub const fn next(self) -> Self
+	{
+		match self
+		{
+			Self::Off => Self::Tracks,
+			Self::Tracks => Self::Albums,
+			Self::Albums => Self::Off,
+		}
+	}
+}
+
+impl Display for ShuffleMode
+{
+	fn fmt(&self, formatter: &mut Formatter<'_>) -> fmt::Result
+	{
+		formatter.write_str
+		(
+			match self
+			{
+				Self::Off => "in order",
+				Self::Tracks => "shuffle",
+				Self::Albums => "album shuffle",
+			}
+		)
+	}
+}
+
+impl RepeatMode
+{
+	/// Get the mode that comes after this one when cycling through them
+	#[must_use]
+	pub const fn next(self) -> Self
+	{
+		match self
+		{
+			Self::Off => Self::All,
+			Self::All => Self::One,
+			Self::One => Self::Off,
+		}
+	}
+}
+
+impl Display for RepeatMode
+{
+	fn fmt(&self, formatter: &mut Formatter<'_>) -> fmt::Result
+	{
+		formatter.write_str
+		(
+			match self
+			{
+				Self::Off => "no repeat",
+				Self::All => "repeat all",
+				Self::One => "repeat one",
+			}
+		)
+	}
 }
 
 impl Playlist
@@ -23,6 +156,14 @@ impl Playlist
 			name,
 			entries: Vec::new(),
 			currentEntry: 0,
+			position: Duration::ZERO,
+			shuffle: ShuffleMode::Off,
+			repeat: RepeatMode::Off,
+			playOrder: Vec::new(),
+			currentRemoved: false,
+			playNextCount: 0,
+			undoHistory: Vec::new(),
+			generation: 0,
 		}
 	}
 
@@ -31,36 +172,451 @@ impl Playlist
 		self.name.as_str()
 	}
 
+	pub fn rename(&mut self, name: String)
+	{
+		self.name = name;
+	}
+
 	pub fn add(&mut self, fileName: &Path)
 	{
 		self.entries.push(fileName.to_path_buf());
+		self.generation += 1;
+		// If we're shuffling, the new entry needs a place in the play order too
+		if self.shuffle != ShuffleMode::Off
+		{
+			self.insertIntoPlayOrder(self.entries.len() - 1);
+		}
 	}
 
-	pub fn replaceWith(&mut self, fileName: &Path)
+	/// Replace the contents of this playlist with the files given, starting back at the first entry to be played
+	pub fn replaceWith(&mut self, fileNames: &[PathBuf])
 	{
+		self.entries = fileNames.to_vec();
+		self.generation += 1;
+		self.currentRemoved = false;
+		self.position = Duration::ZERO;
+		self.generatePlayOrder(None);
+		self.currentEntry = self.entryAt(0).unwrap_or(0);
+	}
+
+	/// Replace the contents of this playlist with those of another, starting back at the first entry to be played
+	pub fn replaceWithContentsOf(&mut self, playlist: &Playlist)
+	{
+		self.replaceWith(&playlist.entries);
+	}
+
+	pub const fn shuffle(&self) -> ShuffleMode
+	{
+		self.shuffle
+	}
+
+	pub const fn repeat(&self) -> RepeatMode
+	{
+		self.repeat
+	}
+
+	/// Switch to the next shuffle mode, working out a new play order for it
+	pub fn cycleShuffle(&mut self)
+	{
+		self.shuffle = self.shuffle.next();
+		self.reshuffle();
+	}
+
+	/// Switch to the next repeat mode
+	pub const fn cycleRepeat(&mut self)
+	{
+		self.repeat = self.repeat.next();
+	}
+
+	/// Work out a new order to play the entries in, keeping the current entry first so it doesn't get played again
+	pub fn reshuffle(&mut self)
+	{
+		let current = (self.currentEntry < self.entries.len()).then_some(self.currentEntry);
+		self.generatePlayOrder(current);
+	}
+
+	/// Insert files to play straight after the current entry, returning the index of the first of them. Successive
+	/// insertions stack up in the order they're made, so the first inserted plays first
+	pub fn insertNext(&mut self, fileNames: &[PathBuf]) -> usize
+	{
+		let count = fileNames.len();
+		let current = if self.currentRemoved { self.currentEntry } else { self.currentEntry + 1 };
+		let index = (current + self.playNextCount).min(self.entries.len());
+		let position = (self.nextPosition() + self.playNextCount).min(self.playOrder.len());
+		let playNextCount = self.playNextCount;
+		self.saveUndoState();
+
+		self.entries.splice(index..index, fileNames.iter().cloned());
+		// Make room for the new entries in the play order, and slot them in after whatever else is to play next
+		if self.shuffle != ShuffleMode::Off
+		{
+			for entry in &mut self.playOrder
+			{
+				if *entry >= index
+				{
+					*entry += count;
+				}
+			}
+			self.playOrder.splice(position..position, index..index + count);
+		}
+
+		// If the current entry was removed, currentEntry is what plays next - which is now the first new entry if
+		// nothing else was already inserted ahead of it
+		if self.currentRemoved && playNextCount == 0
+		{
+			self.currentEntry = index;
+		}
+		else if self.currentEntry >= index
+		{
+			self.currentEntry += count;
+		}
+		self.playNextCount = playNextCount + count;
+		index
+	}
+
+	/// Build the play order for the current shuffle mode, putting the given entry (or its album) first if asked
+	fn generatePlayOrder(&mut self, first: Option<usize>)
+	{
+		self.playNextCount = 0;
+		self.playOrder = match self.shuffle
+		{
+			ShuffleMode::Off => Vec::new(),
+			ShuffleMode::Tracks =>
+			{
+				let mut order: Vec<usize> = (0..self.entries.len()).collect();
+				fastrand::shuffle(&mut order);
+				if let Some(position) = first.and_then(|first| order.iter().position(|&entry| entry == first))
+				{
+					order.swap(0, position);
+				}
+				order
+			},
+			ShuffleMode::Albums =>
+			{
+				// Group the entries up into albums by the directory they live in, keeping each in playlist order
+				let mut albums: Vec<Vec<usize>> = Vec::new();
+				let mut albumIndices = HashMap::new();
+				for (index, entry) in self.entries.iter().enumerate()
+				{
+					let album = *albumIndices.entry(entry.parent())
+						.or_insert_with
+						(
+							||
+							{
+								albums.push(Vec::new());
+								albums.len() - 1
+							}
+						);
+					albums[album].push(index);
+				}
+				// Now shuffle the albums themselves and flatten them back out into the play order
+				fastrand::shuffle(&mut albums);
+				if let Some(album) = first.and_then(|first| albums.iter().position(|album| album.contains(&first)))
+				{
+					albums.swap(0, album);
+				}
+				albums.into_iter().flatten().collect()
+			},
+		};
+	}
+
+	/// Give a newly added entry a place amongst the entries in the play order that are yet to be played
+	fn insertIntoPlayOrder(&mut self, index: usize)
+	{
+		let unplayed = if self.currentRemoved { self.currentPosition() } else { self.currentPosition() + 1 }
+			.min(self.playOrder.len());
+		let position = match self.shuffle
+		{
+			ShuffleMode::Off => return,
+			ShuffleMode::Tracks => fastrand::usize(unplayed..=self.playOrder.len()),
+			// Keep the entry with the rest of its album if that's still to be played, otherwise it goes on the end
+			ShuffleMode::Albums =>
+			{
+				let album = self.entries[index].parent();
+				self.playOrder[unplayed..]
+					.iter()
+					.rposition(|&entry| self.entries[entry].parent() == album)
+					.map_or(self.playOrder.len(), |position| unplayed + position + 1)
+			},
+		};
+		self.playOrder.insert(position, index);
+	}
+
+	/// Look up which entry is at the given position in the play order
+	fn entryAt(&self, position: usize) -> Option<usize>
+	{
+		match self.shuffle
+		{
+			ShuffleMode::Off => (position < self.entries.len()).then_some(position),
+			ShuffleMode::Tracks | ShuffleMode::Albums => self.playOrder.get(position).copied(),
+		}
+	}
+
+	/// Find where in the play order the current entry is. If we've run off the end, this is one past the end
+	fn currentPosition(&self) -> usize
+	{
+		match self.shuffle
+		{
+			ShuffleMode::Off => self.currentEntry,
+			ShuffleMode::Tracks | ShuffleMode::Albums => self.playOrder
+				.iter()
+				.position(|&entry| entry == self.currentEntry)
+				.unwrap_or(self.playOrder.len()),
+		}
+	}
+
+	/// Remove the entries at the given indices as one undoable step. If the current entry is amongst them,
+	/// whatever followed it will play next
+	pub fn remove(&mut self, indices: &[usize])
+	{
+		let mut indices: Vec<_> = indices.iter()
+			.copied()
+			.filter(|&index| index < self.entries.len())
+			.collect();
+		if indices.is_empty()
+		{
+			return;
+		}
+		indices.sort_unstable();
+		indices.dedup();
+
+		self.saveUndoState();
+		// Work from the back so the indices of those still to go aren't disturbed
+		for index in indices.into_iter().rev()
+		{
+			self.removeEntry(index);
+		}
+	}
+
+	fn removeEntry(&mut self, index: usize)
+	{
+		self.entries.remove(index);
+
+		// Take the entry out of the play order, noting where it was, and fix up the indices of those after it
+		let orderPosition = self.playOrder.iter().position(|&entry| entry == index);
+		if let Some(position) = orderPosition
+		{
+			self.playOrder.remove(position);
+		}
+		for entry in &mut self.playOrder
+		{
+			if *entry > index
+			{
+				*entry -= 1;
+			}
+		}
+
+		match index.cmp(&self.currentEntry)
+		{
+			Ordering::Less => self.currentEntry -= 1,
+			Ordering::Equal =>
+			{
+				self.currentRemoved = true;
+				self.position = Duration::ZERO;
+				// If we're shuffling, what plays next is whatever took the removed entry's place in the play order
+				if let Some(position) = orderPosition
+				{
+					self.currentEntry = self.playOrder.get(position).copied().unwrap_or(self.entries.len());
+				}
+			},
+			Ordering::Greater => {},
+		}
+	}
+
+	/// Move the entry at the given index up one place, returning its new index
+	pub fn moveUp(&mut self, index: usize) -> usize
+	{
+		match index.checked_sub(1)
+		{
+			Some(target) => self.moveEntry(index, target),
+			None => index,
+		}
+	}
+
+	/// Move the entry at the given index down one place, returning its new index
+	pub fn moveDown(&mut self, index: usize) -> usize
+	{
+		self.moveEntry(index, index + 1)
+	}
+
+	/// Move the entry at the given index to the top of the playlist, returning its new index
+	pub fn moveToTop(&mut self, index: usize) -> usize
+	{
+		self.moveEntry(index, 0)
+	}
+
+	/// Move an entry from one place in the playlist to another, keeping track of where the current entry
+	/// ends up so playback carries on from the right place. Returns the new index of the entry moved
+	fn moveEntry(&mut self, from: usize, to: usize) -> usize
+	{
+		if from >= self.entries.len() || to >= self.entries.len() || from == to
+		{
+			return from;
+		}
+		self.saveUndoState();
+		let entry = self.entries.remove(from);
+		self.entries.insert(to, entry);
+
+		self.currentEntry = movedIndex(self.currentEntry, from, to);
+		for entry in &mut self.playOrder
+		{
+			*entry = movedIndex(*entry, from, to);
+		}
+		to
+	}
+
+	/// Remove all the entries from the playlist
+	pub fn clear(&mut self)
+	{
+		if self.entries.is_empty()
+		{
+			return;
+		}
+		self.saveUndoState();
+		// If we were part way through the playlist, the current entry's been removed along with everything
+		// else, so whatever gets added next should be what plays next
+		self.currentRemoved = self.currentEntry < self.entries.len() || self.currentRemoved;
 		self.entries.clear();
+		self.playOrder.clear();
+		self.currentEntry = 0;
+	}
+
+	/// Remove every entry from the playlist except the current one
+	pub fn cropToCurrent(&mut self)
+	{
+		// If the current entry isn't actually in the playlist any more, that's the same as clearing it
+		if self.currentRemoved || self.currentEntry >= self.entries.len()
+		{
+			return self.clear();
+		}
+		if self.entries.len() == 1
+		{
+			return;
+		}
+		self.saveUndoState();
+		let current = self.entries.swap_remove(self.currentEntry);
+		self.entries = vec![current];
 		self.currentEntry = 0;
-		self.add(fileName);
+		if self.shuffle != ShuffleMode::Off
+		{
+			self.playOrder = vec![0];
+		}
+	}
+
+	/// Put the playlist back how it was before the last edit, returning whether there was an edit to undo
+	pub fn undo(&mut self) -> bool
+	{
+		let Some(snapshot) = self.undoHistory.pop()
+			else { return false; };
+
+		// Work out what's currently playing, and what was playing when the snapshot was taken
+		let playing = self.current().filter(|_| !self.currentRemoved).map(Path::to_path_buf);
+		let snapshotPlaying = snapshot.entries.get(snapshot.currentEntry).filter(|_| !snapshot.currentRemoved);
+
+		self.currentEntry = snapshot.currentEntry;
+		self.currentRemoved = snapshot.currentRemoved;
+		self.playNextCount = 0;
+		// If playback has moved on since the snapshot was taken, find where the entry now playing lives in
+		// the restored entries so that playback continues on from the right place
+		if let Some(playing) = playing
+		{
+			if snapshotPlaying != Some(&playing)
+			{
+				if let Some(index) = snapshot.entries.iter().position(|entry| entry == &playing)
+				{
+					self.currentEntry = index;
+					self.currentRemoved = false;
+				}
+			}
+		}
+		self.entries = snapshot.entries;
+		self.playOrder = snapshot.playOrder;
+		self.generation += 1;
+		// If the shuffle mode got changed since the snapshot was taken, its play order is no good any more
+		if snapshot.shuffle != self.shuffle
+		{
+			self.reshuffle();
+		}
+		true
+	}
+
+	/// Take a snapshot of the playlist's entries prior to an edit so the edit can be undone. Edits also mess with
+	/// where anything inserted to play next is, so forget about that
+	fn saveUndoState(&mut self)
+	{
+		self.playNextCount = 0;
+		self.generation += 1;
+		if self.undoHistory.len() == UNDO_DEPTH
+		{
+			self.undoHistory.remove(0);
+		}
+		self.undoHistory.push
+		(
+			Snapshot
+			{
+				entries: self.entries.clone(),
+				currentEntry: self.currentEntry,
+				currentRemoved: self.currentRemoved,
+				shuffle: self.shuffle,
+				playOrder: self.playOrder.clone(),
+			}
+		);
 	}
 
-	pub fn contents(&self) -> impl Iterator<Item = ListItem>
+	/// Build list items for the entries in the playlist. If a style is given for the current entry, the
+	/// current entry is marked out from the rest so it's clear where playback is up to
+	pub fn contents(&self, currentStyle: Option<Style>) -> impl Iterator<Item = ListItem>
 	{
+		// Work out which entry to mark as current, if any (if the current entry was removed, there isn't one)
+		let current = currentStyle
+			.filter(|_| !self.currentRemoved)
+			.map(|style| (self.currentEntry, style));
+
 		self.entries
 			.iter()
+			.enumerate()
 			.map
 			(
-				|fileName| ListItem::new(fileName.to_string_lossy())
+				move |(index, fileName)|
+				{
+					let fileName = fileName.to_string_lossy();
+					match current
+					{
+						Some((currentEntry, style)) if currentEntry == index =>
+							ListItem::new(Line::from(vec![Span::raw("▶ "), Span::raw(fileName)]).style(style)),
+						Some(_) => ListItem::new(Line::from(vec![Span::raw("  "), Span::raw(fileName)])),
+						None => ListItem::new(fileName),
+					}
+				}
 			)
 	}
 
+	/// Get how many changes have been made to the entries, which only ever goes up
+	pub const fn generation(&self) -> u64
+	{
+		self.generation
+	}
+
+	pub fn entryCount(&self) -> usize
+	{
+		self.entries.len()
+	}
+
 	pub fn entry(&self, index: usize) -> &Path
 	{
 		self.entries[index].as_path()
 	}
 
+	pub fn entries(&self) -> impl Iterator<Item = &Path>
+	{
+		self.entries.iter().map(PathBuf::as_path)
+	}
+
 	pub fn nextEntry(&mut self, index: usize)
 	{
 		self.currentEntry = index;
+		self.currentRemoved = false;
+		self.playNextCount = 0;
 	}
 
 	pub fn currentEntry(&self) -> usize
@@ -68,26 +624,388 @@ impl Playlist
 		self.currentEntry
 	}
 
+	/// Get the path to the current entry, if we've not already run off the end of the playlist
+	pub fn current(&self) -> Option<&Path>
+	{
+		self.entries.get(self.currentEntry).map(PathBuf::as_path)
+	}
+
+	pub fn position(&self) -> Duration
+	{
+		self.position
+	}
+
+	/// Note how far into the current entry playback has got. If the current entry was removed from the
+	/// playlist while playing, this is ignored as the position would then apply to the wrong entry
+	pub fn setPosition(&mut self, position: Duration)
+	{
+		if !self.currentRemoved
+		{
+			self.position = position;
+		}
+	}
+
+	/// Find out what entry skipping forward would move on to, without actually moving on to it
+	pub fn peek(&self) -> Option<&Path>
+	{
+		self.entryAt(self.nextPosition())
+			.or_else(|| self.entryAt(0).filter(|_| self.repeat == RepeatMode::All))
+			.map(|entry| self.entries[entry].as_path())
+	}
+
+	/// Move back to the entry prior to the current one in the play order, if there is one
+	pub fn previous(&mut self) -> Option<PathBuf>
+	{
+		// If the entry that was playing got removed, currentEntry refers to what came after it, so the prior
+		// entry is the one before that. Likewise if we've run off the end, the prior entry is the last one
+		let position = self.currentPosition().min(self.entries.len());
+		let previous = match position.checked_sub(1)
+		{
+			Some(previous) => previous,
+			// If we're at the start but repeating the whole playlist, go back around to the end
+			None if self.repeat == RepeatMode::All => self.entries.len().checked_sub(1)?,
+			None => return None,
+		};
+		self.currentEntry = self.entryAt(previous)?;
+		self.currentRemoved = false;
+		self.playNextCount = 0;
+		Some(self.entries[self.currentEntry].clone())
+	}
+
+	/// Move on to the entry that should play now the current one has finished playing
 	pub fn next(&mut self) -> Option<PathBuf>
 	{
+		self.advance(true)
+	}
+
+	/// Move on to the next entry at the user's request - unlike `next()`, this ignores repeating the current entry
+	pub fn skip(&mut self) -> Option<PathBuf>
+	{
+		self.advance(false)
+	}
+
+	/// Work out the position in the play order of the entry that comes after the current one
+	fn nextPosition(&self) -> usize
+	{
+		// If the entry that was playing got removed, currentEntry already refers to what comes next
+		if self.currentRemoved { self.currentPosition() } else { self.currentPosition() + 1 }
+	}
+
+	fn advance(&mut self, honourRepeatOne: bool) -> Option<PathBuf>
+	{
+		let nextPosition = self.nextPosition();
+		let currentRemoved = std::mem::take(&mut self.currentRemoved);
 		// If there are no entries in this playlist, we're done.. nothing comes next
 		if self.entries.is_empty()
 		{
 			return None;
 		}
-		// If there are entries, figure out how many vs currentEntry
-		let count = self.entries.len();
-		if self.currentEntry < count
+		// If we're repeating the current entry and it's still around, it's also what comes next
+		if honourRepeatOne && self.repeat == RepeatMode::One && !currentRemoved
 		{
-			// Increment the current entry counter if there's room to
-			self.currentEntry += 1;
+			if let Some(current) = self.current()
+			{
+				return Some(current.to_path_buf());
+			}
 		}
-		// Now check if we're done
-		if self.currentEntry >= count
+		match self.entryAt(nextPosition)
 		{
-			return None;
+			// Happy path - give them what they want, a new entry from the playlist!
+			Some(entry) =>
+			{
+				self.currentEntry = entry;
+				self.playNextCount = self.playNextCount.saturating_sub(1);
+			},
+			// We've run off the end, but we're repeating the playlist so go back around (in a new order if shuffling)
+			None if self.repeat == RepeatMode::All =>
+			{
+				self.generatePlayOrder(None);
+				self.currentEntry = self.entryAt(0)?;
+			},
+			// Otherwise we're done, so note that we've run off the end
+			None =>
+			{
+				self.currentEntry = self.entries.len();
+				return None;
+			},
 		}
-		// Finally, we get to the happy path - give them what they want, a new entry from the playlist!
 		Some(self.entries[self.currentEntry].clone())
 	}
 }
+
+/// Work out where the entry at `index` ends up when the entry at `from` is moved to `to`
+const fn movedIndex(index: usize, from: usize, to: usize) -> usize
+{
+	if index == from
+	{
+		to
+	}
+	else if from < index && to >= index
+	{
+		index - 1
+	}
+	else if from > index && to <= index
+	{
+		index + 1
+	}
+	else
+	{
+		index
+	}
+}
+
+#[cfg(test)]
+mod tests
+{
+	use std::path::{Path, PathBuf};
+
+	use super::{Playlist, ShuffleMode, UNDO_DEPTH};
+
+	fn playlist(fileNames: &[&str]) -> Playlist
+	{
+		let mut playlist = Playlist::new("test".into());
+		playlist.replaceWith(&paths(fileNames));
+		playlist
+	}
+
+	fn paths(fileNames: &[&str]) -> Vec<PathBuf>
+	{
+		fileNames.iter().map(PathBuf::from).collect()
+	}
+
+	fn entries(playlist: &Playlist) -> Vec<&Path>
+	{
+		playlist.entries().collect()
+	}
+
+	#[test]
+	fn undoRemove()
+	{
+		let mut playlist = playlist(&["a", "b", "c", "d"]);
+		playlist.remove(&[3, 1, 3]);
+		assert_eq!(entries(&playlist), paths(&["a", "c"]));
+		assert!(playlist.undo());
+		assert_eq!(entries(&playlist), paths(&["a", "b", "c", "d"]));
+		// Filling the playlist isn't an edit that can be undone
+		assert!(!playlist.undo());
+		assert_eq!(entries(&playlist), paths(&["a", "b", "c", "d"]));
+	}
+
+	#[test]
+	fn undoMoveAndClear()
+	{
+		let mut playlist = playlist(&["a", "b", "c"]);
+		assert_eq!(playlist.moveDown(0), 1);
+		assert_eq!(entries(&playlist), paths(&["b", "a", "c"]));
+		assert_eq!(playlist.currentEntry(), 1);
+		playlist.clear();
+		assert_eq!(playlist.entryCount(), 0);
+
+		assert!(playlist.undo());
+		assert_eq!(entries(&playlist), paths(&["b", "a", "c"]));
+		assert_eq!(playlist.current(), Some(Path::new("a")));
+		assert!(playlist.undo());
+		assert_eq!(entries(&playlist), paths(&["a", "b", "c"]));
+		assert_eq!(playlist.current(), Some(Path::new("a")));
+	}
+
+	#[test]
+	fn undoKeepsPlaybackWhereItIs()
+	{
+		// Remove what's playing, let playback move on, and then undo - what's now playing should carry on
+		let mut playlist = playlist(&["a", "b", "c"]);
+		playlist.remove(&[0]);
+		assert_eq!(playlist.next(), Some(PathBuf::from("b")));
+		assert!(playlist.undo());
+		assert_eq!(entries(&playlist), paths(&["a", "b", "c"]));
+		assert_eq!(playlist.current(), Some(Path::new("b")));
+		assert_eq!(playlist.next(), Some(PathBuf::from("c")));
+	}
+
+	#[test]
+	fn undoChangesGeneration()
+	{
+		let mut playlist = playlist(&["a", "b"]);
+		let generation = playlist.generation();
+		playlist.remove(&[1]);
+		assert!(playlist.generation() > generation);
+		let generation = playlist.generation();
+		assert!(playlist.undo());
+		assert!(playlist.generation() > generation);
+	}
+
+	#[test]
+	fn undoDepthIsLimited()
+	{
+		let mut playlist = playlist(&["a", "b"]);
+		for _ in 0..UNDO_DEPTH + 10
+		{
+			playlist.moveDown(0);
+		}
+		let undone = std::iter::from_fn(|| playlist.undo().then_some(())).count();
+		assert_eq!(undone, UNDO_DEPTH);
+	}
+
+	/// Play through the rest of the playlist from the current entry, returning everything played in order
+	fn playThrough(playlist: &mut Playlist) -> Vec<PathBuf>
+	{
+		let first = playlist.current().map(Path::to_path_buf);
+		first.into_iter().chain(std::iter::from_fn(|| playlist.next())).collect()
+	}
+
+	#[test]
+	fn shuffleTracks()
+	{
+		let fileNames: Vec<_> = (0..20).map(|index| format!("{index}.mp3")).collect();
+		let fileNames: Vec<_> = fileNames.iter().map(String::as_str).collect();
+		let mut playlist = playlist(&fileNames);
+		playlist.nextEntry(5);
+		playlist.cycleShuffle();
+		assert!(playlist.shuffle() == ShuffleMode::Tracks);
+
+		// What's playing carries on playing, and then every other entry gets played once each
+		let mut played = playThrough(&mut playlist);
+		assert_eq!(played[0], PathBuf::from("5.mp3"));
+		played.sort();
+		let mut expected = paths(&fileNames);
+		expected.sort();
+		assert_eq!(played, expected);
+	}
+
+	#[test]
+	fn shuffleAlbums()
+	{
+		let mut playlist = playlist(&["A/1", "B/1", "A/2", "C/1", "B/2", "A/3", "C/2"]);
+		playlist.cycleShuffle();
+		playlist.cycleShuffle();
+		assert!(playlist.shuffle() == ShuffleMode::Albums);
+
+		// The album of the entry playing comes first, and each album's tracks play together in playlist order
+		let played = playThrough(&mut playlist);
+		assert_eq!(played.len(), 7);
+		assert_eq!(played[..3], paths(&["A/1", "A/2", "A/3"]));
+		let rest: Vec<_> = played[3..].iter().map(|path| path.to_string_lossy().into_owned()).collect();
+		assert!
+		(
+			rest == ["B/1", "B/2", "C/1", "C/2"] || rest == ["C/1", "C/2", "B/1", "B/2"],
+			"albums got split up: {rest:?}"
+		);
+	}
+
+	#[test]
+	fn shuffleOffPlaysInOrder()
+	{
+		let mut playlist = playlist(&["a", "b", "c", "d"]);
+		playlist.nextEntry(1);
+		playlist.cycleShuffle();
+		playlist.cycleShuffle();
+		playlist.cycleShuffle();
+		assert!(playlist.shuffle() == ShuffleMode::Off);
+		assert_eq!(playThrough(&mut playlist), paths(&["b", "c", "d"]));
+	}
+
+	#[test]
+	fn shuffleIncludesNewEntries()
+	{
+		let mut playlist = playlist(&["a", "b", "c"]);
+		playlist.cycleShuffle();
+		playlist.add(Path::new("d"));
+		let mut played = playThrough(&mut playlist);
+		played.sort();
+		assert_eq!(played, paths(&["a", "b", "c", "d"]));
+	}
+
+	#[test]
+	fn shufflePrevious()
+	{
+		let mut playlist = playlist(&["a", "b", "c", "d", "e"]);
+		playlist.cycleShuffle();
+		let first = playlist.current().map(Path::to_path_buf);
+		let second = playlist.next();
+		playlist.next();
+		// Going back follows the play order, not the order of the playlist
+		assert_eq!(playlist.previous(), second);
+		assert_eq!(playlist.previous(), first);
+		assert_eq!(playlist.previous(), None);
+	}
+
+	#[test]
+	fn shuffleRemoveCurrent()
+	{
+		let mut playlist = playlist(&["a", "b", "c", "d", "e"]);
+		playlist.cycleShuffle();
+		let played = playThrough(&mut playlist.clone());
+
+		// Removing what's playing means whatever was to play after it plays next
+		let current = playlist.currentEntry();
+		playlist.remove(&[current]);
+		assert_eq!(playlist.next().as_ref(), played.get(1));
+	}
+
+	#[test]
+	fn insertNextStacksUp()
+	{
+		let mut playlist = playlist(&["a", "b", "c"]);
+		assert_eq!(playlist.insertNext(&paths(&["x"])), 1);
+		assert_eq!(playlist.insertNext(&paths(&["y", "z"])), 2);
+		assert_eq!(entries(&playlist), paths(&["a", "x", "y", "z", "b", "c"]));
+		assert_eq!(playlist.current(), Some(Path::new("a")));
+		assert_eq!(playThrough(&mut playlist), paths(&["a", "x", "y", "z", "b", "c"]));
+	}
+
+	#[test]
+	fn insertNextAfterPlayingInserted()
+	{
+		// Once playback reaches the inserted entries, more get inserted after what's now playing
+		let mut playlist = playlist(&["a", "b", "c"]);
+		playlist.insertNext(&paths(&["x", "y"]));
+		assert_eq!(playlist.next(), Some(PathBuf::from("x")));
+		assert_eq!(playlist.insertNext(&paths(&["z"])), 3);
+		assert_eq!(playThrough(&mut playlist), paths(&["x", "y", "z", "b", "c"]));
+	}
+
+	#[test]
+	fn insertNextAfterRemovingCurrent()
+	{
+		let mut playlist = playlist(&["a", "b", "c"]);
+		playlist.remove(&[0]);
+		assert_eq!(playlist.insertNext(&paths(&["x"])), 0);
+		assert_eq!(playlist.insertNext(&paths(&["y"])), 1);
+		assert_eq!(entries(&playlist), paths(&["x", "y", "b", "c"]));
+		assert_eq!(playlist.next(), Some(PathBuf::from("x")));
+		assert_eq!(playlist.next(), Some(PathBuf::from("y")));
+		assert_eq!(playlist.next(), Some(PathBuf::from("b")));
+	}
+
+	#[test]
+	fn insertNextWhileShuffling()
+	{
+		let mut playlist = playlist(&["a", "b", "c", "d", "e"]);
+		playlist.cycleShuffle();
+		let current = playlist.current().map(Path::to_path_buf);
+		playlist.insertNext(&paths(&["x", "y"]));
+		assert_eq!(playlist.current().map(Path::to_path_buf), current);
+		assert_eq!(playlist.next(), Some(PathBuf::from("x")));
+		assert_eq!(playlist.next(), Some(PathBuf::from("y")));
+
+		// And then the rest of the playlist still plays once each
+		let mut played = playThrough(&mut playlist);
+		played.sort();
+		let mut expected = paths(&["a", "b", "c", "d", "e", "y"]);
+		expected.retain(|path| Some(path) != current.as_ref());
+		assert_eq!(played, expected);
+	}
+
+	#[test]
+	fn undoInsertNext()
+	{
+		let mut playlist = playlist(&["a", "b", "c"]);
+		playlist.nextEntry(1);
+		playlist.insertNext(&paths(&["x"]));
+		assert!(playlist.undo());
+		assert_eq!(entries(&playlist), paths(&["a", "b", "c"]));
+		assert_eq!(playlist.current(), Some(Path::new("b")));
+		// Nothing's waiting to play next any more, so this goes straight after the current entry
+		assert_eq!(playlist.insertNext(&paths(&["y"])), 2);
+	}
+}
@@ -0,0 +1,174 @@
+// SPDX-License-Identifier: BSD-3-Clause
+use std::fmt::Write;
+use std::time::Duration;
+
+use super::{entryPath, PlaylistContents, PlaylistEntry};
+
+/// Parse the text of an M3U playlist into its entries. Extended M3U `#EXTINF` lines give the duration and title of
+/// the entry that follows them, and `#PLAYLIST` gives the title of the playlist - any other `#` lines are comments
+/// or directives we've no use for, so get skipped
+pub fn read(text: &str) -> PlaylistContents
+{
+	let mut contents = PlaylistContents::default();
+	let mut info = None;
+	for line in text.lines()
+	{
+		let line = line.trim();
+		if line.is_empty()
+		{
+			continue;
+		}
+		if let Some(extInf) = line.strip_prefix("#EXTINF:")
+		{
+			info = Some(parseExtInf(extInf));
+			continue;
+		}
+		if let Some(title) = line.strip_prefix("#PLAYLIST:")
+		{
+			contents.title = Some(title.trim().to_string());
+			continue;
+		}
+		if line.starts_with('#')
+		{
+			continue;
+		}
+
+		// This is an entry, so grab any info that was given for it and work out where it lives
+		let info = info.take().unwrap_or_default();
+		if let Some(path) = entryPath(line)
+		{
+			contents.entries.push(PlaylistEntry { path, ..info });
+		}
+	}
+	contents
+}
+
+/// Write out the contents given as the text of an extended M3U playlist
+pub fn write(contents: &PlaylistContents) -> String
+{
+	let mut text = String::from("#EXTM3U\n");
+	// Writing to a String can't fail
+	if let Some(title) = &contents.title
+	{
+		let _ = writeln!(text, "#PLAYLIST:{title}");
+	}
+	for entry in &contents.entries
+	{
+		// Durations are given in whole seconds, with -1 meaning the duration isn't known
+		let duration = entry.duration
+			.and_then(|duration| i64::try_from(duration.as_secs()).ok())
+			.unwrap_or(-1);
+		let _ = writeln!(text, "#EXTINF:{duration},{}", entry.displayName());
+		let _ = writeln!(text, "{}", entry.path.display());
+	}
+	text
+}
+
+/// Parse the part of an `#EXTINF` line after the tag, which is the duration in seconds (possibly followed by some
+/// attributes) then a comma and the name to display for the entry
+fn parseExtInf(extInf: &str) -> PlaylistEntry
+{
+	let (duration, name) = extInf.split_once(',').unwrap_or((extInf, ""));
+	let mut entry = PlaylistEntry
+	{
+		duration: duration.split_whitespace()
+			.next()
+			.and_then(|duration| duration.parse::<f64>().ok())
+			// Durations too long to represent (or not numbers at all) are as good as not having one
+			.filter(|duration| *duration > 0.0)
+			.and_then(|duration| Duration::try_from_secs_f64(duration).ok()),
+		..PlaylistEntry::default()
+	};
+	entry.setDisplayName(name);
+	entry
+}
+
+#[cfg(test)]
+mod tests
+{
+	use std::path::PathBuf;
+	use std::time::Duration;
+
+	use super::{read, write};
+	use crate::playlistFile::{PlaylistContents, PlaylistEntry};
+
+	#[test]
+	fn readExtended()
+	{
+		let contents = read
+		(
+			"#EXTM3U\n#PLAYLIST:Road Trip\n#EXTINF:123,Daft Punk - One More Time\nDiscovery/01 One More Time.flac\n\
+			# A comment\n#EXTINF:-1,Untitled\n  Discovery/02 Aerodynamic.flac  \n\n"
+		);
+		assert_eq!(contents.title.as_deref(), Some("Road Trip"));
+		assert_eq!(contents.entries.len(), 2);
+
+		let first = &contents.entries[0];
+		assert_eq!(first.path, PathBuf::from("Discovery/01 One More Time.flac"));
+		assert_eq!(first.artist.as_deref(), Some("Daft Punk"));
+		assert_eq!(first.title.as_deref(), Some("One More Time"));
+		assert_eq!(first.duration, Some(Duration::from_secs(123)));
+
+		// An unknown duration gives none, and the name without an artist is all title
+		let second = &contents.entries[1];
+		assert_eq!(second.path, PathBuf::from("Discovery/02 Aerodynamic.flac"));
+		assert_eq!(second.artist, None);
+		assert_eq!(second.title.as_deref(), Some("Untitled"));
+		assert_eq!(second.duration, None);
+	}
+
+	#[test]
+	fn readPlain()
+	{
+		// Info only goes with the entry straight after it, and streams aren't something we can play
+		let contents = read("a.mp3\r\nhttp://radio.example.com/stream\r\n#EXTINF:10,Title\r\nb.mp3\r\nc.mp3\r\n");
+		let paths: Vec<_> = contents.entries.iter().map(|entry| entry.path.clone()).collect();
+		assert_eq!(paths, [PathBuf::from("a.mp3"), PathBuf::from("b.mp3"), PathBuf::from("c.mp3")]);
+		assert_eq!(contents.entries[1].title.as_deref(), Some("Title"));
+		assert_eq!(contents.entries[2].title, None);
+		assert_eq!(contents.title, None);
+	}
+
+	#[test]
+	fn readBadDurations()
+	{
+		let contents = read("#EXTINF:1e300,Forever\na.mp3\n#EXTINF:soon,Whenever\nb.mp3\n#EXTINF:4.5 tvg-id=\"x\",Short\nc.mp3\n");
+		assert_eq!(contents.entries[0].duration, None);
+		assert_eq!(contents.entries[1].duration, None);
+		assert_eq!(contents.entries[2].duration, Some(Duration::from_millis(4500)));
+	}
+
+	#[test]
+	fn writeExtended()
+	{
+		let contents = PlaylistContents
+		{
+			title: Some("Road Trip".into()),
+			entries: vec!
+			[
+				PlaylistEntry
+				{
+					path: "Discovery/01 One More Time.flac".into(),
+					title: Some("One More Time".into()),
+					artist: Some("Daft Punk".into()),
+					duration: Some(Duration::from_millis(320_500)),
+					..PlaylistEntry::default()
+				},
+				PlaylistEntry { path: "/music/Unknown.mp3".into(), ..PlaylistEntry::default() },
+			],
+		};
+		assert_eq!
+		(
+			write(&contents),
+			"#EXTM3U\n#PLAYLIST:Road Trip\n#EXTINF:320,Daft Punk - One More Time\nDiscovery/01 One More Time.flac\n\
+			#EXTINF:-1,Unknown\n/music/Unknown.mp3\n"
+		);
+
+		// And what's written reads back in the same
+		let reread = read(&write(&contents));
+		assert_eq!(reread.title, contents.title);
+		assert_eq!(reread.entries[0].title, contents.entries[0].title);
+		assert_eq!(reread.entries[0].duration, Some(Duration::from_secs(320)));
+		assert_eq!(reread.entries[1].path, contents.entries[1].path);
+	}
+}
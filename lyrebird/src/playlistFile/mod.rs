@@ -0,0 +1,525 @@
+// SPDX-License-Identifier: BSD-3-Clause
+use std::ffi::OsStr;
+use std::fs;
+use std::iter;
+use std::path::{absolute, Component, Path, PathBuf};
+use std::time::Duration;
+
+use color_eyre::eyre::{eyre, Result};
+use percent_encoding::percent_decode_str;
+
+use crate::library::MusicLibrary;
+use crate::metadata::TrackMetadata;
+use crate::playlist::Playlist;
+
+mod m3u;
+mod pls;
+mod xspf;
+
+/// How far apart the durations of a playlist entry and a file in the library can be for them to still match
+const DURATION_TOLERANCE: Duration = Duration::from_secs(2);
+
+/// An entry read from, or to be written to, a playlist file along with whatever metadata the format carries for it
+#[derive(Default)]
+pub struct PlaylistEntry
+{
+	/// Path to the entry's file, exactly as it's written in the playlist file
+	pub path: PathBuf,
+	pub title: Option<String>,
+	pub artist: Option<String>,
+	pub album: Option<String>,
+	pub duration: Option<Duration>,
+}
+
+/// The contents of a playlist file - its entries, and the title of the playlist if the format has one
+#[derive(Default)]
+pub struct PlaylistContents
+{
+	pub title: Option<String>,
+	pub entries: Vec<PlaylistEntry>,
+}
+
+/// The playlist file formats that can be imported and exported
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum PlaylistFormat
+{
+	/// Plain or extended M3U, which may be in Latin-1 for files written by older players
+	M3U,
+	/// Extended M3U that's always UTF-8
+	M3U8,
+	/// XML Shareable Playlist Format
+	XSPF,
+	/// Shoutcast/Winamp style INI playlists
+	PLS,
+}
+
+/// How the paths to entries are to be written out when exporting a playlist
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum PathStyle
+{
+	/// Relative to the directory the playlist file is in, where possible
+	Relative,
+	Absolute,
+}
+
+impl PlaylistFormat
+{
+	/// Figure out which format a playlist file is in from its extension
+	pub fn fromPath(path: &Path) -> Result<Self>
+	{
+		let extension = path.extension()
+			.and_then(OsStr::to_str)
+			.map(str::to_ascii_lowercase);
+		match extension.as_deref()
+		{
+			Some("m3u") => Ok(Self::M3U),
+			Some("m3u8") => Ok(Self::M3U8),
+			Some("xspf") => Ok(Self::XSPF),
+			Some("pls") => Ok(Self::PLS),
+			_ => Err(eyre!("Unrecognised playlist format for {}", path.display())),
+		}
+	}
+}
+
+impl PlaylistEntry
+{
+	/// Build an entry for a file, using whatever metadata the library has for it
+	fn describe(metadata: Option<&TrackMetadata>, path: PathBuf) -> Self
+	{
+		Self
+		{
+			path,
+			title: metadata.and_then(|metadata| metadata.title.clone()),
+			artist: metadata.and_then(|metadata| metadata.artist.clone()),
+			album: metadata.and_then(|metadata| metadata.album.clone()),
+			duration: metadata.and_then(|metadata| metadata.duration),
+		}
+	}
+
+	/// Build the name to display for this entry, falling back on the file name if there's no title for it
+	fn displayName(&self) -> String
+	{
+		match (&self.artist, &self.title)
+		{
+			(Some(artist), Some(title)) => format!("{artist} - {title}"),
+			(None, Some(title)) => title.clone(),
+			_ => self.path
+				.file_stem()
+				.map(|name| name.to_string_lossy().into_owned())
+				.unwrap_or_default(),
+		}
+	}
+
+	/// Fill in the artist and title of this entry from a display name, which is typically "Artist - Title"
+	fn setDisplayName(&mut self, name: &str)
+	{
+		let name = name.trim();
+		match name.split_once(" - ")
+		{
+			Some((artist, title)) =>
+			{
+				self.artist = Some(artist.trim().to_string());
+				self.title = Some(title.trim().to_string());
+			},
+			None if name.is_empty() => {},
+			None => self.title = Some(name.to_string()),
+		}
+	}
+}
+
+/// Read a playlist file in, naming the resulting playlist after its title or the file. Relative paths in the file
+/// are taken as being relative to the directory the playlist file is in, and any entries that aren't where the
+/// playlist says are looked for in the library if one is given
+pub fn importPlaylist(fileName: &Path, library: Option<&MusicLibrary>) -> Result<Playlist>
+{
+	let format = PlaylistFormat::fromPath(fileName)?;
+	let text = decodeText(&fs::read(fileName)?);
+	let contents = match format
+	{
+		PlaylistFormat::M3U | PlaylistFormat::M3U8 => m3u::read(&text),
+		PlaylistFormat::XSPF => xspf::read(&text)?,
+		PlaylistFormat::PLS => pls::read(&text),
+	};
+
+	// Now build a playlist from the entries, resolving where each lives
+	let fileName = absolute(fileName)?;
+	let baseDir = fileName.parent().unwrap_or(Path::new(""));
+	let name = contents.title
+		.filter(|title| !title.trim().is_empty())
+		.or_else(|| fileName.file_stem().map(|name| name.to_string_lossy().into_owned()))
+		.unwrap_or_else(|| "Imported playlist".to_string());
+	let mut playlist = Playlist::new(name);
+	for entry in contents.entries
+	{
+		let path = normalisePath(&baseDir.join(&entry.path));
+		if path.exists()
+		{
+			playlist.add(&path);
+			continue;
+		}
+		// The entry isn't where the playlist says it is, so see if the library has it somewhere else, keeping
+		// the path as-is if not so the user can see what's missing
+		let found = library.and_then(|library| findInLibrary(library, &path, &entry));
+		playlist.add(found.as_deref().unwrap_or(&path));
+	}
+	Ok(playlist)
+}
+
+/// Write a playlist out to a file, in the format indicated by the file's extension, taking the metadata written
+/// for each entry from what the library knows of it
+pub fn exportPlaylist(playlist: &Playlist, fileName: &Path, style: PathStyle, library: &MusicLibrary) -> Result<()>
+{
+	let format = PlaylistFormat::fromPath(fileName)?;
+	let fileName = absolute(fileName)?;
+	let baseDir = fileName.parent().unwrap_or(Path::new(""));
+
+	// Build entries for everything in the playlist, working out how to refer to each from the playlist file
+	let contents = PlaylistContents
+	{
+		title: Some(playlist.name().to_string()),
+		entries: playlist.entries()
+			.map
+			(
+				|entry|
+				{
+					let path = match style
+					{
+						PathStyle::Relative => relativePath(baseDir, entry),
+						PathStyle::Absolute => entry.to_path_buf(),
+					};
+					PlaylistEntry::describe(library.trackMetadata(entry), path)
+				}
+			)
+			.collect(),
+	};
+
+	let data = match format
+	{
+		PlaylistFormat::M3U | PlaylistFormat::M3U8 => m3u::write(&contents),
+		PlaylistFormat::XSPF => xspf::write(&contents)?,
+		PlaylistFormat::PLS => pls::write(&contents),
+	};
+	fs::write(fileName, data)?;
+	Ok(())
+}
+
+/// Try to find the file an entry refers to in the library, for when it's not at the path the playlist gives (eg,
+/// the playlist was written on another machine with the music mounted somewhere else, or the files have since been
+/// renamed). Every file in the library is scored against the entry using the metadata the library has for it, and
+/// whichever matches best is picked
+fn findInLibrary(library: &MusicLibrary, path: &Path, entry: &PlaylistEntry) -> Option<PathBuf>
+{
+	let wanted = WantedEntry::new(path, entry);
+	library.allFiles()
+		.filter_map(|candidate| Some((wanted.score(candidate, library.trackMetadata(candidate))?, candidate)))
+		.max_by_key(|(score, _)| *score)
+		.map(|(_, candidate)| candidate.to_path_buf())
+}
+
+/// What's known about a playlist entry being looked for in the library, lowercased once up front so each file in
+/// the library can be compared against it cheaply
+struct WantedEntry<'a>
+{
+	path: &'a Path,
+	fileName: Option<String>,
+	title: Option<String>,
+	artist: Option<String>,
+	album: Option<String>,
+	duration: Option<Duration>,
+}
+
+impl<'a> WantedEntry<'a>
+{
+	fn new(path: &'a Path, entry: &PlaylistEntry) -> Self
+	{
+		let lowercase = |text: &Option<String>| text.as_deref().map(str::to_lowercase);
+		Self
+		{
+			path,
+			fileName: path.file_name().map(|name| name.to_string_lossy().to_lowercase()),
+			title: lowercase(&entry.title),
+			artist: lowercase(&entry.artist),
+			album: lowercase(&entry.album),
+			duration: entry.duration,
+		}
+	}
+
+	/// Score how well a file from the library matches the entry, the higher the better. Files whose metadata
+	/// contradicts the entry's don't match at all, and nor do those with neither the entry's file name nor its title
+	/// as there's nothing to say they're the same track
+	fn score(&self, candidate: &Path, metadata: Option<&TrackMetadata>) -> Option<usize>
+	{
+		let mut score = 0;
+		let mut identified = false;
+
+		let candidateName = candidate.file_name().map(|name| name.to_string_lossy().to_lowercase());
+		if self.fileName.is_some() && candidateName == self.fileName
+		{
+			score += 2;
+			identified = true;
+		}
+
+		let title = metadata.and_then(|metadata| metadata.title.as_deref());
+		let artist = metadata.and_then(|metadata| metadata.artist.as_deref());
+		let album = metadata.and_then(|metadata| metadata.album.as_deref());
+		for (expected, actual, weight) in [(&self.title, title, 2), (&self.artist, artist, 1), (&self.album, album, 1)]
+		{
+			match (expected, actual)
+			{
+				(Some(expected), Some(actual)) if *expected == actual.to_lowercase() => score += weight,
+				(Some(_), Some(_)) => return None,
+				_ => {},
+			}
+		}
+		match (&self.title, title)
+		{
+			// If both have a title, it's only got this far if they're the same
+			(Some(_), Some(_)) => identified = true,
+			// Files that have no title of their own can still be picked out by their names
+			(Some(expected), None) =>
+			{
+				let stem = candidate.file_stem().map(|stem| stem.to_string_lossy().to_lowercase());
+				if stem.is_some_and(|stem| stem.contains(expected.as_str()))
+				{
+					score += 1;
+					identified = true;
+				}
+			},
+			_ => {},
+		}
+		if !identified
+		{
+			return None;
+		}
+
+		if let (Some(expected), Some(actual)) = (self.duration, metadata.and_then(|metadata| metadata.duration))
+		{
+			if expected.abs_diff(actual) > DURATION_TOLERANCE
+			{
+				return None;
+			}
+			score += 1;
+		}
+
+		// Count how many of the directories the entry is meant to be in match up with the candidate's
+		if let (Some(candidateDir), Some(dir)) = (candidate.parent(), self.path.parent())
+		{
+			score += candidateDir.components().rev()
+				.zip(dir.components().rev())
+				.take_while(|(candidateComponent, component)| candidateComponent == component)
+				.count();
+		}
+		Some(score)
+	}
+}
+
+/// Turn the raw contents of a playlist file into text. Anything that's valid UTF-8 (with or without a BOM) is taken
+/// as such, otherwise the file is assumed to be Latin-1 as older players wrote plain M3U and PLS files in that
+fn decodeText(data: &[u8]) -> String
+{
+	let data = data.strip_prefix(b"\xEF\xBB\xBF").unwrap_or(data);
+	match std::str::from_utf8(data)
+	{
+		Ok(text) => text.to_string(),
+		Err(_) => data.iter().copied().map(char::from).collect(),
+	}
+}
+
+/// Work out the path to an entry from how it's written in a playlist. `file://` URLs are turned back into paths,
+/// but any other kind of URL (eg, an internet radio stream) isn't something we can play so gives nothing
+fn entryPath(location: &str) -> Option<PathBuf>
+{
+	if let Some(path) = location.strip_prefix("file://")
+	{
+		// Drop the host part of the URL, which is usually empty
+		let path = &path[path.find('/')?..];
+		return Some(PathBuf::from(percent_decode_str(path).decode_utf8_lossy().into_owned()));
+	}
+	if location.contains("://")
+	{
+		return None;
+	}
+	// Playlists written on Windows use backslashes to separate path components, so turn those into something
+	// that works here
+	if cfg!(not(windows))
+	{
+		return Some(PathBuf::from(location.replace('\\', "/")));
+	}
+	Some(PathBuf::from(location))
+}
+
+/// Tidy up a path by taking out any `.` and `..` components in it, so entries match up with what's in the library
+fn normalisePath(path: &Path) -> PathBuf
+{
+	let mut normalised = PathBuf::new();
+	for component in path.components()
+	{
+		match component
+		{
+			Component::CurDir => {},
+			// Going up out of a normal component cancels it out, but going up above the root does nothing
+			Component::ParentDir if matches!(normalised.components().next_back(), Some(Component::Normal(_))) =>
+				{ normalised.pop(); },
+			Component::ParentDir if normalised.has_root() => {},
+			component => normalised.push(component),
+		}
+	}
+	normalised
+}
+
+/// Work out how to refer to a file from the given directory. If the two have nothing in common (eg, they're on
+/// different drives), there's no relative way to do so and the file's path is used as-is
+fn relativePath(baseDir: &Path, fileName: &Path) -> PathBuf
+{
+	let mut base = baseDir.components().peekable();
+	let mut target = fileName.components().peekable();
+	// Skip over all the leading components the two have in common
+	let mut common = 0;
+	while let (Some(baseComponent), Some(targetComponent)) = (base.peek(), target.peek())
+	{
+		if baseComponent != targetComponent
+		{
+			break;
+		}
+		base.next();
+		target.next();
+		common += 1;
+	}
+	if common == 0
+	{
+		return fileName.to_path_buf();
+	}
+	// Go up out of what's left of the base directory, then down into what's left of the file's path
+	iter::repeat_n(Component::ParentDir, base.count())
+		.chain(target)
+		.collect()
+}
+
+#[cfg(test)]
+mod tests
+{
+	use std::path::{Path, PathBuf};
+	use std::time::Duration;
+
+	use super::{decodeText, entryPath, normalisePath, relativePath, PlaylistEntry, WantedEntry};
+	use crate::metadata::TrackMetadata;
+
+	fn metadata(title: Option<&str>, artist: Option<&str>, duration: Option<u64>) -> TrackMetadata
+	{
+		TrackMetadata
+		{
+			title: title.map(String::from),
+			artist: artist.map(String::from),
+			duration: duration.map(Duration::from_secs),
+			..TrackMetadata::default()
+		}
+	}
+
+	#[test]
+	fn decodeUTF8AndLatin1()
+	{
+		assert_eq!(decodeText("Café\n".as_bytes()), "Café\n");
+		// The byte order mark isn't part of the text
+		assert_eq!(decodeText(b"\xEF\xBB\xBFCaf\xC3\xA9"), "Café");
+		// Anything that isn't UTF-8 is taken to be Latin-1
+		assert_eq!(decodeText(b"Caf\xE9 \xFCber"), "Café über");
+		assert_eq!(decodeText(b"\xEF\xBB\xBFCaf\xE9"), "Café");
+	}
+
+	#[test]
+	fn normalise()
+	{
+		assert_eq!(normalisePath(Path::new("/music/./a/../b/c.mp3")), PathBuf::from("/music/b/c.mp3"));
+		assert_eq!(normalisePath(Path::new("/music/a/b/../../c.mp3")), PathBuf::from("/music/c.mp3"));
+		// Going up above the root stays at the root
+		assert_eq!(normalisePath(Path::new("/music/../../../c.mp3")), PathBuf::from("/c.mp3"));
+		// But a relative path can't know what's above where it starts, so keeps going up
+		assert_eq!(normalisePath(Path::new("a/../../b/c.mp3")), PathBuf::from("../b/c.mp3"));
+		assert_eq!(normalisePath(Path::new("./c.mp3")), PathBuf::from("c.mp3"));
+	}
+
+	#[test]
+	fn relative()
+	{
+		assert_eq!(relativePath(Path::new("/music"), Path::new("/music/a/b.mp3")), PathBuf::from("a/b.mp3"));
+		assert_eq!
+		(
+			relativePath(Path::new("/music/playlists"), Path::new("/music/albums/b.mp3")),
+			PathBuf::from("../albums/b.mp3")
+		);
+		assert_eq!(relativePath(Path::new("/home/me/lists"), Path::new("/srv/b.mp3")), PathBuf::from("../../../srv/b.mp3"));
+		// With nothing at all in common there's no way there from the directory, so the path stays as it is
+		assert_eq!(relativePath(Path::new("lists"), Path::new("/music/b.mp3")), PathBuf::from("/music/b.mp3"));
+	}
+
+	#[cfg(windows)]
+	#[test]
+	fn relativeAcrossDrives()
+	{
+		assert_eq!
+		(
+			relativePath(Path::new(r"C:\Users\me\Music"), Path::new(r"D:\Music\b.mp3")),
+			PathBuf::from(r"D:\Music\b.mp3")
+		);
+		assert_eq!
+		(
+			relativePath(Path::new(r"C:\Users\me\Music"), Path::new(r"C:\Users\me\Music\a\b.mp3")),
+			PathBuf::from(r"a\b.mp3")
+		);
+	}
+
+	#[test]
+	fn entryPaths()
+	{
+		assert_eq!(entryPath("/music/a b.mp3"), Some(PathBuf::from("/music/a b.mp3")));
+		assert_eq!(entryPath("file:///music/a%20b.mp3"), Some(PathBuf::from("/music/a b.mp3")));
+		// The host part of a URL isn't part of the path
+		assert_eq!(entryPath("file://localhost/music/a.mp3"), Some(PathBuf::from("/music/a.mp3")));
+		assert_eq!(entryPath("file://server"), None);
+		assert_eq!(entryPath("http://radio.example.com/stream"), None);
+	}
+
+	#[cfg(not(windows))]
+	#[test]
+	fn entryPathsFromWindows()
+	{
+		assert_eq!(entryPath(r"..\Music\Album\a.mp3"), Some(PathBuf::from("../Music/Album/a.mp3")));
+	}
+
+	#[test]
+	fn matchByName()
+	{
+		let entry = PlaylistEntry::default();
+		let wanted = WantedEntry::new(Path::new("/old/music/Artist/Album/Track.MP3"), &entry);
+		// The file name is enough, and each parent directory that's the same makes it a better match
+		assert_eq!(wanted.score(Path::new("/music/Other/track.mp3"), None), Some(2));
+		assert_eq!(wanted.score(Path::new("/music/Artist/Album/Track.mp3"), None), Some(5));
+		assert_eq!(wanted.score(Path::new("/music/Artist/Album/Other.mp3"), None), None);
+	}
+
+	#[test]
+	fn matchByMetadata()
+	{
+		let entry = PlaylistEntry
+		{
+			title: Some("One More Time".into()),
+			artist: Some("Daft Punk".into()),
+			duration: Some(Duration::from_secs(320)),
+			..PlaylistEntry::default()
+		};
+		let wanted = WantedEntry::new(Path::new("gone.mp3"), &entry);
+		let candidate = Path::new("/music/01.flac");
+
+		// The title picks the file out, and each other thing that agrees makes it a better match
+		assert_eq!(wanted.score(candidate, Some(&metadata(Some("one more time"), None, None))), Some(2));
+		assert_eq!(wanted.score(candidate, Some(&metadata(Some("One More Time"), Some("Daft Punk"), Some(321)))), Some(4));
+		// But anything that disagrees rules it out
+		assert_eq!(wanted.score(candidate, Some(&metadata(Some("Aerodynamic"), Some("Daft Punk"), None))), None);
+		assert_eq!(wanted.score(candidate, Some(&metadata(Some("One More Time"), Some("Someone"), None))), None);
+		assert_eq!(wanted.score(candidate, Some(&metadata(Some("One More Time"), None, Some(330)))), None);
+		// And an artist on its own isn't enough to say which track it is
+		assert_eq!(wanted.score(candidate, Some(&metadata(None, Some("Daft Punk"), None))), None);
+		// Files without a title can still be found by their names
+		assert_eq!(wanted.score(Path::new("/music/01 One More Time.flac"), None), Some(1));
+	}
+}
@@ -0,0 +1,148 @@
+// SPDX-License-Identifier: BSD-3-Clause
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::time::Duration;
+
+use super::{entryPath, PlaylistContents, PlaylistEntry};
+
+/// Parse the text of a PLS playlist into its entries. Each entry is made up of numbered `FileN`, `TitleN` and
+/// `LengthN` keys in the `[playlist]` section, which can come in any order
+pub fn read(text: &str) -> PlaylistContents
+{
+	let mut entries = BTreeMap::<usize, PlaylistEntry>::new();
+	for line in text.lines()
+	{
+		// Skip anything that isn't a key/value pair (section headers, comments, etc)
+		let Some((key, value)) = line.split_once('=')
+			else { continue; };
+		let key = key.trim().to_ascii_lowercase();
+		let value = value.trim();
+
+		// Split the key into its name and the number of the entry it's for
+		let Some(split) = key.find(|character: char| character.is_ascii_digit())
+			else { continue; };
+		let (name, number) = key.split_at(split);
+		let Ok(number) = number.parse()
+			else { continue; };
+
+		let entry = entries.entry(number).or_default();
+		match name
+		{
+			"file" => entry.path = entryPath(value).unwrap_or_default(),
+			"title" => entry.setDisplayName(value),
+			// Lengths are in whole seconds, with -1 meaning the length isn't known
+			"length" => entry.duration = value.parse().ok()
+				.filter(|length| *length > 0)
+				.map(Duration::from_secs),
+			_ => {},
+		}
+	}
+
+	PlaylistContents
+	{
+		title: None,
+		// Only keep the entries that have a file we can actually play
+		entries: entries.into_values()
+			.filter(|entry| !entry.path.as_os_str().is_empty())
+			.collect(),
+	}
+}
+
+/// Write out the contents given as the text of a PLS playlist
+pub fn write(contents: &PlaylistContents) -> String
+{
+	let mut text = String::from("[playlist]\n");
+	// Writing to a String can't fail
+	for (number, entry) in contents.entries.iter().enumerate()
+	{
+		let number = number + 1;
+		let length = entry.duration
+			.and_then(|duration| i64::try_from(duration.as_secs()).ok())
+			.unwrap_or(-1);
+		let _ = writeln!(text, "File{number}={}", entry.path.display());
+		let _ = writeln!(text, "Title{number}={}", entry.displayName());
+		let _ = writeln!(text, "Length{number}={length}");
+	}
+	let _ = writeln!(text, "NumberOfEntries={}", contents.entries.len());
+	text.push_str("Version=2\n");
+	text
+}
+
+#[cfg(test)]
+mod tests
+{
+	use std::path::PathBuf;
+	use std::time::Duration;
+
+	use super::{read, write};
+	use crate::playlistFile::{PlaylistContents, PlaylistEntry};
+
+	#[test]
+	fn readOutOfOrder()
+	{
+		let contents = read
+		(
+			"[playlist]\nTitle2=Aerodynamic\nfile2=Discovery\\02 Aerodynamic.flac\nLENGTH1=320\n\
+			File1=Discovery/01 One More Time.flac\nTitle1=Daft Punk - One More Time\nLength2=-1\n\
+			Title3=No file\nNumberOfEntries=3\nVersion=2\n"
+		);
+		assert_eq!(contents.title, None);
+		// The entry with no file is dropped
+		assert_eq!(contents.entries.len(), 2);
+
+		let first = &contents.entries[0];
+		assert_eq!(first.path, PathBuf::from("Discovery/01 One More Time.flac"));
+		assert_eq!(first.artist.as_deref(), Some("Daft Punk"));
+		assert_eq!(first.title.as_deref(), Some("One More Time"));
+		assert_eq!(first.duration, Some(Duration::from_secs(320)));
+
+		let second = &contents.entries[1];
+		#[cfg(not(windows))]
+		assert_eq!(second.path, PathBuf::from("Discovery/02 Aerodynamic.flac"));
+		assert_eq!(second.title.as_deref(), Some("Aerodynamic"));
+		assert_eq!(second.duration, None);
+	}
+
+	#[test]
+	fn readSkipsStreams()
+	{
+		let contents = read("[playlist]\nFile1=http://radio.example.com/stream\nFile2=a.mp3\nNumberOfEntries=2\n");
+		assert_eq!(contents.entries.len(), 1);
+		assert_eq!(contents.entries[0].path, PathBuf::from("a.mp3"));
+	}
+
+	#[test]
+	fn writeAndReadBack()
+	{
+		let contents = PlaylistContents
+		{
+			title: None,
+			entries: vec!
+			[
+				PlaylistEntry
+				{
+					path: "/music/a.mp3".into(),
+					title: Some("One".into()),
+					artist: Some("Someone".into()),
+					duration: Some(Duration::from_secs(61)),
+					..PlaylistEntry::default()
+				},
+				PlaylistEntry { path: "b.mp3".into(), ..PlaylistEntry::default() },
+			],
+		};
+		assert_eq!
+		(
+			write(&contents),
+			"[playlist]\nFile1=/music/a.mp3\nTitle1=Someone - One\nLength1=61\n\
+			File2=b.mp3\nTitle2=b\nLength2=-1\nNumberOfEntries=2\nVersion=2\n"
+		);
+
+		let reread = read(&write(&contents));
+		assert_eq!(reread.entries.len(), 2);
+		assert_eq!(reread.entries[0].path, contents.entries[0].path);
+		assert_eq!(reread.entries[0].title, contents.entries[0].title);
+		assert_eq!(reread.entries[0].artist, contents.entries[0].artist);
+		assert_eq!(reread.entries[0].duration, contents.entries[0].duration);
+		assert_eq!(reread.entries[1].path, contents.entries[1].path);
+	}
+}
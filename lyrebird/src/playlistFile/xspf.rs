@@ -0,0 +1,269 @@
+// SPDX-License-Identifier: BSD-3-Clause
+use std::io;
+use std::path::{Component, Path, PathBuf};
+use std::time::Duration;
+
+use color_eyre::eyre::Result;
+use percent_encoding::{percent_decode_str, utf8_percent_encode, AsciiSet, CONTROLS};
+use quick_xml::events::{BytesDecl, BytesText, Event};
+use quick_xml::{Reader, Writer};
+
+use super::{entryPath, PlaylistContents, PlaylistEntry};
+
+/// Characters that have to be percent-encoded when turning a path into a location URI
+const URI_ENCODE_SET: &AsciiSet = &CONTROLS
+	.add(b' ').add(b'"').add(b'#').add(b'%').add(b'<').add(b'>').add(b'?')
+	.add(b'[').add(b']').add(b'\\').add(b'^').add(b'`').add(b'{').add(b'|').add(b'}');
+
+/// Parse the text of an XSPF playlist into its title and entries. Each `<track>` in the `<trackList>` gives its
+/// file as a `<location>` URI, along with optional `<title>`, `<creator>`, `<album>` and `<duration>` metadata
+pub fn read(text: &str) -> Result<PlaylistContents>
+{
+	let mut reader = Reader::from_str(text);
+	reader.config_mut().trim_text(true);
+
+	let mut contents = PlaylistContents::default();
+	// Names of the elements we're currently inside of, innermost last
+	let mut elements = Vec::new();
+	let mut track = None;
+	loop
+	{
+		match reader.read_event()?
+		{
+			Event::Start(element) =>
+			{
+				let name = String::from_utf8_lossy(element.local_name().as_ref()).into_owned();
+				if name == "track"
+				{
+					track = Some(PlaylistEntry::default());
+				}
+				elements.push(name);
+			},
+			Event::End(_) =>
+			{
+				// If that was the end of a track, and the track had a file we can play, it's a new entry
+				if elements.pop().as_deref() == Some("track")
+				{
+					if let Some(entry) = track.take().filter(|entry| !entry.path.as_os_str().is_empty())
+					{
+						contents.entries.push(entry);
+					}
+				}
+			},
+			Event::Text(text) => storeText(&elements, &mut track, &mut contents, &text.unescape()?),
+			Event::CData(data) =>
+				storeText(&elements, &mut track, &mut contents, &String::from_utf8_lossy(&data.into_inner())),
+			Event::Eof => break,
+			_ => {},
+		}
+	}
+	Ok(contents)
+}
+
+/// Store the text content of an element in the right place, based on which element it is and what it's part of
+fn storeText(elements: &[String], track: &mut Option<PlaylistEntry>, contents: &mut PlaylistContents, text: &str)
+{
+	let parent = elements.iter().rev().nth(1).map(String::as_str);
+	let element = elements.last().map(String::as_str);
+	match (parent, element, track)
+	{
+		// A track can have several locations - take the first that's a file we can play
+		(Some("track"), Some("location"), Some(entry)) if entry.path.as_os_str().is_empty() =>
+			entry.path = locationPath(text).unwrap_or_default(),
+		(Some("track"), Some("title"), Some(entry)) => entry.title = Some(text.to_string()),
+		(Some("track"), Some("creator"), Some(entry)) => entry.artist = Some(text.to_string()),
+		(Some("track"), Some("album"), Some(entry)) => entry.album = Some(text.to_string()),
+		// Durations are in milliseconds
+		(Some("track"), Some("duration"), Some(entry)) =>
+			entry.duration = text.parse().ok().map(Duration::from_millis),
+		(Some("playlist"), Some("title"), _) => contents.title = Some(text.to_string()),
+		_ => {},
+	}
+}
+
+/// Write out the contents given as the text of an XSPF playlist
+pub fn write(contents: &PlaylistContents) -> Result<String>
+{
+	let mut writer = Writer::new_with_indent(Vec::new(), b'\t', 1);
+	writer.write_event(Event::Decl(BytesDecl::new("1.0", Some("UTF-8"), None)))?;
+	writer.create_element("playlist")
+		.with_attributes([("version", "1"), ("xmlns", "http://xspf.org/ns/0/")])
+		.write_inner_content
+		(
+			|writer|
+			{
+				if let Some(title) = &contents.title
+				{
+					writer.create_element("title").write_text_content(BytesText::new(title))?;
+				}
+				writer.create_element("trackList")
+					.write_inner_content
+					(
+						|writer|
+						{
+							for entry in &contents.entries
+							{
+								writer.create_element("track").write_inner_content(|writer| writeTrack(writer, entry))?;
+							}
+							Ok(())
+						}
+					)?;
+				Ok(())
+			}
+		)?;
+	Ok(String::from_utf8(writer.into_inner())?)
+}
+
+fn writeTrack(writer: &mut Writer<Vec<u8>>, entry: &PlaylistEntry) -> io::Result<()>
+{
+	writer.create_element("location").write_text_content(BytesText::new(&locationURI(&entry.path)))?;
+	let metadata = [("title", &entry.title), ("creator", &entry.artist), ("album", &entry.album)];
+	for (element, value) in metadata
+	{
+		if let Some(value) = value
+		{
+			writer.create_element(element).write_text_content(BytesText::new(value))?;
+		}
+	}
+	if let Some(duration) = entry.duration
+	{
+		writer.create_element("duration").write_text_content(BytesText::new(&duration.as_millis().to_string()))?;
+	}
+	Ok(())
+}
+
+/// Turn a track's location URI back into a path. Relative URIs are percent-encoded just like `file://` URLs are
+fn locationPath(location: &str) -> Option<PathBuf>
+{
+	if location.contains("://")
+	{
+		return entryPath(location);
+	}
+	entryPath(&percent_decode_str(location).decode_utf8_lossy())
+}
+
+/// Turn a path into a URI for a track's location - absolute paths become `file://` URLs, while relative ones
+/// become relative URIs
+fn locationURI(path: &Path) -> String
+{
+	// URIs always use forward slashes to separate components, so rebuild the path that way
+	let components: Vec<_> = path.components()
+		.filter(|component| *component != Component::RootDir)
+		.map(|component| component.as_os_str().to_string_lossy())
+		.collect();
+	let location = utf8_percent_encode(&components.join("/"), URI_ENCODE_SET).to_string();
+	if path.is_absolute()
+	{
+		format!("file:///{location}")
+	}
+	else
+	{
+		location
+	}
+}
+
+#[cfg(test)]
+mod tests
+{
+	use std::path::{Path, PathBuf};
+	use std::time::Duration;
+
+	use super::{locationPath, locationURI, read, write};
+	use crate::playlistFile::{PlaylistContents, PlaylistEntry};
+
+	#[test]
+	fn readTracks()
+	{
+		let contents = read
+		(
+			r#"<?xml version="1.0" encoding="UTF-8"?>
+			<playlist version="1" xmlns="http://xspf.org/ns/0/">
+				<title>Road Trip</title>
+				<trackList>
+					<track>
+						<location>http://radio.example.com/stream</location>
+						<location>file:///music/Daft%20Punk/One%20More%20Time.flac</location>
+						<title>One More Time</title>
+						<creator>Daft Punk</creator>
+						<album>Discovery</album>
+						<duration>320357</duration>
+					</track>
+					<track>
+						<location>Rock &amp; Roll/b%23.mp3</location>
+						<title><![CDATA[B <sharp>]]></title>
+					</track>
+					<track>
+						<title>Nowhere to be found</title>
+					</track>
+				</trackList>
+			</playlist>"#
+		).expect("playlist is valid XSPF");
+		assert_eq!(contents.title.as_deref(), Some("Road Trip"));
+		assert_eq!(contents.entries.len(), 2);
+
+		// The stream can't be played, so the file location after it is used instead
+		let first = &contents.entries[0];
+		assert_eq!(first.path, PathBuf::from("/music/Daft Punk/One More Time.flac"));
+		assert_eq!(first.title.as_deref(), Some("One More Time"));
+		assert_eq!(first.artist.as_deref(), Some("Daft Punk"));
+		assert_eq!(first.album.as_deref(), Some("Discovery"));
+		assert_eq!(first.duration, Some(Duration::from_millis(320_357)));
+
+		let second = &contents.entries[1];
+		assert_eq!(second.path, PathBuf::from("Rock & Roll/b#.mp3"));
+		assert_eq!(second.title.as_deref(), Some("B <sharp>"));
+		assert_eq!(second.artist, None);
+	}
+
+	#[test]
+	fn readInvalid()
+	{
+		assert!(read("<playlist><trackList></playlist>").is_err());
+	}
+
+	#[test]
+	fn locations()
+	{
+		assert_eq!(locationURI(Path::new("/music/a b/c#1.mp3")), "file:///music/a%20b/c%231.mp3");
+		assert_eq!(locationURI(Path::new("a b/c.mp3")), "a%20b/c.mp3");
+		assert_eq!(locationPath("a%20b/c.mp3"), Some(PathBuf::from("a b/c.mp3")));
+		assert_eq!(locationPath("file:///music/a%20b/c%231.mp3"), Some(PathBuf::from("/music/a b/c#1.mp3")));
+		assert_eq!(locationPath("https://example.com/c.mp3"), None);
+	}
+
+	#[test]
+	fn writeAndReadBack()
+	{
+		let contents = PlaylistContents
+		{
+			title: Some("Mix & Match".into()),
+			entries: vec!
+			[
+				PlaylistEntry
+				{
+					path: "/music/Rock & Roll/a b.mp3".into(),
+					title: Some("A <b>".into()),
+					artist: Some("Someone".into()),
+					album: Some("Something".into()),
+					duration: Some(Duration::from_millis(61_500)),
+				},
+				PlaylistEntry { path: "relative/c%d.mp3".into(), ..PlaylistEntry::default() },
+			],
+		};
+		let text = write(&contents).expect("writing to a string can't fail");
+		assert!(text.contains("<location>file:///music/Rock%20&amp;%20Roll/a%20b.mp3</location>"));
+		assert!(text.contains("<location>relative/c%25d.mp3</location>"));
+
+		let reread = read(&text).expect("written playlist is valid XSPF");
+		assert_eq!(reread.title, contents.title);
+		assert_eq!(reread.entries.len(), 2);
+		let (entry, expected) = (&reread.entries[0], &contents.entries[0]);
+		assert_eq!(entry.path, expected.path);
+		assert_eq!(entry.title, expected.title);
+		assert_eq!(entry.artist, expected.artist);
+		assert_eq!(entry.album, expected.album);
+		assert_eq!(entry.duration, expected.duration);
+		assert_eq!(reread.entries[1].path, contents.entries[1].path);
+		assert_eq!(reread.entries[1].title, None);
+	}
+}
@@ -1,20 +1,43 @@
 // SPDX-License-Identifier: BSD-3-Clause
-use crossterm::event::{KeyCode, KeyEvent, KeyEventKind};
+use std::collections::BTreeMap;
+use std::fs;
+use std::iter;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+
+use color_eyre::eyre::{eyre, Result};
+use crossterm::event::{KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
+use directories::{BaseDirs, ProjectDirs};
 use ratatui::buffer::Buffer;
 use ratatui::layout::{Alignment, Constraint, Layout, Rect};
-use ratatui::style::Style;
+use ratatui::style::{Style, Stylize};
+use ratatui::text::{Line, Span};
 use ratatui::widgets::{Block, BorderType, List, ListDirection, ListItem, ListState, Padding, StatefulWidget, Widget};
 use serde::{Deserialize, Serialize};
+use serde_repr::{Deserialize_repr, Serialize_repr};
+use tracing::error;
 
+use crate::history::History;
+use crate::library::MusicLibrary;
+use crate::persistence::{writeJsonAtomically, StoredVersion};
+use crate::ratings::{RatingChange, Ratings};
+use crate::playlistFile::{exportPlaylist, importPlaylist, PathStyle};
+use crate::widgets::marks::Marks;
+use crate::widgets::prompt::{Prompt, PromptResult};
 use crate::window::Operation;
 use crate::playlist::Playlist;
+use crate::smartPlaylist::{SmartContents, SmartPlaylist, SmartRefresh};
 
 #[derive(Serialize, Deserialize)]
 pub struct Playlists
 {
+	version: PlaylistsVersion,
 	nowPlaying: Playlist,
 	#[expect(clippy::struct_field_names, reason = "naming is hard, okay")]
 	playlists: Vec<Playlist>,
+	/// Playlists made up of whatever in the library matches a query, listed after the saved playlists
+	#[serde(default)]
+	smartPlaylists: Vec<SmartPlaylist>,
 	#[serde(skip)]
 	activeEntry: Style,
 	#[serde(skip)]
@@ -23,6 +46,63 @@ pub struct Playlists
 	currentPlaylistState: ListState,
 	#[serde(skip)]
 	playlistsState: ListState,
+	/// Playlists in the listing marked for acting on together
+	#[serde(skip)]
+	playlistMarks: Marks,
+	/// Entries of the selected playlist marked for acting on together
+	#[serde(skip)]
+	entryMarks: Marks,
+	/// Prompt currently being displayed to the user, and what its answer is to be used for
+	#[serde(skip)]
+	prompt: Option<(Prompt, PromptAction)>,
+	/// Whether the playlists on disk were written by a newer version of Lyrebird, and so mustn't be overwritten
+	#[serde(skip)]
+	newerOnDisk: bool,
+	/// Counter handing out the tickets smart playlists' contents are worked out under
+	#[serde(skip)]
+	smartTickets: u64,
+}
+
+#[derive(Serialize_repr, Deserialize_repr, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+#[repr(u8)]
+pub enum PlaylistsVersion
+{
+	Version1 = 1,
+	/// Smart playlists are kept alongside the saved playlists
+	Version2 = 2,
+}
+
+impl PlaylistsVersion
+{
+	const CURRENT: Self = Self::Version2;
+}
+
+/// What the answer to a prompt is for
+#[derive(Clone, Copy)]
+enum PromptAction
+{
+	/// Naming a new playlist
+	Create,
+	/// Renaming the playlist at the index held
+	Rename(usize),
+	/// Confirming deletion of the playlist at the index held
+	Delete(usize),
+	/// Asking for a playlist file to import
+	Import,
+	/// Asking for the file to export the playlist at the index held in the listing to
+	Export(usize),
+	/// Naming a new smart playlist
+	CreateSmart,
+	/// Renaming the smart playlist at the index held
+	RenameSmart(usize),
+	/// Confirming deletion of the smart playlist at the index held
+	DeleteSmart(usize),
+	/// Confirming deletion of the marked playlists, the number of which is held
+	DeleteMarked(usize),
+	/// Asking for the query picking out the tracks of the smart playlist at the index held
+	SmartQuery(usize),
+	/// Asking for the most tracks the smart playlist at the index held is to have
+	SmartLimit(usize),
 }
 
 #[derive(Clone, Copy, Default)]
@@ -39,35 +119,642 @@ impl Playlists
 	{
 		Self
 		{
+			version: PlaylistsVersion::CURRENT,
 			nowPlaying: Playlist::new("Now Playing".into()),
 			playlists: Vec::new(),
+			smartPlaylists: Vec::new(),
 			activeEntry,
 			activeSide: Side::Playlists,
 			currentPlaylistState: ListState::default(),
-			playlistsState: ListState::default(),
+			playlistsState: ListState::default().with_selected(Some(0)),
+			playlistMarks: Marks::default(),
+			entryMarks: Marks::default(),
+			prompt: None,
+			newerOnDisk: false,
+			smartTickets: 0,
+		}
+	}
+
+	/// Read the user's playlists back in from the data directory, or start afresh if there are none yet (or what's
+	/// there can't be made sense of)
+	pub fn read(paths: &ProjectDirs, activeEntry: Style) -> Result<Self>
+	{
+		let playlistsPath = paths.data_dir().join("playlists.json");
+
+		if !playlistsPath.exists()
+		{
+			return Ok(Self::new(activeEntry));
+		}
+
+		let contents = fs::read(&playlistsPath)?;
+		// If a newer version of Lyrebird wrote the playlists, we can't understand them, but mustn't lose them either
+		if serde_json::from_slice::<StoredVersion>(&contents)
+			.is_ok_and(|stored| stored.version > PlaylistsVersion::CURRENT as u8)
+		{
+			error!("Playlists in {} are from a newer version of Lyrebird, ignoring them", playlistsPath.display());
+			let mut playlists = Self::new(activeEntry);
+			playlists.newerOnDisk = true;
+			return Ok(playlists);
+		}
+
+		match serde_json::from_slice::<Self>(&contents)
+		{
+			Ok(mut playlists) =>
+			{
+				playlists.version = PlaylistsVersion::CURRENT;
+				playlists.activeEntry = activeEntry;
+				playlists.playlistsState.select(Some(0));
+				Ok(playlists)
+			},
+			// If the playlists can't be read (eg, they got mangled), start afresh rather than refusing to start at
+			// all, keeping what was there aside in case it can be rescued by hand
+			Err(report) =>
+			{
+				let asidePath = playlistsPath.with_extension("json.bad");
+				error!
+				(
+					"Reading playlists {} failed, moving it to {}: {}",
+					playlistsPath.display(), asidePath.display(), report
+				);
+				if let Err(report) = fs::rename(&playlistsPath, &asidePath)
+				{
+					error!("Failed to move {} aside: {}", playlistsPath.display(), report);
+				}
+				Ok(Self::new(activeEntry))
+			},
+		}
+	}
+
+	/// Whether changes to the playlists can't be saved, as that'd overwrite ones from a newer version of Lyrebird
+	pub const fn readOnly(&self) -> bool
+		{ self.newerOnDisk }
+
+	pub fn write(&self, paths: &ProjectDirs) -> Result<()>
+	{
+		// Playlists from a newer version of Lyrebird were already complained about when read, and mustn't be
+		// overwritten, so there's nothing to do here
+		if self.newerOnDisk
+		{
+			return Ok(());
 		}
+		writeJsonAtomically(&paths.data_dir().join("playlists.json"), self)
 	}
 
 	pub fn handleKeyEvent(&mut self, key: &KeyEvent) -> Operation
 	{
+		// If there's a prompt up, it gets all the key events till it's dealt with
+		if self.prompt.is_some()
+		{
+			return self.handlePromptKeyEvent(key);
+		}
+
 		if key.kind == KeyEventKind::Press || key.kind == KeyEventKind::Repeat
 		{
-			match key.code
+			// Marking playlists for loading or deleting several at once
+			if let Side::Playlists = self.activeSide
 			{
-				KeyCode::Left => self.moveLeft(),
-				KeyCode::Right => self.moveRight(),
-				KeyCode::Up => self.moveUp(),
-				KeyCode::Down => self.moveDown(),
-				KeyCode::Enter => { return self.makeSelection(); },
+				let count = 1 + self.playlists.len() + self.smartPlaylists.len();
+				if self.playlistMarks.handleKeyEvent(key, &mut self.playlistsState, count)
+				{
+					// Marking moves the selection on, so the contents shown are for another playlist now
+					self.resetContents();
+					return Operation::None;
+				}
+			}
+
+			// Marking entries of the selected playlist for acting on several at once
+			if let Side::PlaylistContents = self.activeSide
+			{
+				// Marks made before the playlist last changed (eg, from another tab) may no longer be on the same
+				// entries, so forget them if it has
+				let (count, generation) = (self.selectedPlaylist().entryCount(), self.selectedPlaylist().generation());
+				self.entryMarks.follow(generation);
+				if self.entryMarks.handleKeyEvent(key, &mut self.currentPlaylistState, count)
+				{
+					return Operation::None;
+				}
+
+				// Rating the chosen entries
+				if let Some(change) = RatingChange::forKey(key.code)
+				{
+					return Operation::rate(self.chosenEntries(), change);
+				}
+			}
+
+			let shifted = key.modifiers.contains(KeyModifiers::SHIFT);
+			match (self.activeSide, key.code)
+			{
+				(_, KeyCode::Left) => self.moveLeft(),
+				(_, KeyCode::Right) => self.moveRight(),
+				(Side::PlaylistContents, KeyCode::Up) if shifted => self.moveSelectedEntry(Playlist::moveUp),
+				(Side::PlaylistContents, KeyCode::Down) if shifted => self.moveSelectedEntry(Playlist::moveDown),
+				(_, KeyCode::Up) => self.moveUp(),
+				(_, KeyCode::Down) => self.moveDown(),
+				(_, KeyCode::Enter) => { return self.makeSelection(); },
+				(_, KeyCode::Char('n')) => self.promptFor(PromptAction::Create),
+				(_, KeyCode::Char('N')) => self.promptFor(PromptAction::CreateSmart),
+				// Managing the selected playlist
+				(Side::Playlists, KeyCode::Char('r')) =>
+					self.promptForSelected(PromptAction::Rename, PromptAction::RenameSmart),
+				(Side::Playlists, KeyCode::Char('d') | KeyCode::Delete) => self.promptForDelete(),
+				(Side::Playlists, KeyCode::Char('l')) => { return self.loadSelected(None); },
+				(Side::Playlists, KeyCode::Char('i')) => self.promptFor(PromptAction::Import),
+				(Side::Playlists, KeyCode::Char('e')) => self.promptFor(PromptAction::Export(self.selectedIndex())),
+				// Changing how the selected smart playlist is put together
+				(Side::Playlists, KeyCode::Char('E')) => self.promptForSelectedSmart(PromptAction::SmartQuery),
+				(Side::Playlists, KeyCode::Char('L')) => self.promptForSelectedSmart(PromptAction::SmartLimit),
+				(Side::Playlists, KeyCode::Char('o')) => self.changeSelectedSmart(SmartPlaylist::cycleSortOrder),
+				(Side::Playlists, KeyCode::Char('O')) => self.changeSelectedSmart(SmartPlaylist::toggleDescending),
+				// Editing the contents of the selected playlist
+				(Side::PlaylistContents, KeyCode::Char('d') | KeyCode::Delete) => self.removeChosenEntries(),
+				(Side::PlaylistContents, KeyCode::Char('t')) => self.moveSelectedEntry(Playlist::moveToTop),
+				(Side::PlaylistContents, KeyCode::Char('c')) =>
+				{
+					if let Some(playlist) = self.selectedPlaylistMut()
+					{
+						playlist.clear();
+						self.entryMarks.clear();
+					}
+				},
+				(Side::PlaylistContents, KeyCode::Char('k')) => self.cropNowPlaying(),
+				(Side::PlaylistContents, KeyCode::Char('u')) =>
+				{
+					if let Some(playlist) = self.selectedPlaylistMut()
+					{
+						playlist.undo();
+						self.entryMarks.clear();
+					}
+				},
+				(Side::PlaylistContents, KeyCode::Char('+')) => { return self.enqueueChosenEntries(); },
+				(Side::PlaylistContents, KeyCode::Char('i')) => { return self.insertChosenNext(); },
+				(Side::PlaylistContents, KeyCode::Char('a')) => { return Operation::addToPlaylist(self.chosenEntries()); },
 				_ => {},
 			}
 		}
 		Operation::None
 	}
 
+	/// Whether the tab is currently taking all key input for itself (eg, for typing a playlist name)
+	pub const fn capturesInput(&self) -> bool
+		{ self.prompt.is_some() }
+
 	pub fn nowPlaying(&mut self) -> &mut Playlist
 		{ &mut self.nowPlaying }
 
+	/// Get the names of all the playlists, starting with Now Playing
+	pub fn names(&self) -> Vec<String>
+	{
+		iter::once(&self.nowPlaying)
+			.chain(self.playlists.iter())
+			.map(|playlist| playlist.name().to_string())
+			.collect()
+	}
+
+	/// Add files to the saved playlist at the given index (as given by `names()`, so not Now Playing)
+	pub fn addTo(&mut self, index: usize, fileNames: &[PathBuf])
+	{
+		if let Some(playlist) = index.checked_sub(1).and_then(|index| self.playlists.get_mut(index))
+		{
+			for fileName in fileNames
+			{
+				playlist.add(fileName);
+			}
+		}
+	}
+
+	/// Import a playlist file as a new saved playlist, selecting it and returning the name it was given. Entries
+	/// that aren't where the file says they are get looked for in the library, if one is given
+	pub fn importFile(&mut self, fileName: &Path, library: Option<&MusicLibrary>) -> Result<&str>
+	{
+		Ok(self.addImported(importPlaylist(fileName, library)?))
+	}
+
+	/// Add a playlist that's been imported as a new saved playlist, selecting it and returning its name
+	pub fn addImported(&mut self, playlist: Playlist) -> &str
+	{
+		self.playlists.push(playlist);
+		self.playlistsState.select(Some(self.playlists.len()));
+		self.resetContents();
+		// The smart playlists have moved down one, so the marks are no longer on the playlists they were
+		self.playlistMarks.clear();
+		self.playlists[self.playlists.len() - 1].name()
+	}
+
+	/// Set up bringing the smart playlists up to date with the library, play history and ratings, if any have
+	/// changed since they were last worked out
+	pub fn refreshSmartPlaylists(&mut self, library: Arc<RwLock<MusicLibrary>>, history: &History,
+		ratings: &Ratings, savedQueries: &BTreeMap<String, String>) -> Option<SmartRefresh>
+	{
+		SmartRefresh::new(&mut self.smartPlaylists, library, history, ratings, savedQueries, &mut self.smartTickets)
+	}
+
+	/// Take on the contents worked out for smart playlists, for whichever of them they're still wanted by
+	pub fn smartPlaylistsRefreshed(&mut self, results: Vec<SmartContents>)
+	{
+		let selected = self.smartIndex();
+		for contents in results
+		{
+			let Some(index) = self.smartPlaylists.iter().position(|playlist| playlist.isFor(&contents))
+				else { continue; };
+			self.smartPlaylists[index].refreshed(contents);
+			// If the selected playlist's contents changed, what was marked in it no longer means anything
+			if selected == Some(index)
+			{
+				self.entryMarks.clear();
+			}
+		}
+	}
+
+	/// The saved queries have changed, so any smart playlists using them need working out again
+	pub fn invalidateSmartPlaylists(&mut self)
+	{
+		for playlist in &mut self.smartPlaylists
+		{
+			playlist.invalidate();
+		}
+	}
+
+	/// Export the playlist with the given name to a file, the format being picked by the file's extension
+	pub fn exportNamed(&self, name: &str, fileName: &Path, style: PathStyle, library: &MusicLibrary) -> Result<()>
+	{
+		let playlist = iter::once(&self.nowPlaying)
+			.chain(self.playlists.iter())
+			.find(|playlist| playlist.name() == name)
+			.ok_or_else(|| eyre!("No playlist named {name}"))?;
+		exportPlaylist(playlist, fileName, style, library)
+	}
+
+	/// Export the playlist at the given index in the listing to a file, with paths relative to where it's written
+	pub fn exportAt(&self, index: usize, fileName: &Path, library: &MusicLibrary) -> Result<()>
+	{
+		exportPlaylist(self.playlistAt(index), fileName, PathStyle::Relative, library)
+	}
+
+	/// Figure out which playlist is selected in the listing - 0 is Now Playing, the saved playlists follow, and
+	/// then the smart playlists
+	fn selectedIndex(&self) -> usize
+	{
+		self.playlistsState.selected().unwrap_or_default().min(self.playlists.len() + self.smartPlaylists.len())
+	}
+
+	/// Figure out which saved playlist is selected, if it's one of those
+	fn savedIndex(&self) -> Option<usize>
+	{
+		self.selectedIndex().checked_sub(1).filter(|&index| index < self.playlists.len())
+	}
+
+	/// Figure out which smart playlist is selected, if it's one of those
+	fn smartIndex(&self) -> Option<usize>
+	{
+		self.selectedIndex().checked_sub(self.playlists.len() + 1)
+	}
+
+	/// Get the playlist at the given index in the listing
+	fn playlistAt(&self, index: usize) -> &Playlist
+	{
+		match index.checked_sub(1)
+		{
+			None => &self.nowPlaying,
+			Some(index) if index < self.playlists.len() => &self.playlists[index],
+			Some(index) => self.smartPlaylists[index - self.playlists.len()].contents(),
+		}
+	}
+
+	fn selectedPlaylist(&self) -> &Playlist
+	{
+		self.playlistAt(self.selectedIndex())
+	}
+
+	/// Get the selected playlist for editing - smart playlists can't be edited, as their contents come from the
+	/// library
+	fn selectedPlaylistMut(&mut self) -> Option<&mut Playlist>
+	{
+		match self.selectedIndex()
+		{
+			0 => Some(&mut self.nowPlaying),
+			index => self.playlists.get_mut(index - 1),
+		}
+	}
+
+	/// Change how the selected smart playlist is put together, if a smart playlist is selected
+	fn changeSelectedSmart(&mut self, change: fn(&mut SmartPlaylist))
+	{
+		if let Some(index) = self.smartIndex()
+		{
+			change(&mut self.smartPlaylists[index]);
+			self.resetContents();
+		}
+	}
+
+	/// Move the selected entry of the selected playlist about, keeping it selected as it moves
+	fn moveSelectedEntry(&mut self, operation: fn(&mut Playlist, usize) -> usize)
+	{
+		let selected = self.currentPlaylistState.selected();
+		if let (Some(index), Some(playlist)) = (selected, self.selectedPlaylistMut())
+		{
+			let index = operation(playlist, index);
+			self.currentPlaylistState.select(Some(index));
+			self.entryMarks.clear();
+		}
+	}
+
+	/// Get the files for the marked entries of the selected playlist, or the selected entry if none are marked
+	fn chosenEntries(&self) -> Vec<PathBuf>
+	{
+		let playlist = self.selectedPlaylist();
+		self.entryMarks.orSelected(self.currentPlaylistState.selected())
+			.into_iter()
+			.filter(|&entry| entry < playlist.entryCount())
+			.map(|entry| playlist.entry(entry).to_path_buf())
+			.collect()
+	}
+
+	/// Queue the chosen entries of a saved playlist up to play after what's currently playing. Entries of Now
+	/// Playing are already queued, so there's nothing to do for those
+	fn insertChosenNext(&self) -> Operation
+	{
+		if self.selectedIndex() == 0
+		{
+			return Operation::None;
+		}
+		Operation::insertNext(self.chosenEntries())
+	}
+
+	/// Add the chosen entries of a saved playlist to the end of Now Playing
+	fn enqueueChosenEntries(&self) -> Operation
+	{
+		if self.selectedIndex() == 0
+		{
+			return Operation::None;
+		}
+		Operation::playlist(self.chosenEntries())
+	}
+
+	fn removeChosenEntries(&mut self)
+	{
+		let entries = self.entryMarks.orSelected(self.currentPlaylistState.selected());
+		if let Some(playlist) = self.selectedPlaylistMut()
+		{
+			playlist.remove(&entries);
+			self.entryMarks.clear();
+		}
+	}
+
+	/// The selected playlist has changed, so forget about what was selected and marked in the old one
+	fn resetContents(&mut self)
+	{
+		self.currentPlaylistState = ListState::default();
+		self.entryMarks.clear();
+	}
+
+	/// Crop Now Playing down to just what's currently playing - this only makes sense for Now Playing,
+	/// as the saved playlists don't have anything playing
+	fn cropNowPlaying(&mut self)
+	{
+		if self.selectedIndex() == 0
+		{
+			self.nowPlaying.cropToCurrent();
+			self.currentPlaylistState.select(Some(0));
+		}
+	}
+
+	fn promptFor(&mut self, action: PromptAction)
+	{
+		let prompt = match action
+		{
+			PromptAction::Create => Prompt::text("New playlist name", ""),
+			PromptAction::Rename(index) => Prompt::text("Rename playlist", self.playlists[index].name()),
+			PromptAction::Delete(index) =>
+				Prompt::confirm("Delete playlist", &format!("Delete {}?", self.playlists[index].name())),
+			PromptAction::Import => Prompt::text("Import playlist file", ""),
+			PromptAction::Export(index) =>
+			{
+				let name = self.playlistAt(index).name();
+				Prompt::text(&format!("Export {name} to"), &format!("{name}.m3u8"))
+			},
+			PromptAction::CreateSmart => Prompt::text("New smart playlist name", ""),
+			PromptAction::RenameSmart(index) =>
+				Prompt::text("Rename smart playlist", self.smartPlaylists[index].name()),
+			PromptAction::DeleteSmart(index) => Prompt::confirm
+			(
+				"Delete smart playlist", &format!("Delete {}?", self.smartPlaylists[index].name())
+			),
+			PromptAction::DeleteMarked(count) =>
+				Prompt::confirm("Delete playlists", &format!("Delete {count} marked playlists?")),
+			PromptAction::SmartQuery(index) =>
+			{
+				let playlist = &self.smartPlaylists[index];
+				Prompt::text(&format!("Tracks for {}", playlist.name()), playlist.query())
+			},
+			PromptAction::SmartLimit(index) =>
+			{
+				let limit = self.smartPlaylists[index].limit().map(|limit| limit.to_string());
+				Prompt::text("Most tracks to have (blank for no limit)", &limit.unwrap_or_default())
+			},
+		};
+		self.prompt = Some((prompt, action));
+	}
+
+	/// Put up a prompt for an action on the selected saved or smart playlist - Now Playing can't be renamed or
+	/// deleted
+	fn promptForSelected(&mut self, saved: fn(usize) -> PromptAction, smart: fn(usize) -> PromptAction)
+	{
+		if let Some(index) = self.savedIndex()
+		{
+			self.promptFor(saved(index));
+		}
+		self.promptForSelectedSmart(smart);
+	}
+
+	/// Put up a prompt for deleting the marked playlists, or if none are marked, the selected one
+	fn promptForDelete(&mut self)
+	{
+		if self.playlistMarks.isEmpty()
+		{
+			self.promptForSelected(PromptAction::Delete, PromptAction::DeleteSmart);
+			return;
+		}
+		// Now Playing can't be deleted, so it doesn't count even if it's marked
+		let count = self.playlistMarks.orSelected(None).into_iter().filter(|&index| index != 0).count();
+		if count != 0
+		{
+			self.promptFor(PromptAction::DeleteMarked(count));
+		}
+	}
+
+	fn deleteMarked(&mut self)
+	{
+		// Go from the end of the listing back, so removing a playlist doesn't move those still to go - this also
+		// means the smart playlists all go before any of the saved ones
+		for index in self.playlistMarks.orSelected(None).into_iter().rev()
+		{
+			match index.checked_sub(1)
+			{
+				// Now Playing can't be deleted
+				None => {},
+				Some(index) if index < self.playlists.len() => { self.playlists.remove(index); },
+				Some(index) => { self.smartPlaylists.remove(index - self.playlists.len()); },
+			}
+		}
+		self.resetContents();
+		self.playlistMarks.clear();
+	}
+
+	/// Put up a prompt for an action on the selected smart playlist, if one is selected
+	fn promptForSelectedSmart(&mut self, action: fn(usize) -> PromptAction)
+	{
+		if let Some(index) = self.smartIndex()
+		{
+			self.promptFor(action(index));
+		}
+	}
+
+	fn handlePromptKeyEvent(&mut self, key: &KeyEvent) -> Operation
+	{
+		let Some((prompt, action)) = &mut self.prompt
+			else { return Operation::None; };
+
+		let mut operation = Operation::None;
+		match (prompt.handleKeyEvent(key), *action)
+		{
+			(PromptResult::Pending, _) => { return operation; },
+			(PromptResult::Text(name), PromptAction::Create) =>
+			{
+				let name = name.trim();
+				if !name.is_empty()
+				{
+					self.playlists.push(Playlist::new(name.to_string()));
+					// Select the new playlist so the user can see it
+					self.playlistsState.select(Some(self.playlists.len()));
+					self.resetContents();
+					// The smart playlists have moved down one, so the marks are no longer on the playlists they were
+					self.playlistMarks.clear();
+				}
+			},
+			(PromptResult::Text(name), PromptAction::Rename(index)) =>
+			{
+				let name = name.trim();
+				if !name.is_empty()
+				{
+					self.playlists[index].rename(name.to_string());
+				}
+			},
+			(PromptResult::Confirmed, PromptAction::Delete(index)) =>
+			{
+				self.playlists.remove(index);
+				self.playlistsState.select(Some(index));
+				self.resetContents();
+				self.playlistMarks.clear();
+			},
+			// Importing needs the library to find any missing entries in, so that gets handed off to the main window
+			(PromptResult::Text(fileName), PromptAction::Import) =>
+				operation = Operation::ImportPlaylist(expandHome(fileName.trim())),
+			// As does exporting, for the metadata to write out for the entries
+			(PromptResult::Text(fileName), PromptAction::Export(index)) =>
+				operation = Operation::ExportPlaylist(index, expandHome(fileName.trim())),
+			(PromptResult::Text(name), PromptAction::CreateSmart) =>
+			{
+				let name = name.trim();
+				if !name.is_empty()
+				{
+					self.smartPlaylists.push(SmartPlaylist::new(name.to_string(), String::new()));
+					// Select the new playlist, and go straight on to asking what's to be in it
+					let index = self.smartPlaylists.len() - 1;
+					self.playlistsState.select(Some(self.playlists.len() + self.smartPlaylists.len()));
+					self.resetContents();
+					self.promptFor(PromptAction::SmartQuery(index));
+					return operation;
+				}
+			},
+			(PromptResult::Text(name), PromptAction::RenameSmart(index)) =>
+			{
+				let name = name.trim();
+				if !name.is_empty()
+				{
+					self.smartPlaylists[index].rename(name.to_string());
+				}
+			},
+			(PromptResult::Confirmed, PromptAction::DeleteSmart(index)) =>
+			{
+				self.smartPlaylists.remove(index);
+				self.playlistsState.select(Some(self.playlists.len() + index));
+				self.resetContents();
+				self.playlistMarks.clear();
+			},
+			(PromptResult::Confirmed, PromptAction::DeleteMarked(_)) => self.deleteMarked(),
+			(PromptResult::Text(query), PromptAction::SmartQuery(index)) =>
+			{
+				self.smartPlaylists[index].setQuery(query.trim().to_string());
+				self.resetContents();
+			},
+			(PromptResult::Text(limit), PromptAction::SmartLimit(index)) =>
+			{
+				let limit = limit.trim();
+				match limit.parse()
+				{
+					Ok(limit) => self.smartPlaylists[index].setLimit(Some(limit)),
+					_ if limit.is_empty() => self.smartPlaylists[index].setLimit(None),
+					Err(_) => operation = Operation::Error(format!("{limit} is not a number of tracks")),
+				}
+				self.resetContents();
+			},
+			_ => {},
+		}
+		// The prompt has been dealt with one way or another, so get rid of it
+		self.prompt = None;
+		operation
+	}
+
+	/// Load the selected saved or smart playlist into Now Playing and start playing from the given entry, or from
+	/// the start of the play order if no entry is given
+	fn loadSelected(&mut self, entry: Option<usize>) -> Operation
+	{
+		// If several playlists are marked, they get loaded one after the other
+		if entry.is_none() && !self.playlistMarks.isEmpty()
+		{
+			let files: Vec<_> = self.playlistMarks.orSelected(None)
+				.into_iter()
+				.filter(|&index| index != 0)
+				.flat_map(|index| self.playlistAt(index).entries().map(Path::to_path_buf))
+				.collect();
+			if files.is_empty()
+			{
+				return Operation::None;
+			}
+			self.nowPlaying.replaceWith(&files);
+			return self.nowPlaying.current()
+				.map_or(Operation::None, |fileName| Operation::PlayNext(fileName.to_path_buf()));
+		}
+
+		let index = self.selectedIndex();
+		if index == 0
+		{
+			return Operation::None;
+		}
+
+		let playlist = match self.smartIndex()
+		{
+			Some(smart) => self.smartPlaylists[smart].contents(),
+			None => &self.playlists[index - 1],
+		};
+		self.nowPlaying.replaceWithContentsOf(playlist);
+		if let Some(entry) = entry
+		{
+			if entry >= self.nowPlaying.entryCount()
+			{
+				return Operation::None;
+			}
+			// Make sure that if we're shuffling, the chosen entry starts the play order off
+			self.nowPlaying.nextEntry(entry);
+			self.nowPlaying.reshuffle();
+		}
+		match self.nowPlaying.current()
+		{
+			Some(fileName) => Operation::PlayNext(fileName.to_path_buf()),
+			None => Operation::None,
+		}
+	}
+
 	const fn moveLeft(&mut self)
 		{ self.activeSide = Side::Playlists; }
 
@@ -81,7 +768,7 @@ impl Playlists
 			Side::Playlists =>
 			{
 				self.playlistsState.select_previous();
-				self.currentPlaylistState = ListState::default();
+				self.resetContents();
 			}
 			Side::PlaylistContents =>
 			{
@@ -97,7 +784,7 @@ impl Playlists
 			Side::Playlists =>
 			{
 				self.playlistsState.select_next();
-				self.currentPlaylistState = ListState::default();
+				self.resetContents();
 			}
 			Side::PlaylistContents =>
 			{
@@ -110,24 +797,37 @@ impl Playlists
 	{
 		match self.activeSide
 		{
-			Side::Playlists => Operation::None,
+			Side::Playlists =>
+			{
+				// Switch over to the contents of the selected playlist
+				self.activeSide = Side::PlaylistContents;
+				Operation::None
+			},
 			Side::PlaylistContents =>
 			{
 				// Figure out which file this is from the list, starting by looking up
 				// which entry is currently selected (if any)
-				match self.currentPlaylistState.selected()
+				let Some(index) = self.currentPlaylistState.selected()
+					else { return Operation::None; };
+				// If the selected playlist isn't Now Playing, load it into Now Playing starting from that entry,
+				// unless some of its entries are marked in which case play just those
+				if self.selectedIndex() != 0
 				{
-					// If we have a valid selection
-					Some(index) =>
+					if !self.entryMarks.isEmpty()
 					{
-						// Look that up in the now playing list
-						let fileName = self.nowPlaying.entry(index).to_path_buf();
-						// Set it as the next thing to play, and ask the file to be switched to
-						self.nowPlaying.nextEntry(index);
-						Operation::PlayNext(fileName)
-					},
-					None => Operation::None,
+						return Operation::play(self.chosenEntries());
+					}
+					return self.loadSelected(Some(index));
 				}
+				// Otherwise, look the entry up in the now playing list (making sure it's valid)
+				if index >= self.nowPlaying.entryCount()
+				{
+					return Operation::None;
+				}
+				let fileName = self.nowPlaying.entry(index).to_path_buf();
+				// Set it as the next thing to play, and ask the file to be switched to
+				self.nowPlaying.nextEntry(index);
+				Operation::PlayNext(fileName)
 			}
 		}
 	}
@@ -143,33 +843,57 @@ impl Widget for &mut Playlists
 		let layout = Layout::horizontal([Constraint::Fill(1), Constraint::Fill(2)])
 			.split(area);
 
+		// If the playlists can't be saved, say so where the user will see it
+		let playlistsBlock = Block::bordered()
+			.title(" Playlists ")
+			.title_alignment(Alignment::Left)
+			.title_style
+			(
+				match self.activeSide
+				{
+					Side::Playlists => self.activeEntry,
+					Side::PlaylistContents => Style::default(),
+				}
+			)
+			.border_type(BorderType::Rounded)
+			.padding(Padding::horizontal(1));
+		let playlistsBlock = if self.newerOnDisk
+		{
+			playlistsBlock.title_bottom(Line::from(" From a newer Lyrebird, changes won't be saved ").red())
+		}
+		else
+		{
+			playlistsBlock
+		};
+
 		// Render the playlist listing using the internal state object
 		StatefulWidget::render
 		(
 			// Build a list of playlists currently available to the user
 			List::new
 			(
-				self.playlists
-					.iter()
-					.map(|playlist| ListItem::new(playlist.name()))
-			)
-				// Put it in a bordered block for presentation
-				.block
+				self.playlistMarks.apply
 				(
-					Block::bordered()
-						.title(" Playlists ")
-						.title_alignment(Alignment::Left)
-						.title_style
+					iter::once(&self.nowPlaying)
+						.chain(self.playlists.iter())
+						.map(|playlist| ListItem::new(playlist.name()))
+						.chain
 						(
-							match self.activeSide
-							{
-								Side::Playlists => self.activeEntry,
-								Side::PlaylistContents => Style::default(),
-							}
+							// Mark the smart playlists out from the rest, as they can't be edited like the others
+							self.smartPlaylists
+								.iter()
+								.map
+								(
+									|playlist| ListItem::new
+									(
+										Line::from(vec![Span::from(playlist.name()), Span::from(" (smart)").dim()])
+									)
+								)
 						)
-						.border_type(BorderType::Rounded)
-						.padding(Padding::horizontal(1))
 				)
+			)
+				// Put it in a bordered block for presentation
+				.block(playlistsBlock)
 				.highlight_style(self.activeEntry)
 				.direction(ListDirection::TopToBottom),
 			layout[0],
@@ -177,16 +901,30 @@ impl Widget for &mut Playlists
 			&mut self.playlistsState
 		);
 
-		// Render the now playing playlist using the internal state object
+		// Render the selected playlist using the internal state object
+		// (marking out the entry currently playing if it's Now Playing)
+		let (playlist, currentStyle) = match (self.selectedIndex(), self.smartIndex())
+		{
+			(0, _) => (&self.nowPlaying, Some(Style::new().bold())),
+			(_, Some(index)) => (self.smartPlaylists[index].contents(), None),
+			(index, None) => (&self.playlists[index - 1], None),
+		};
+		self.entryMarks.follow(playlist.generation());
+		// Smart playlists show how they're put together along with their name
+		let title = match self.smartIndex()
+		{
+			Some(index) => self.smartPlaylists[index].description(),
+			None => playlist.name().to_string(),
+		};
 		StatefulWidget::render
 		(
-			// Build a list of all the files in the Now Playing playlist
-			List::new(self.nowPlaying.contents())
+			// Build a list of all the files in the selected playlist
+			List::new(self.entryMarks.apply(playlist.contents(currentStyle)))
 				// Put it in a bordered block for presentation
 				.block
 				(
 					Block::bordered()
-						.title(" Now Playing ")
+						.title(format!(" {title} "))
 						.title_alignment(Alignment::Left)
 						.title_style
 						(
@@ -205,5 +943,23 @@ impl Widget for &mut Playlists
 			buf,
 			&mut self.currentPlaylistState
 		);
+
+		// If there's a prompt up, draw it over the top of everything else
+		if let Some((prompt, _)) = &mut self.prompt
+		{
+			prompt.render(area, buf);
+		}
+	}
+}
+
+/// Expand a leading `~` in a path the user typed in to their home directory
+fn expandHome(path: &str) -> PathBuf
+{
+	let home = BaseDirs::new();
+	match (path.strip_prefix('~'), &home)
+	{
+		(Some(rest), Some(home)) if rest.is_empty() || rest.starts_with(['/', '\\']) =>
+			home.home_dir().join(rest.trim_start_matches(['/', '\\'])),
+		_ => PathBuf::from(path),
 	}
 }
@@ -0,0 +1,798 @@
+// SPDX-License-Identifier: BSD-3-Clause
+use std::collections::BTreeMap;
+use std::ffi::OsStr;
+use std::iter::Peekable;
+use std::mem;
+use std::path::Path;
+use std::time::SystemTime;
+use std::vec;
+
+use color_eyre::eyre::{eyre, Result};
+
+use crate::history::TrackStats;
+use crate::metadata::TrackMetadata;
+use crate::ratings::TrackRating;
+
+/// Formats that hold audio without throwing any of it away, going by their file extensions
+const LOSSLESS_FORMATS: [&str; 9] = ["FLAC", "WAV", "AIF", "AIFF", "APE", "WV", "OFR", "OFS", "TTA"];
+const SECONDS_PER_DAY: u64 = 24 * 60 * 60;
+
+/// A query over the tracks in the library, such as `artist:rammstein year:>1999 format:flac -genre:live`.
+///
+/// Terms are either plain text, which has to appear in the title, artist, album or file name of a track, or
+/// `field:value` to look at just one thing about a track. Text fields match if the value appears anywhere in them,
+/// or with `field:=value` if they're exactly the value. Number fields take `=`, `<`, `<=`, `>` or `>=` in front of
+/// the value, or a range as `low..high`. Terms can be combined with `AND` (which is also what putting them next to
+/// each other means), `OR` and `NOT` (or `-` in front of a term), grouped with brackets, and phrases with spaces in
+/// put in double quotes. A saved query can be used as part of another with `@name`
+pub struct Query(Expression);
+
+/// What a query gets checked against for each track
+pub struct Track<'a>
+{
+	pub file: &'a Path,
+	pub metadata: Option<&'a TrackMetadata>,
+	/// Size of the file in bytes, if known
+	pub size: Option<u64>,
+	/// What the play history says about the track, if it's ever been played
+	pub stats: Option<&'a TrackStats>,
+	/// How the user's rated the track, if they have
+	pub rating: Option<&'a TrackRating>,
+}
+
+enum Expression
+{
+	/// The empty query, which matches everything
+	All,
+	And(Box<Expression>, Box<Expression>),
+	Or(Box<Expression>, Box<Expression>),
+	Not(Box<Expression>),
+	Term(Term),
+}
+
+enum Term
+{
+	/// Text that has to appear in any of the title, artist, album or file name
+	Text(String),
+	/// Text that has to appear in the field, or be all that's in it if `exact` is set
+	Field { field: TextField, text: String, exact: bool },
+	Number(NumberField, Comparison),
+	Lossless(bool),
+	Favourite(bool),
+}
+
+#[derive(Clone, Copy)]
+enum TextField
+{
+	Title,
+	Artist,
+	Album,
+	AlbumArtist,
+	Genre,
+	Format,
+	FileName,
+	Path,
+}
+
+#[derive(Clone, Copy)]
+enum NumberField
+{
+	Year,
+	Track,
+	Disc,
+	Channels,
+	BitsPerSample,
+	/// Samples per second, in Hz
+	SampleRate,
+	/// Average bit rate in kb/s, worked out from the file's size and running time
+	BitRate,
+	/// Running time in seconds
+	Duration,
+	/// Times played right through
+	Plays,
+	Skips,
+	/// Days since the track was last played
+	LastPlayed,
+	/// Stars the user's given the track, 0 being unrated
+	Rating,
+}
+
+enum Comparison
+{
+	Equal(u64),
+	Less(u64),
+	LessEqual(u64),
+	Greater(u64),
+	GreaterEqual(u64),
+	Between(u64, u64),
+}
+
+#[derive(PartialEq, Eq)]
+enum Token
+{
+	Open,
+	Close,
+	And,
+	Or,
+	Not,
+	Word
+	{
+		field: Option<String>,
+		text: String,
+		/// Whether any of the word was in quotes, which stops it being taken as a keyword
+		quoted: bool,
+	},
+}
+
+impl Query
+{
+	/// Parse a query, looking up any saved queries it uses by name in those given
+	pub fn parse(text: &str, saved: &BTreeMap<String, String>) -> Result<Self>
+	{
+		Parser::parse(text, saved, &mut Vec::new()).map(Self)
+	}
+
+	/// Check whether some search text is just plain words, rather than a query using any fields or operators
+	pub fn isPlain(text: &str) -> bool
+	{
+		tokenise(text).is_ok_and
+		(
+			|tokens| tokens
+				.iter()
+				.all(|token| matches!(token, Token::Word { field: None, text, quoted: false } if !text.starts_with('@')))
+		)
+	}
+
+	pub fn matches(&self, track: &Track<'_>) -> bool
+	{
+		self.0.matches(track)
+	}
+}
+
+impl Expression
+{
+	fn matches(&self, track: &Track<'_>) -> bool
+	{
+		match self
+		{
+			Self::All => true,
+			Self::And(lhs, rhs) => lhs.matches(track) && rhs.matches(track),
+			Self::Or(lhs, rhs) => lhs.matches(track) || rhs.matches(track),
+			Self::Not(expression) => !expression.matches(track),
+			Self::Term(term) => term.matches(track),
+		}
+	}
+}
+
+impl Term
+{
+	/// Tracks that don't have what a term looks at never match it - so they do match the term negated
+	fn matches(&self, track: &Track<'_>) -> bool
+	{
+		match self
+		{
+			Self::Text(text) =>
+				[TextField::Title, TextField::Artist, TextField::Album, TextField::AlbumArtist, TextField::FileName]
+					.into_iter()
+					.filter_map(|field| field.value(track))
+					.any(|value| value.to_lowercase().contains(text)),
+			Self::Field { field, text, exact } => field.value(track)
+				.map(|value| value.to_lowercase())
+				.is_some_and(|value| if *exact { value == *text } else { value.contains(text) }),
+			Self::Number(field, comparison) => field.value(track).is_some_and(|value| comparison.matches(value)),
+			Self::Lossless(lossless) => TextField::Format.value(track)
+				.is_some_and(|format| LOSSLESS_FORMATS.contains(&format.as_str()) == *lossless),
+			Self::Favourite(favourite) => track.rating.is_some_and(|rating| rating.favourite) == *favourite,
+		}
+	}
+}
+
+impl TextField
+{
+	fn named(name: &str) -> Option<Self>
+	{
+		Some(match name
+		{
+			"title" => Self::Title,
+			"artist" => Self::Artist,
+			"album" => Self::Album,
+			"albumartist" => Self::AlbumArtist,
+			"genre" => Self::Genre,
+			"format" => Self::Format,
+			"filename" => Self::FileName,
+			"path" => Self::Path,
+			_ => return None,
+		})
+	}
+
+	fn value(self, track: &Track<'_>) -> Option<String>
+	{
+		let metadata = track.metadata;
+		match self
+		{
+			Self::Title => metadata?.title.clone(),
+			Self::Artist => metadata?.artist.clone(),
+			Self::Album => metadata?.album.clone(),
+			Self::AlbumArtist => metadata?.albumArtist.clone(),
+			Self::Genre => metadata?.genre.clone(),
+			Self::Format => metadata?.format.clone(),
+			Self::FileName => track.file.file_name().map(OsStr::to_string_lossy).map(Into::into),
+			Self::Path => Some(track.file.to_string_lossy().into()),
+		}
+	}
+}
+
+impl NumberField
+{
+	fn named(name: &str) -> Option<Self>
+	{
+		Some(match name
+		{
+			"year" => Self::Year,
+			"track" => Self::Track,
+			"disc" => Self::Disc,
+			"channels" => Self::Channels,
+			"bits" => Self::BitsPerSample,
+			"samplerate" => Self::SampleRate,
+			"bitrate" => Self::BitRate,
+			"duration" => Self::Duration,
+			"plays" => Self::Plays,
+			"skips" => Self::Skips,
+			"lastplayed" => Self::LastPlayed,
+			"rating" => Self::Rating,
+			_ => return None,
+		})
+	}
+
+	fn value(self, track: &Track<'_>) -> Option<u64>
+	{
+		let metadata = track.metadata;
+		// Tracks that have never been played have no history, which is the same as having been played no times
+		let stats = track.stats.copied().unwrap_or_default();
+		match self
+		{
+			Self::Year => metadata?.year.map(u64::from),
+			Self::Track => metadata?.trackNumber.map(u64::from),
+			Self::Disc => metadata?.discNumber.map(u64::from),
+			Self::Channels => metadata?.channels.map(u64::from),
+			Self::BitsPerSample => metadata?.bitsPerSample.map(u64::from),
+			Self::SampleRate => metadata?.sampleRate.map(u64::from),
+			Self::BitRate => (track.size? * 8 / 1000).checked_div(metadata?.duration?.as_secs()),
+			Self::Duration => metadata?.duration.map(|duration| duration.as_secs()),
+			Self::Plays => Some(stats.plays.into()),
+			Self::Skips => Some(stats.skips.into()),
+			Self::Rating => Some(track.rating.map_or(0, |rating| rating.stars).into()),
+			Self::LastPlayed => Some
+			(
+				SystemTime::now().duration_since(stats.lastPlayed?).unwrap_or_default().as_secs() / SECONDS_PER_DAY
+			),
+		}
+	}
+
+	/// Parse a value for the field - running times can be given as eg `4m`, `3m30s`, `1h` or `3:30`
+	fn parse(self, value: &str) -> Option<u64>
+	{
+		match self
+		{
+			Self::Duration => parseDuration(value),
+			_ => value.parse().ok(),
+		}
+	}
+}
+
+impl Comparison
+{
+	fn parse(field: NumberField, value: &str) -> Result<Self>
+	{
+		let parse = |number: &str| field.parse(number)
+			.ok_or_else(|| eyre!("'{}' is not a valid value in a query", number));
+		let comparison = if let Some(value) = value.strip_prefix(">=")
+			{ Self::GreaterEqual(parse(value)?) }
+		else if let Some(value) = value.strip_prefix("<=")
+			{ Self::LessEqual(parse(value)?) }
+		else if let Some(value) = value.strip_prefix('>')
+			{ Self::Greater(parse(value)?) }
+		else if let Some(value) = value.strip_prefix('<')
+			{ Self::Less(parse(value)?) }
+		else if let Some(value) = value.strip_prefix('=')
+			{ Self::Equal(parse(value)?) }
+		else if let Some((low, high)) = value.split_once("..")
+			{ Self::Between(parse(low)?, parse(high)?) }
+		else
+			{ Self::Equal(parse(value)?) };
+		Ok(comparison)
+	}
+
+	const fn matches(&self, value: u64) -> bool
+	{
+		match *self
+		{
+			Self::Equal(expected) => value == expected,
+			Self::Less(limit) => value < limit,
+			Self::LessEqual(limit) => value <= limit,
+			Self::Greater(limit) => value > limit,
+			Self::GreaterEqual(limit) => value >= limit,
+			Self::Between(low, high) => value >= low && value <= high,
+		}
+	}
+}
+
+/// Parse the value for a field that's either yes or no
+fn yesOrNo(field: &str, value: &str) -> Result<bool>
+{
+	match value.to_lowercase().as_str()
+	{
+		"yes" | "true" => Ok(true),
+		"no" | "false" => Ok(false),
+		_ => Err(eyre!("The '{}' field takes yes or no, not '{}'", field, value)),
+	}
+}
+
+/// Parse a running time into seconds - either as hours, minutes and seconds with units (eg `1h2m3s`, `4m`, or just
+/// `90` for seconds), or separated by colons (eg `3:30`)
+fn parseDuration(value: &str) -> Option<u64>
+{
+	if value.contains(':')
+	{
+		return value.split(':')
+			.try_fold(0, |total: u64, part| total.checked_mul(60)?.checked_add(part.parse::<u64>().ok()?));
+	}
+
+	let mut total = 0_u64;
+	let mut number = String::new();
+	for character in value.chars()
+	{
+		if character.is_ascii_digit()
+		{
+			number.push(character);
+			continue;
+		}
+		let scale = match character
+		{
+			'h' => 3600,
+			'm' => 60,
+			's' => 1,
+			_ => return None,
+		};
+		// Times too long to count in seconds aren't valid ones
+		total = number.parse::<u64>().ok()?
+			.checked_mul(scale)
+			.and_then(|seconds| total.checked_add(seconds))?;
+		number.clear();
+	}
+	// Anything left on the end without a unit is seconds
+	if !number.is_empty()
+	{
+		total = total.checked_add(number.parse::<u64>().ok()?)?;
+	}
+	Some(total)
+}
+
+/// Split a query up into brackets, keywords and words (with the field they're for, if any)
+fn tokenise(text: &str) -> Result<Vec<Token>>
+{
+	let mut tokens = Vec::new();
+	let mut chars = text.chars().peekable();
+	while let Some(&character) = chars.peek()
+	{
+		match character
+		{
+			_ if character.is_whitespace() => { chars.next(); },
+			'(' => { chars.next(); tokens.push(Token::Open); },
+			')' => { chars.next(); tokens.push(Token::Close); },
+			_ =>
+			{
+				// A `-` on the front of a word negates it
+				if character == '-'
+				{
+					chars.next();
+					if chars.peek().is_some_and(|&next| !next.is_whitespace())
+					{
+						tokens.push(Token::Not);
+						continue;
+					}
+					tokens.push(Token::Word { field: None, text: "-".into(), quoted: false });
+					continue;
+				}
+
+				let mut field = None;
+				let mut text = String::new();
+				let mut quoted = false;
+				while let Some(&character) = chars.peek()
+				{
+					if character.is_whitespace() || character == '(' || character == ')'
+					{
+						break;
+					}
+					chars.next();
+					match character
+					{
+						'"' =>
+						{
+							quoted = true;
+							loop
+							{
+								match chars.next()
+								{
+									Some('"') => break,
+									Some(character) => text.push(character),
+									None => return Err(eyre!("Query has a quote with no closing quote")),
+								}
+							}
+						},
+						// The first `:` outside of quotes splits the field name from the value
+						':' if field.is_none() && !quoted => field = Some(mem::take(&mut text)),
+						_ => text.push(character),
+					}
+				}
+
+				tokens.push
+				(
+					match text.as_str()
+					{
+						"AND" if field.is_none() && !quoted => Token::And,
+						"OR" if field.is_none() && !quoted => Token::Or,
+						"NOT" if field.is_none() && !quoted => Token::Not,
+						_ => Token::Word { field, text, quoted },
+					}
+				);
+			},
+		}
+	}
+	Ok(tokens)
+}
+
+/// Recursive descent parser for queries - `OR` binds loosest, then `AND`, then `NOT`
+struct Parser<'a>
+{
+	tokens: Peekable<vec::IntoIter<Token>>,
+	saved: &'a BTreeMap<String, String>,
+	/// The saved queries being expanded to get here, so ones that use themselves can be caught
+	expanding: &'a mut Vec<String>,
+}
+
+impl<'a> Parser<'a>
+{
+	fn parse(text: &str, saved: &'a BTreeMap<String, String>, expanding: &'a mut Vec<String>) -> Result<Expression>
+	{
+		let mut parser = Parser { tokens: tokenise(text)?.into_iter().peekable(), saved, expanding };
+		if parser.tokens.peek().is_none()
+		{
+			return Ok(Expression::All);
+		}
+		let expression = parser.or()?;
+		match parser.tokens.next()
+		{
+			None => Ok(expression),
+			Some(Token::Close) => Err(eyre!("Query has a ')' with no matching '('")),
+			Some(_) => Err(eyre!("Query has something unexpected in it")),
+		}
+	}
+
+	fn or(&mut self) -> Result<Expression>
+	{
+		let mut expression = self.and()?;
+		while self.tokens.next_if_eq(&Token::Or).is_some()
+		{
+			expression = Expression::Or(Box::new(expression), Box::new(self.and()?));
+		}
+		Ok(expression)
+	}
+
+	fn and(&mut self) -> Result<Expression>
+	{
+		let mut expression = self.not()?;
+		loop
+		{
+			// Terms one after the other without anything between them are ANDed together too
+			match self.tokens.peek()
+			{
+				None | Some(Token::Close | Token::Or) => break,
+				Some(Token::And) => { self.tokens.next(); },
+				Some(_) => {},
+			}
+			expression = Expression::And(Box::new(expression), Box::new(self.not()?));
+		}
+		Ok(expression)
+	}
+
+	fn not(&mut self) -> Result<Expression>
+	{
+		if self.tokens.next_if_eq(&Token::Not).is_some()
+		{
+			return Ok(Expression::Not(Box::new(self.not()?)));
+		}
+		self.atom()
+	}
+
+	fn atom(&mut self) -> Result<Expression>
+	{
+		match self.tokens.next()
+		{
+			Some(Token::Open) =>
+			{
+				let expression = self.or()?;
+				match self.tokens.next()
+				{
+					Some(Token::Close) => Ok(expression),
+					_ => Err(eyre!("Query has a '(' with no matching ')'")),
+				}
+			},
+			Some(Token::Word { field: Some(field), text, .. }) => Self::fieldTerm(&field, &text).map(Expression::Term),
+			Some(Token::Word { field: None, text, quoted }) =>
+			{
+				match text.strip_prefix('@')
+				{
+					Some(name) if !quoted => self.savedQuery(name),
+					_ => Ok(Expression::Term(Term::Text(text.to_lowercase()))),
+				}
+			},
+			Some(Token::Close) => Err(eyre!("Query has a ')' with no matching '('")),
+			Some(Token::And | Token::Or) => Err(eyre!("Query has AND or OR without a term before it")),
+			Some(Token::Not) | None => Err(eyre!("Query ends where a term was expected")),
+		}
+	}
+
+	fn fieldTerm(field: &str, value: &str) -> Result<Term>
+	{
+		let field = field.to_lowercase();
+		if value.is_empty()
+		{
+			return Err(eyre!("Query has no value for the '{}' field", field));
+		}
+
+		if let Some(textField) = TextField::named(&field)
+		{
+			let (text, exact) = value.strip_prefix('=').map_or((value, false), |text| (text, true));
+			return Ok(Term::Field { field: textField, text: text.to_lowercase(), exact });
+		}
+		if let Some(numberField) = NumberField::named(&field)
+		{
+			return Comparison::parse(numberField, value).map(|comparison| Term::Number(numberField, comparison));
+		}
+		match field.as_str()
+		{
+			"lossless" => yesOrNo(&field, value).map(Term::Lossless),
+			"favourite" | "favorite" => yesOrNo(&field, value).map(Term::Favourite),
+			_ => Err(eyre!("Query uses unknown field '{}'", field)),
+		}
+	}
+
+	/// Use a saved query as a term in this one
+	fn savedQuery(&mut self, name: &str) -> Result<Expression>
+	{
+		let text = self.saved.get(name)
+			.ok_or_else(|| eyre!("No saved query named '{}'", name))?;
+		if self.expanding.iter().any(|expanding| expanding == name)
+		{
+			return Err(eyre!("Saved query '{}' uses itself", name));
+		}
+		self.expanding.push(name.to_string());
+		let expression = Parser::parse(text, self.saved, self.expanding);
+		self.expanding.pop();
+		expression
+	}
+}
+
+#[cfg(test)]
+mod tests
+{
+	use std::collections::BTreeMap;
+	use std::path::Path;
+	use std::time::Duration;
+
+	use super::{parseDuration, Query, Track};
+	use crate::metadata::TrackMetadata;
+
+	fn metadata() -> TrackMetadata
+	{
+		TrackMetadata
+		{
+			title: Some("Mein Herz brennt".into()),
+			artist: Some("Rammstein".into()),
+			album: Some("Mutter".into()),
+			year: Some(2001),
+			duration: Some(Duration::from_secs(280)),
+			format: Some("FLAC".into()),
+			..TrackMetadata::default()
+		}
+	}
+
+	fn track(metadata: &TrackMetadata) -> Track<'_>
+	{
+		Track
+		{
+			file: Path::new("/music/Rammstein/Mutter/01 Mein Herz brennt.flac"),
+			metadata: Some(metadata),
+			size: Some(35_000_000),
+			stats: None,
+			rating: None,
+		}
+	}
+
+	fn matchesWith(query: &str, saved: &BTreeMap<String, String>) -> bool
+	{
+		let metadata = metadata();
+		Query::parse(query, saved)
+			.unwrap_or_else(|error| panic!("query {query} failed to parse: {error}"))
+			.matches(&track(&metadata))
+	}
+
+	fn matches(query: &str) -> bool
+	{
+		matchesWith(query, &BTreeMap::new())
+	}
+
+	fn fails(query: &str) -> bool
+	{
+		Query::parse(query, &BTreeMap::new()).is_err()
+	}
+
+	fn saved(queries: &[(&str, &str)]) -> BTreeMap<String, String>
+	{
+		queries
+			.iter()
+			.map(|&(name, query)| (name.to_string(), query.to_string()))
+			.collect()
+	}
+
+	#[test]
+	fn emptyQueryMatchesEverything()
+	{
+		assert!(matches(""));
+		assert!(matches("   "));
+	}
+
+	#[test]
+	fn textAndFields()
+	{
+		assert!(matches("herz"));
+		assert!(matches("HERZ mutter"));
+		assert!(!matches("sonne"));
+		assert!(matches("artist:rammstein"));
+		assert!(matches("ARTIST:ramm"));
+		assert!(matches("artist:=rammstein"));
+		assert!(!matches("artist:=ramm"));
+		assert!(matches("filename:01"));
+		assert!(matches("path:/music/"));
+		assert!(matches("format:flac lossless:yes"));
+		assert!(!matches("lossless:no"));
+		assert!(!matches("favourite:yes"));
+		assert!(matches("favorite:no"));
+	}
+
+	#[test]
+	fn andBindsTighterThanOr()
+	{
+		assert!(matches("artist:abba OR artist:rammstein year:2001"));
+		assert!(!matches("artist:abba OR artist:rammstein year:1999"));
+		assert!(matches("artist:rammstein OR artist:abba year:1999"));
+		assert!(!matches("(artist:rammstein OR artist:abba) year:1999"));
+		assert!(matches("artist:rammstein AND year:2001"));
+		assert!(!matches("artist:rammstein AND year:1999"));
+	}
+
+	#[test]
+	fn negation()
+	{
+		assert!(!matches("-artist:rammstein"));
+		assert!(matches("-artist:abba"));
+		assert!(matches("NOT artist:abba artist:rammstein"));
+		assert!(matches("-artist:rammstein OR year:2001"));
+		assert!(!matches("NOT (artist:rammstein year:2001)"));
+		assert!(matches("NOT NOT artist:rammstein"));
+		// Tracks without what a term looks at don't match it, so do match it negated
+		assert!(!matches("genre:live"));
+		assert!(matches("-genre:live"));
+	}
+
+	#[test]
+	fn quoting()
+	{
+		assert!(matches("\"herz brennt\""));
+		assert!(matches("title:\"herz brennt\""));
+		assert!(!matches("title:\"brennt herz\""));
+		// Quoted keywords are just words, and quoted colons don't start a field
+		assert!(!matches("\"AND\""));
+		assert!(!matches("\"artist:rammstein\""));
+		assert!(fails("AND"));
+		assert!(fails("title:\"herz"));
+	}
+
+	#[test]
+	fn numbers()
+	{
+		assert!(matches("year:2001"));
+		assert!(matches("year:=2001"));
+		assert!(matches("year:>2000"));
+		assert!(!matches("year:>2001"));
+		assert!(matches("year:>=2001"));
+		assert!(matches("year:<=2001"));
+		assert!(!matches("year:<2001"));
+		assert!(matches("year:2000..2002"));
+		assert!(!matches("year:1990..2000"));
+		assert!(matches("duration:4m..5m"));
+		assert!(matches("duration:>=4:40"));
+		assert!(!matches("duration:>4:40"));
+		assert!(matches("bitrate:>900"));
+		assert!(matches("plays:0 skips:0 rating:0"));
+		assert!(!matches("lastplayed:<1"));
+	}
+
+	#[test]
+	fn malformedQueries()
+	{
+		assert!(fails("(artist:rammstein"));
+		assert!(fails("artist:rammstein)"));
+		assert!(fails("artist:rammstein OR"));
+		assert!(fails("OR artist:rammstein"));
+		assert!(fails("NOT"));
+		assert!(fails("title:"));
+		assert!(fails("colour:red"));
+		assert!(fails("year:abc"));
+		assert!(fails("year:1990..abc"));
+		assert!(fails("lossless:maybe"));
+		assert!(fails("duration:>99999999999999999999m"));
+		assert!(fails("duration:>10000000000000000000m"));
+	}
+
+	#[test]
+	fn savedQueries()
+	{
+		let saved = saved(&[("german", "artist:rammstein OR genre:neue"), ("recent", "@german year:>2000")]);
+		assert!(matchesWith("@german", &saved));
+		assert!(matchesWith("@recent", &saved));
+		assert!(!matchesWith("-@recent", &saved));
+		assert!(!matchesWith("@recent year:<2001", &saved));
+		// Using the same saved query more than once isn't it using itself
+		assert!(matchesWith("@german @german", &saved));
+		// Quoting stops a word being taken as a saved query
+		assert!(!matchesWith("\"@german\"", &saved));
+		assert!(Query::parse("@missing", &saved).is_err());
+	}
+
+	#[test]
+	fn savedQueryCycles()
+	{
+		let saved = saved(&[("loop", "title:x OR @loop"), ("a", "@b"), ("b", "year:2001 @a")]);
+		assert!(Query::parse("@loop", &saved).is_err());
+		assert!(Query::parse("@a", &saved).is_err());
+		assert!(Query::parse("year:2001 OR @b", &saved).is_err());
+	}
+
+	#[test]
+	fn durations()
+	{
+		assert_eq!(parseDuration("90"), Some(90));
+		assert_eq!(parseDuration("4m"), Some(240));
+		assert_eq!(parseDuration("3m30s"), Some(210));
+		assert_eq!(parseDuration("1h"), Some(3600));
+		assert_eq!(parseDuration("1h2m3s"), Some(3723));
+		assert_eq!(parseDuration("3:30"), Some(210));
+		assert_eq!(parseDuration("1:02:03"), Some(3723));
+		assert_eq!(parseDuration("4x"), None);
+		assert_eq!(parseDuration("3::30"), None);
+		assert_eq!(parseDuration("3:3a"), None);
+	}
+
+	#[test]
+	fn durationsTooLongToCount()
+	{
+		assert_eq!(parseDuration("18446744073709551615"), Some(u64::MAX));
+		assert_eq!(parseDuration("99999999999999999999"), None);
+		assert_eq!(parseDuration("18446744073709551615h"), None);
+		assert_eq!(parseDuration("10000000000000000000m"), None);
+		assert_eq!(parseDuration("18446744073709551615s1s"), None);
+		assert_eq!(parseDuration("18446744073709551615:00"), None);
+	}
+
+	#[test]
+	fn plainSearches()
+	{
+		assert!(Query::isPlain("herz brennt"));
+		assert!(!Query::isPlain("artist:rammstein"));
+		assert!(!Query::isPlain("herz OR brennt"));
+		assert!(!Query::isPlain("-herz"));
+		assert!(!Query::isPlain("@german"));
+	}
+}
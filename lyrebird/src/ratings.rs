@@ -0,0 +1,447 @@
+// SPDX-License-Identifier: BSD-3-Clause
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use color_eyre::eyre::Result;
+use crossterm::event::KeyCode;
+use directories::ProjectDirs;
+use serde::{Deserialize, Serialize};
+use serde_repr::{Deserialize_repr, Serialize_repr};
+use tokio_util::sync::CancellationToken;
+use tracing::error;
+
+use crate::library::MusicLibrary;
+use crate::persistence::{writeJsonAtomically, StoredVersion};
+
+/// Most stars a track can be given
+pub const MAX_STARS: u8 = 5;
+/// How much of the start and end of a file go into its fingerprint
+const FINGERPRINT_SAMPLE: u64 = 64 * 1024;
+
+/// The user's star ratings for tracks and which they've marked as favourites, kept in the data directory
+#[derive(Serialize, Deserialize)]
+pub struct Ratings
+{
+	version: RatingsVersion,
+	tracks: TrackRatings,
+	/// Copy of the ratings that can be handed out and held on to while they're changed
+	#[serde(skip)]
+	shared: Arc<TrackRatings>,
+	/// Count of changes made to the ratings since they were read in, so anything worked out from them can tell
+	/// when it needs working out again
+	#[serde(skip)]
+	generation: u64,
+	/// Which version of the library tracks that have moved were last looked for in
+	#[serde(skip)]
+	relinkedFor: Option<u64>,
+	/// Tracks rated since their fingerprints were last taken, waiting for that to be done in the background
+	#[serde(skip)]
+	unfingerprinted: BTreeSet<PathBuf>,
+	/// Whether the ratings on disk were written by a newer version of Lyrebird, and so mustn't be overwritten
+	#[serde(skip)]
+	newerOnDisk: bool,
+}
+
+#[derive(Serialize_repr, Deserialize_repr, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+#[repr(u8)]
+pub enum RatingsVersion
+{
+	Version1 = 1,
+}
+
+impl RatingsVersion
+{
+	const CURRENT: Self = Self::Version1;
+}
+
+/// The ratings for each track that has one, by path
+#[derive(Clone, Default, Serialize, Deserialize)]
+pub struct TrackRatings(BTreeMap<PathBuf, TrackRating>);
+
+#[derive(Clone, Copy, Default, Serialize, Deserialize)]
+pub struct TrackRating
+{
+	/// How many stars out of `MAX_STARS` the track has been given, 0 being unrated
+	pub stars: u8,
+	#[serde(default)]
+	pub favourite: bool,
+	/// What the file looked like when it was last rated, so the rating can follow it if it's moved
+	#[serde(default)]
+	fingerprint: Option<Fingerprint>,
+}
+
+/// Identifies a file by what's in it rather than where it is - made from its size and a hash of its start and end,
+/// as hashing the whole of every file would take far too long
+#[derive(Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Fingerprint
+{
+	size: u64,
+	hash: u64,
+}
+
+/// Looking for where rated tracks that have gone missing from the library have moved to. This means looking inside
+/// files, which can take a while, so it's set up from the ratings and library and then run in the background
+pub struct Relink
+{
+	/// The rated tracks no longer in the library, and what they looked like
+	missing: Vec<(PathBuf, Fingerprint)>,
+	/// Unrated files in the library that could be them, by size
+	candidates: BTreeMap<u64, Vec<PathBuf>>,
+}
+
+/// Taking the fingerprints of tracks that have just been rated. This means reading from each of them, which can
+/// take a while on slow disks, so it's run in the background rather than holding up the rating
+pub struct Fingerprinting
+{
+	files: Vec<PathBuf>,
+}
+
+/// How the user's asked for the ratings of some tracks to be changed
+#[derive(Clone, Copy)]
+pub enum RatingChange
+{
+	Raise,
+	Lower,
+	/// Make the tracks favourites, or if they all already are, make them not
+	ToggleFavourite,
+}
+
+impl RatingChange
+{
+	/// Figure out which change to the ratings a key asks for, if any - these are the same in every list of tracks
+	pub const fn forKey(key: KeyCode) -> Option<Self>
+	{
+		match key
+		{
+			KeyCode::Char(']') => Some(Self::Raise),
+			KeyCode::Char('[') => Some(Self::Lower),
+			KeyCode::Char('f') => Some(Self::ToggleFavourite),
+			_ => None,
+		}
+	}
+}
+
+impl Ratings
+{
+	pub fn new() -> Self
+	{
+		Self
+		{
+			version: RatingsVersion::CURRENT,
+			tracks: TrackRatings::default(),
+			shared: Arc::default(),
+			generation: 0,
+			relinkedFor: None,
+			unfingerprinted: BTreeSet::new(),
+			newerOnDisk: false,
+		}
+	}
+
+	/// Read the user's ratings back in from the data directory, or start afresh if nothing's been rated yet (or
+	/// what's there can't be made sense of)
+	pub fn read(paths: &ProjectDirs) -> Result<Self>
+	{
+		let ratingsPath = paths.data_dir().join("ratings.json");
+
+		if !ratingsPath.exists()
+		{
+			return Ok(Self::new());
+		}
+
+		let contents = fs::read(&ratingsPath)?;
+		// If a newer version of Lyrebird wrote the ratings, we can't understand them, but mustn't lose them either
+		if serde_json::from_slice::<StoredVersion>(&contents)
+			.is_ok_and(|stored| stored.version > RatingsVersion::CURRENT as u8)
+		{
+			error!("Ratings in {} are from a newer version of Lyrebird, ignoring them", ratingsPath.display());
+			let mut ratings = Self::new();
+			ratings.newerOnDisk = true;
+			return Ok(ratings);
+		}
+
+		match serde_json::from_slice::<Self>(&contents)
+		{
+			Ok(mut ratings) =>
+			{
+				ratings.shared = Arc::new(ratings.tracks.clone());
+				Ok(ratings)
+			},
+			// If the ratings can't be read (eg, they got mangled), start afresh rather than refusing to start at
+			// all, keeping what was there aside in case it can be rescued by hand
+			Err(report) =>
+			{
+				let asidePath = ratingsPath.with_extension("json.bad");
+				error!
+				(
+					"Reading ratings {} failed, moving it to {}: {}",
+					ratingsPath.display(), asidePath.display(), report
+				);
+				if let Err(report) = fs::rename(&ratingsPath, &asidePath)
+				{
+					error!("Failed to move {} aside: {}", ratingsPath.display(), report);
+				}
+				Ok(Self::new())
+			},
+		}
+	}
+
+	pub fn write(&self, paths: &ProjectDirs) -> Result<()>
+	{
+		// Ratings from a newer version of Lyrebird were already complained about when read, and mustn't be
+		// overwritten, so there's nothing to do here
+		if self.newerOnDisk
+		{
+			return Ok(());
+		}
+		writeJsonAtomically(&paths.data_dir().join("ratings.json"), self)
+	}
+
+	/// Get the ratings for each track, as a handle that can be held on to while they're changed
+	pub fn ratings(&self) -> Arc<TrackRatings>
+	{
+		self.shared.clone()
+	}
+
+	/// Get how many changes have been made to the ratings since they were read in, which only ever goes up
+	pub const fn generation(&self) -> u64
+	{
+		self.generation
+	}
+
+	/// Change the ratings of some tracks
+	pub fn change(&mut self, files: &[PathBuf], change: RatingChange)
+	{
+		// Work out which way toggling goes before any of the tracks get changed
+		let favourite = files.iter().any(|file| !self.tracks.get(file).is_some_and(|rating| rating.favourite));
+		for file in files
+		{
+			let rating = self.tracks.0.entry(file.clone()).or_default();
+			match change
+			{
+				RatingChange::Raise => rating.stars = (rating.stars + 1).min(MAX_STARS),
+				RatingChange::Lower => rating.stars = rating.stars.saturating_sub(1),
+				RatingChange::ToggleFavourite => rating.favourite = favourite,
+			}
+			// A track that's back to being unrated and not a favourite needs nothing remembering about it
+			if rating.stars == 0 && !rating.favourite
+			{
+				self.tracks.0.remove(file);
+				self.unfingerprinted.remove(file);
+				continue;
+			}
+			// Take the file's fingerprint afresh in case it's changed (eg, been re-tagged) since it was last rated
+			self.unfingerprinted.insert(file.clone());
+		}
+		self.changed();
+	}
+
+	/// Set up taking the fingerprints of the tracks rated since that was last done, if there are any
+	pub fn fingerprint(&mut self) -> Option<Fingerprinting>
+	{
+		if self.unfingerprinted.is_empty()
+		{
+			return None;
+		}
+		let files = std::mem::take(&mut self.unfingerprinted).into_iter().collect();
+		Some(Fingerprinting { files })
+	}
+
+	/// Store the fingerprints taken of tracks that were rated, given as pairs of track and fingerprint
+	pub fn fingerprinted(&mut self, fingerprints: Vec<(PathBuf, Fingerprint)>)
+	{
+		for (file, fingerprint) in fingerprints
+		{
+			// The track might have been unrated while its fingerprint was being taken, so only keep it if not
+			if let Some(rating) = self.tracks.0.get_mut(&file)
+			{
+				rating.fingerprint = Some(fingerprint);
+			}
+		}
+	}
+
+	/// Set up looking for where tracks that are rated but no longer in the library have moved to, if the library's
+	/// changed since that was last done and there are any such tracks
+	pub fn relink(&mut self, library: &MusicLibrary) -> Option<Relink>
+	{
+		if self.relinkedFor == Some(library.generation())
+		{
+			return None;
+		}
+		self.relinkedFor = Some(library.generation());
+
+		// Rated tracks whose fingerprints never got taken (eg, because we exited first) can't be followed if they
+		// move, so have those taken while we're here
+		let unfingerprinted: Vec<_> = self.tracks.0
+			.iter()
+			.filter(|(file, rating)| rating.fingerprint.is_none() && library.trackMetadata(file).is_some())
+			.map(|(file, _)| file.clone())
+			.collect();
+		self.unfingerprinted.extend(unfingerprinted);
+
+		let missing: Vec<_> = self.tracks.0
+			.iter()
+			.filter(|(file, _)| library.trackMetadata(file).is_none())
+			.filter_map(|(file, rating)| Some((file.clone(), rating.fingerprint?)))
+			.collect();
+		if missing.is_empty()
+		{
+			return None;
+		}
+
+		// Only files the same size as a missing track could be it, so only those need looking inside of
+		let sizes: BTreeSet<_> = missing.iter().map(|(_, fingerprint)| fingerprint.size).collect();
+		let mut candidates = BTreeMap::<u64, Vec<PathBuf>>::new();
+		for file in library.allFiles().filter(|file| self.tracks.get(file).is_none())
+		{
+			if let Some(size) = library.fileSize(file).filter(|size| sizes.contains(size))
+			{
+				candidates.entry(size).or_default().push(file.to_path_buf());
+			}
+		}
+		Some(Relink { missing, candidates })
+	}
+
+	/// Carry the ratings of tracks that have moved over to where they moved to, given as pairs of where each track
+	/// was and where it is now. Returns whether any were
+	pub fn relinked(&mut self, moves: Vec<(PathBuf, PathBuf)>) -> bool
+	{
+		let mut relinked = false;
+		for (file, movedTo) in moves
+		{
+			// The ratings might have changed while the tracks were being looked for, so make sure nothing gets lost
+			if self.tracks.get(&movedTo).is_some()
+			{
+				continue;
+			}
+			if let Some(rating) = self.tracks.0.remove(&file)
+			{
+				self.tracks.0.insert(movedTo, rating);
+				relinked = true;
+			}
+		}
+		if relinked
+		{
+			self.changed();
+		}
+		relinked
+	}
+
+	/// The ratings have changed, so update the copy handed out and let everything using them know
+	fn changed(&mut self)
+	{
+		self.shared = Arc::new(self.tracks.clone());
+		self.generation += 1;
+	}
+}
+
+impl Default for Ratings
+{
+	fn default() -> Self
+	{
+		Self::new()
+	}
+}
+
+impl Relink
+{
+	/// Look inside the files that could be the missing tracks to find which they are, returning pairs of where each
+	/// track that was found was and where it is now. Stops early with what's been found so far if asked to
+	pub fn run(mut self, cancellation: &CancellationToken) -> Vec<(PathBuf, PathBuf)>
+	{
+		let mut moves = Vec::new();
+		for (file, fingerprint) in self.missing
+		{
+			if cancellation.is_cancelled()
+			{
+				break;
+			}
+			let Some(sameSize) = self.candidates.get_mut(&fingerprint.size)
+				else { continue; };
+			let Some(index) = sameSize.iter()
+				.position(|candidate| Fingerprint::of(candidate).is_ok_and(|candidate| candidate == fingerprint))
+				else { continue; };
+			moves.push((file, sameSize.swap_remove(index)));
+		}
+		moves
+	}
+}
+
+impl Fingerprinting
+{
+	/// Take the fingerprint of each of the tracks, returning pairs of track and fingerprint for those it could be
+	/// taken of. Stops early with what's been taken so far if asked to
+	pub fn run(self, cancellation: &CancellationToken) -> Vec<(PathBuf, Fingerprint)>
+	{
+		let mut fingerprints = Vec::new();
+		for file in self.files
+		{
+			if cancellation.is_cancelled()
+			{
+				break;
+			}
+			match Fingerprint::of(&file)
+			{
+				Ok(fingerprint) => fingerprints.push((file, fingerprint)),
+				Err(error) => error!("Failed to fingerprint {}: {}", file.display(), error),
+			}
+		}
+		fingerprints
+	}
+}
+
+impl TrackRatings
+{
+	pub fn get(&self, file: &Path) -> Option<&TrackRating>
+	{
+		self.0.get(file)
+	}
+}
+
+impl TrackRating
+{
+	/// Describe the rating for display, as stars out of the most there can be followed by a heart for favourites
+	pub fn describe(&self) -> String
+	{
+		let mut description = String::new();
+		if self.stars > 0
+		{
+			description.extend((0..MAX_STARS).map(|star| if star < self.stars { '★' } else { '☆' }));
+		}
+		if self.favourite
+		{
+			description.push('♥');
+		}
+		description
+	}
+}
+
+impl Fingerprint
+{
+	fn of(file: &Path) -> Result<Self>
+	{
+		let mut handle = File::open(file)?;
+		let size = handle.metadata()?.len();
+
+		// Take the start of the file, and the end too if there's any more to it
+		let mut sample = Vec::new();
+		(&mut handle).take(FINGERPRINT_SAMPLE).read_to_end(&mut sample)?;
+		if size > FINGERPRINT_SAMPLE
+		{
+			handle.seek(SeekFrom::Start((size - FINGERPRINT_SAMPLE).max(FINGERPRINT_SAMPLE)))?;
+			handle.take(FINGERPRINT_SAMPLE).read_to_end(&mut sample)?;
+		}
+		Ok(Self { size, hash: fnv1a(&sample) })
+	}
+}
+
+/// 64-bit FNV-1a hash - used rather than the standard library's hasher as that isn't guaranteed to give the same
+/// answer from one build to the next, and fingerprints are kept between runs
+fn fnv1a(data: &[u8]) -> u64
+{
+	data.iter()
+		.fold(0xcbf2_9ce4_8422_2325, |hash, &byte| (hash ^ u64::from(byte)).wrapping_mul(0x0100_0000_01b3))
+}
@@ -0,0 +1,505 @@
+// SPDX-License-Identifier: BSD-3-Clause
+use std::borrow::Cow;
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, BTreeSet};
+use std::ffi::OsStr;
+use std::mem;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+
+use crossterm::event::{KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
+use ratatui::buffer::Buffer;
+use ratatui::layout::{Alignment, Constraint, Layout, Rect};
+use ratatui::style::{Modifier, Style, Stylize};
+use ratatui::text::{Line, Span};
+use ratatui::widgets::{Block, BorderType, Clear, List, ListItem, ListState, Padding, ScrollbarState, Widget};
+
+use crate::fuzzyMatch::fuzzyMatch;
+use crate::history::TrackStatistics;
+use crate::library::MusicLibrary;
+use crate::naturalOrder::naturalPathCmp;
+use crate::query::Query;
+use crate::ratings::{RatingChange, TrackRatings};
+use crate::widgets::marks::Marks;
+use crate::widgets::{centredArea, renderScrolledList};
+use crate::window::Operation;
+
+/// Most results to list - past this, typing more of the search is the way to find things
+const MAX_RESULTS: usize = 1000;
+/// Style the characters that matched the search are picked out with
+const MATCH_STYLE: Style = Style::new().add_modifier(Modifier::BOLD.union(Modifier::UNDERLINED));
+
+/// Popup for finding tracks in the library, filtering it down as the user types. Plain words are matched fuzzily,
+/// and anything more (fields, operators, quotes) is taken as a query
+pub struct Search
+{
+	activeEntry: Style,
+	focus: Focus,
+	query: String,
+	/// The queries the user has saved, for using in searches as `@name`
+	savedQueries: BTreeMap<String, String>,
+	/// Play counts and the like, for queries that look at those, as of when the search was opened
+	statistics: Arc<TrackStatistics>,
+	/// The user's ratings, likewise
+	ratings: Arc<TrackRatings>,
+	/// What's wrong with the search, if it's a query that doesn't parse
+	error: Option<String>,
+	results: Vec<Hit>,
+	/// Every track that matched the plain search last run (not just the results listed), so that typing more of it
+	/// only has to look through those again rather than the whole library
+	matched: Vec<PathBuf>,
+	/// The plain search `matched` is for
+	searched: String,
+	/// Which version of the library the results were found in
+	generation: Option<u64>,
+	resultsState: ListState,
+	resultsScrollbar: ScrollbarState,
+	resultMarks: Marks,
+	/// How many results fit in the popup, as of when it was last drawn
+	viewportHeight: u16,
+
+	library: Arc<RwLock<MusicLibrary>>,
+}
+
+/// Which part of the popup keys go to
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Focus
+{
+	Query,
+	Results,
+}
+
+/// The parts of a track that get searched
+#[derive(Clone, Copy)]
+enum Field
+{
+	FileName,
+	Title,
+	Artist,
+	Album,
+}
+
+impl Field
+{
+	const ALL: [Self; 4] = [Self::FileName, Self::Title, Self::Artist, Self::Album];
+
+	const fn index(self) -> usize
+	{
+		self as usize
+	}
+}
+
+/// A track that matched the search, with the text of each of its fields and which characters in them matched
+struct Hit
+{
+	file: PathBuf,
+	score: usize,
+	fields: [(String, BTreeSet<usize>); Field::ALL.len()],
+}
+
+/// What happened as a result of a key event on the search
+pub enum SearchOutcome
+{
+	/// The user's still searching
+	Pending,
+	/// The user's done with the search without picking anything
+	Closed,
+	/// The user wants something done with what they found, and to carry on searching after
+	Act(Operation),
+	/// The user wants something done with what they found, and is done searching
+	Finish(Operation),
+}
+
+impl Search
+{
+	pub fn new(activeEntry: Style, library: Arc<RwLock<MusicLibrary>>, savedQueries: BTreeMap<String, String>,
+		statistics: Arc<TrackStatistics>, ratings: Arc<TrackRatings>) -> Self
+	{
+		Self
+		{
+			activeEntry,
+			focus: Focus::Query,
+			query: String::new(),
+			savedQueries,
+			statistics,
+			ratings,
+			error: None,
+			results: Vec::new(),
+			matched: Vec::new(),
+			searched: String::new(),
+			generation: None,
+			resultsState: ListState::default(),
+			resultsScrollbar: ScrollbarState::default(),
+			resultMarks: Marks::default(),
+			viewportHeight: 0,
+
+			library,
+		}
+	}
+
+	pub fn handleKeyEvent(&mut self, key: &KeyEvent) -> SearchOutcome
+	{
+		if key.kind != KeyEventKind::Press && key.kind != KeyEventKind::Repeat
+		{
+			return SearchOutcome::Pending;
+		}
+
+		// Escape backs out of the results to the search text, and from there out of the search entirely
+		if key.code == KeyCode::Esc
+		{
+			if self.focus == Focus::Query
+			{
+				return SearchOutcome::Closed;
+			}
+			self.focus = Focus::Query;
+			return SearchOutcome::Pending;
+		}
+
+		// Marking results for acting on several at once
+		if self.focus == Focus::Results &&
+			self.resultMarks.handleKeyEvent(key, &mut self.resultsState, self.results.len())
+		{
+			return SearchOutcome::Pending;
+		}
+
+		// Rating the chosen results
+		if self.focus == Focus::Results
+		{
+			if let Some(change) = RatingChange::forKey(key.code)
+			{
+				return SearchOutcome::Act(Operation::rate(self.makeSelection(), change));
+			}
+		}
+
+		match (self.focus, key.code)
+		{
+			// Tab swaps between typing the search and acting on the results
+			(Focus::Query, KeyCode::Tab) if !self.results.is_empty() => self.focus = Focus::Results,
+			(Focus::Results, KeyCode::Tab) => self.focus = Focus::Query,
+			// The results can be moved through from either
+			(_, KeyCode::Up) => self.resultsState.select_previous(),
+			(_, KeyCode::Down) => self.resultsState.select_next(),
+			(_, KeyCode::PageUp) => self.resultsState.scroll_up_by(self.viewportHeight),
+			(_, KeyCode::PageDown) => self.resultsState.scroll_down_by(self.viewportHeight),
+			// Playing the chosen results works from either too, being done with the search
+			(_, KeyCode::Enter) | (Focus::Results, KeyCode::Char('p')) =>
+				{ return SearchOutcome::Finish(Operation::play(self.makeSelection())); },
+			// Saving the search as a query for later, so long as it is one
+			(Focus::Query, KeyCode::Char('s')) if key.modifiers.contains(KeyModifiers::CONTROL) =>
+			{
+				if !self.query.trim().is_empty() && self.error.is_none()
+				{
+					return SearchOutcome::Act(Operation::SaveQuery(self.query.clone()));
+				}
+			},
+			(Focus::Query, KeyCode::Backspace) =>
+			{
+				self.query.pop();
+				self.refresh();
+			},
+			// Only take characters that weren't typed as part of a shortcut
+			(Focus::Query, KeyCode::Char(character))
+				if !key.modifiers.intersects(KeyModifiers::CONTROL | KeyModifiers::ALT) =>
+			{
+				self.query.push(character);
+				self.refresh();
+			},
+			// Otherwise acting on the chosen results in the same way as the other tabs
+			(Focus::Results, KeyCode::Char('+')) =>
+				{ return SearchOutcome::Act(Operation::playlist(self.makeSelection())); },
+			(Focus::Results, KeyCode::Char('i')) =>
+				{ return SearchOutcome::Act(Operation::insertNext(self.makeSelection())); },
+			(Focus::Results, KeyCode::Char('a')) =>
+				{ return SearchOutcome::Act(Operation::addToPlaylist(self.makeSelection())); },
+			// Jumping to where the selected result is in the Tree tab
+			(Focus::Results, KeyCode::Char('t')) =>
+			{
+				let operation = self.selectedFile().map_or(Operation::None, Operation::Reveal);
+				return SearchOutcome::Finish(operation);
+			},
+			_ => {},
+		}
+		SearchOutcome::Pending
+	}
+
+	/// Update the saved queries searches can use, such as after the user's saved another
+	pub fn useSavedQueries(&mut self, savedQueries: BTreeMap<String, String>)
+	{
+		self.savedQueries = savedQueries;
+		self.refresh();
+	}
+
+	/// Search again if the library's changed since the results were found, so they don't go stale while the search
+	/// is open, keeping the same result selected if it's still there
+	pub fn followLibrary(&mut self)
+	{
+		let generation = self.library.read().ok().map(|library| library.generation());
+		if generation == self.generation
+		{
+			return;
+		}
+		let selected = self.selectedFile();
+		// What matched before might not be all that matches now
+		self.searched.clear();
+		self.refresh();
+		if let Some(index) = selected.and_then(|file| self.results.iter().position(|hit| hit.file == file))
+		{
+			self.resultsState.select(Some(index));
+		}
+	}
+
+	/// The search text has changed, so find what matches it now, starting back at the top of the results
+	fn refresh(&mut self)
+	{
+		let Ok(library) = self.library.read()
+			else { return; };
+		self.generation = Some(library.generation());
+		let results = if Query::isPlain(&self.query)
+		{
+			// Anything that matches a search with more typed on the end also matches the search without it, so
+			// only what matched that needs looking through again
+			let previous = mem::take(&mut self.matched);
+			let narrowing = !self.searched.trim().is_empty() && self.query.starts_with(&self.searched);
+			let (matched, results) = if narrowing
+			{
+				search(&library, &self.query, previous.iter().map(PathBuf::as_path))
+			}
+			else
+			{
+				search(&library, &self.query, library.allFiles())
+			};
+			self.matched = matched;
+			self.searched.clone_from(&self.query);
+			Ok(results)
+		}
+		else
+		{
+			self.matched.clear();
+			self.searched.clear();
+			Query::parse(&self.query, &self.savedQueries).map
+			(
+				|query| library.tracksMatching(&query, &self.statistics, &self.ratings)
+					.iter()
+					.take(MAX_RESULTS)
+					.map(|file| Hit::new(&library, file, 0))
+					.collect()
+			)
+		};
+		drop(library);
+		(self.results, self.error) = match results
+		{
+			Ok(results) => (results, None),
+			Err(error) => (Vec::new(), Some(error.to_string())),
+		};
+		self.resultsState = ListState::default();
+		if !self.results.is_empty()
+		{
+			self.resultsState.select_first();
+		}
+		self.resultMarks.clear();
+	}
+
+	fn selectedFile(&self) -> Option<PathBuf>
+	{
+		self.resultsState.selected()
+			.and_then(|index| self.results.get(index))
+			.map(|hit| hit.file.clone())
+	}
+
+	/// Figure out which tracks are chosen - the marked results, or failing that, the selected one
+	fn makeSelection(&self) -> Vec<PathBuf>
+	{
+		self.resultMarks.orSelected(self.resultsState.selected())
+			.into_iter()
+			.filter_map(|index| self.results.get(index))
+			.map(|hit| hit.file.clone())
+			.collect()
+	}
+}
+
+/// Find which of the candidate tracks match a search, giving back all of them along with the results to list, best
+/// matches first. Each word of the search has to fuzzily match at least one of a track's fields, with the track
+/// scored by the best matches for the words
+fn search<'a>(library: &MusicLibrary, query: &str, candidates: impl Iterator<Item = &'a Path>)
+	-> (Vec<PathBuf>, Vec<Hit>)
+{
+	let words: Vec<_> = query.split_whitespace().collect();
+	if words.is_empty()
+	{
+		return (Vec::new(), Vec::new());
+	}
+
+	let mut results: Vec<_> = candidates
+		.filter_map(|file| matchTrack(library, file, &words))
+		.collect();
+	results.sort_by
+	(
+		|lhs, rhs| Reverse(lhs.score).cmp(&Reverse(rhs.score)).then_with(|| naturalPathCmp(&lhs.file, &rhs.file))
+	);
+	let matched = results.iter().map(|hit| hit.file.clone()).collect();
+	results.truncate(MAX_RESULTS);
+	(matched, results)
+}
+
+fn matchTrack(library: &MusicLibrary, file: &Path, words: &[&str]) -> Option<Hit>
+{
+	// Match against the fields where they are, only making a copy of them for tracks that turn out to match
+	let texts = fieldTexts(library, file);
+	let mut score = 0;
+	let mut positions: [BTreeSet<usize>; Field::ALL.len()] = Default::default();
+	for word in words
+	{
+		// Find the field the word matches best, and note which characters of it matched for displaying
+		let (field, fuzzyMatch) = Field::ALL
+			.into_iter()
+			.filter_map(|field| fuzzyMatch(word, &texts[field.index()]).map(|fuzzyMatch| (field, fuzzyMatch)))
+			.max_by_key(|(_, fuzzyMatch)| fuzzyMatch.score)?;
+		score += fuzzyMatch.score;
+		positions[field.index()].extend(fuzzyMatch.positions);
+	}
+	Some(Hit
+	{
+		file: file.to_path_buf(),
+		score,
+		fields: Field::ALL.map(|field| (texts[field.index()].to_string(), mem::take(&mut positions[field.index()]))),
+	})
+}
+
+/// Get the text of each of the fields of a track that get searched
+fn fieldTexts<'a>(library: &'a MusicLibrary, file: &'a Path) -> [Cow<'a, str>; Field::ALL.len()]
+{
+	let metadata = library.trackMetadata(file);
+	Field::ALL.map
+	(
+		|field| match field
+		{
+			Field::FileName => file.file_name().unwrap_or_else(|| OsStr::new("")).to_string_lossy(),
+			Field::Title => metadata.and_then(|metadata| metadata.title.as_deref()).unwrap_or_default().into(),
+			Field::Artist => metadata.and_then(|metadata| metadata.artist.as_deref()).unwrap_or_default().into(),
+			Field::Album => metadata.and_then(|metadata| metadata.album.as_deref()).unwrap_or_default().into(),
+		}
+	)
+}
+
+/// Split some text up into spans in the style given, picking out the characters that matched the search
+fn highlight<'a>(text: &str, matched: &BTreeSet<usize>, style: Style) -> Vec<Span<'a>>
+{
+	let styleFor = |isMatch: bool| if isMatch { style.patch(MATCH_STYLE) } else { style };
+	let mut spans = Vec::new();
+	let mut run = String::new();
+	let mut runMatched = false;
+	for (index, character) in text.chars().enumerate()
+	{
+		let isMatch = matched.contains(&index);
+		if isMatch != runMatched && !run.is_empty()
+		{
+			spans.push(Span::styled(mem::take(&mut run), styleFor(runMatched)));
+		}
+		runMatched = isMatch;
+		run.push(character);
+	}
+	if !run.is_empty()
+	{
+		spans.push(Span::styled(run, styleFor(runMatched)));
+	}
+	spans
+}
+
+impl Hit
+{
+	fn new(library: &MusicLibrary, file: &Path, score: usize) -> Self
+	{
+		let texts = fieldTexts(library, file);
+		Self
+		{
+			file: file.to_path_buf(),
+			score,
+			fields: Field::ALL.map(|field| (texts[field.index()].to_string(), BTreeSet::new())),
+		}
+	}
+
+	/// Build the line for the result - the track's title (or failing that its file name), who it's by and what
+	/// it's on, and then its file name if that's not already been shown
+	fn line(&self) -> Line<'static>
+	{
+		let [fileName, title, artist, album] = &self.fields;
+		let details = Style::new().dim();
+		let mut spans = Vec::new();
+		if title.0.is_empty()
+		{
+			spans.extend(highlight(&fileName.0, &fileName.1, Style::new()));
+		}
+		else
+		{
+			spans.extend(highlight(&title.0, &title.1, Style::new()));
+		}
+		if !artist.0.is_empty()
+		{
+			spans.push(Span::styled(" - ", details));
+			spans.extend(highlight(&artist.0, &artist.1, details));
+		}
+		if !album.0.is_empty()
+		{
+			spans.push(Span::styled(" (", details));
+			spans.extend(highlight(&album.0, &album.1, details));
+			spans.push(Span::styled(")", details));
+		}
+		if !title.0.is_empty()
+		{
+			spans.push(Span::styled(" ", details));
+			spans.extend(highlight(&fileName.0, &fileName.1, details));
+		}
+		Line::from(spans)
+	}
+}
+
+impl Widget for &mut Search
+{
+	fn render(self, area: Rect, buf: &mut Buffer)
+		where Self: Sized
+	{
+		// Take up most of the space the popup's drawn over, leaving a little of what's underneath showing
+		let area = centredArea(area, area.width.saturating_mul(4) / 5, area.height.saturating_mul(4) / 5);
+
+		// Blank out whatever is under the popup and draw its border
+		Clear.render(area, buf);
+		let title = match &self.error
+		{
+			Some(error) => format!(" Search - {error} "),
+			None if self.query.is_empty() => " Search ".to_string(),
+			None => format!(" Search - {} matches ", self.results.len()),
+		};
+		let block = Block::bordered()
+			.title(title)
+			.title_alignment(Alignment::Left)
+			.border_type(BorderType::Rounded)
+			.padding(Padding::horizontal(1));
+		let inner = block.inner(area);
+		block.render(area, buf);
+
+		// Split the popup up into the search text and the results under it
+		let layout = Layout::vertical([Constraint::Length(1), Constraint::Length(1), Constraint::Fill(1)])
+			.split(inner);
+
+		// Display the search text with a block cursor on the end of it while it's being typed
+		let queryStyle = if self.focus == Focus::Query { self.activeEntry } else { Style::default() };
+		let cursor = if self.focus == Focus::Query { Span::raw(" ").reversed() } else { Span::raw("") };
+		Line::from_iter([Span::styled("/ ", queryStyle), Span::raw(self.query.as_str()), cursor])
+			.render(layout[0], buf);
+
+		let resultItems = self.results.iter().map(|hit| ListItem::new(hit.line()));
+		let resultsList = List::new(self.resultMarks.apply(resultItems))
+			.highlight_style(if self.focus == Focus::Results { self.activeEntry } else { Style::new().reversed() });
+
+		self.viewportHeight = layout[2].height;
+		renderScrolledList
+		(
+			resultsList,
+			self.results.len(),
+			self.viewportHeight.into(),
+			layout[2],
+			buf,
+			(&mut self.resultsState, &mut self.resultsScrollbar),
+		);
+	}
+}
@@ -0,0 +1,64 @@
+// SPDX-License-Identifier: BSD-3-Clause
+use color_eyre::eyre::Result;
+#[cfg(unix)]
+use tokio::signal::unix::{signal, Signal, SignalKind};
+#[cfg(windows)]
+use tokio::signal::windows::{ctrl_close, ctrl_shutdown, CtrlClose, CtrlShutdown};
+
+/// Listens for the OS asking us to exit so we can shut down gracefully rather than being killed outright
+pub struct ExitSignals
+{
+	#[cfg(unix)]
+	terminate: Signal,
+	#[cfg(unix)]
+	hangup: Signal,
+	#[cfg(windows)]
+	close: CtrlClose,
+	#[cfg(windows)]
+	shutdown: CtrlShutdown,
+}
+
+impl ExitSignals
+{
+	#[cfg(unix)]
+	pub fn new() -> Result<Self>
+	{
+		Ok(Self
+		{
+			terminate: signal(SignalKind::terminate())?,
+			hangup: signal(SignalKind::hangup())?,
+		})
+	}
+
+	#[cfg(windows)]
+	pub fn new() -> Result<Self>
+	{
+		Ok(Self
+		{
+			close: ctrl_close()?,
+			shutdown: ctrl_shutdown()?,
+		})
+	}
+
+	/// Wait for one of the exit signals to be delivered
+	#[cfg(unix)]
+	pub async fn recv(&mut self)
+	{
+		tokio::select!
+		{
+			_ = self.terminate.recv() => {},
+			_ = self.hangup.recv() => {},
+		}
+	}
+
+	/// Wait for one of the exit signals to be delivered
+	#[cfg(windows)]
+	pub async fn recv(&mut self)
+	{
+		tokio::select!
+		{
+			_ = self.close.recv() => {},
+			_ = self.shutdown.recv() => {},
+		}
+	}
+}
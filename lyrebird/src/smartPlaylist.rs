@@ -0,0 +1,424 @@
+// SPDX-License-Identifier: BSD-3-Clause
+use std::cmp::Ordering;
+use std::collections::BTreeMap;
+use std::fmt::Display;
+use std::hash::{DefaultHasher, Hash, Hasher};
+use std::path::PathBuf;
+use std::sync::{Arc, RwLock};
+
+use serde::{Deserialize, Serialize};
+
+use crate::history::{History, TrackStatistics};
+use crate::library::MusicLibrary;
+use crate::metadata::TrackMetadata;
+use crate::naturalOrder::naturalCmp;
+use crate::playlist::Playlist;
+use crate::query::Query;
+use crate::ratings::{Ratings, TrackRatings};
+
+/// A playlist whose contents are the tracks in the library matching a query, rather than a fixed list of files.
+/// The contents are worked out again whenever the library changes
+#[derive(Serialize, Deserialize)]
+pub struct SmartPlaylist
+{
+	name: String,
+	/// The query picking out the tracks in the playlist, as the user wrote it
+	query: String,
+	#[serde(default)]
+	sortBy: SortOrder,
+	/// Whether the sort order is reversed
+	#[serde(default)]
+	descending: bool,
+	/// Most tracks to have in the playlist, if it's limited
+	#[serde(default)]
+	limit: Option<usize>,
+	/// The tracks matching the query, as of the last time they were worked out
+	#[serde(skip, default = "emptyContents")]
+	contents: Playlist,
+	/// What's wrong with the query, if it couldn't be parsed
+	#[serde(skip)]
+	error: Option<String>,
+	/// Which versions of the library, play history and ratings the contents were worked out from
+	#[serde(skip)]
+	generations: Option<(u64, u64, u64)>,
+	/// What the random order is worked out from, so it stays the same while only the play history or ratings change
+	#[serde(skip)]
+	shuffleSeed: u64,
+	/// Identifies the last time the contents were set to be worked out, so the answer can find its way back here
+	#[serde(skip)]
+	ticket: Option<u64>,
+}
+
+/// Working the contents of some smart playlists out. This means running their queries over the whole library, which
+/// can take a while, so it's set up from the playlists and then run in the background
+pub struct SmartRefresh
+{
+	library: Arc<RwLock<MusicLibrary>>,
+	statistics: Arc<TrackStatistics>,
+	ratings: Arc<TrackRatings>,
+	savedQueries: BTreeMap<String, String>,
+	/// The playlists to work out, by their tickets
+	playlists: Vec<(u64, SmartQuery)>,
+}
+
+/// Everything about a smart playlist needed to work out its contents
+struct SmartQuery
+{
+	query: String,
+	sortBy: SortOrder,
+	descending: bool,
+	limit: Option<usize>,
+	shuffleSeed: u64,
+}
+
+/// The contents worked out for a smart playlist, or what was wrong with its query
+pub struct SmartContents
+{
+	ticket: u64,
+	tracks: Result<Vec<PathBuf>, String>,
+}
+
+/// What order a smart playlist's tracks are put in
+#[derive(Clone, Copy, Default, Serialize, Deserialize)]
+pub enum SortOrder
+{
+	/// The order they'd play in going through the library's directories
+	#[default]
+	Library,
+	Title,
+	Artist,
+	Album,
+	Year,
+	Duration,
+	DateAdded,
+	PlayCount,
+	LastPlayed,
+	Rating,
+	/// Shuffled each time the library or the playlist itself changes
+	Random,
+}
+
+impl SortOrder
+{
+	/// Get the next order along, for cycling through them
+	pub const fn next(self) -> Self
+	{
+		match self
+		{
+			Self::Library => Self::Title,
+			Self::Title => Self::Artist,
+			Self::Artist => Self::Album,
+			Self::Album => Self::Year,
+			Self::Year => Self::Duration,
+			Self::Duration => Self::DateAdded,
+			Self::DateAdded => Self::PlayCount,
+			Self::PlayCount => Self::LastPlayed,
+			Self::LastPlayed => Self::Rating,
+			Self::Rating => Self::Random,
+			Self::Random => Self::Library,
+		}
+	}
+
+	/// Put tracks in this order, keeping those that compare the same in the order they were in
+	fn sort(self, tracks: &mut [PathBuf], library: &MusicLibrary, statistics: &TrackStatistics,
+		ratings: &TrackRatings, shuffleSeed: u64)
+	{
+		let compareText = |lhs: Option<&String>, rhs: Option<&String>| match (lhs, rhs)
+		{
+			(Some(lhs), Some(rhs)) => naturalCmp(lhs, rhs),
+			(lhs, rhs) => rhs.is_none().cmp(&lhs.is_none()),
+		};
+		let field = |order: Self, lhs: &TrackMetadata, rhs: &TrackMetadata| match order
+		{
+			Self::Title => compareText(lhs.title.as_ref(), rhs.title.as_ref()),
+			Self::Artist => compareText(lhs.albumArtist.as_ref().or(lhs.artist.as_ref()),
+				rhs.albumArtist.as_ref().or(rhs.artist.as_ref())),
+			Self::Album => compareText(lhs.album.as_ref(), rhs.album.as_ref()),
+			Self::Year => lhs.year.cmp(&rhs.year),
+			Self::Duration => lhs.duration.cmp(&rhs.duration),
+			Self::DateAdded => lhs.dateAdded.cmp(&rhs.dateAdded),
+			Self::Library | Self::PlayCount | Self::LastPlayed | Self::Rating | Self::Random => Ordering::Equal,
+		};
+
+		match self
+		{
+			Self::Library => {},
+			// Each track's place comes from the seed and the track alone, so tracks coming and going (eg, as they get
+			// played) don't move the rest about
+			Self::Random => tracks.sort_by_cached_key
+			(
+				|track|
+				{
+					let mut hasher = DefaultHasher::new();
+					shuffleSeed.hash(&mut hasher);
+					track.hash(&mut hasher);
+					hasher.finish()
+				}
+			),
+			// Tracks that have never been played count as played no times, and longest ago
+			Self::PlayCount => tracks.sort_by_key(|track| statistics.get(track).map_or(0, |stats| stats.plays)),
+			Self::LastPlayed => tracks.sort_by_key(|track| statistics.get(track).and_then(|stats| stats.lastPlayed)),
+			// Unrated tracks go with the lowest rated, favourites after the rest with the same rating
+			Self::Rating => tracks.sort_by_key
+			(
+				|track| ratings.get(track).map_or((0, false), |rating| (rating.stars, rating.favourite))
+			),
+			_ => tracks.sort_by
+			(
+				|lhs, rhs| match (library.trackMetadata(lhs), library.trackMetadata(rhs))
+				{
+					(Some(lhs), Some(rhs)) => field(self, lhs, rhs),
+					// Tracks we know nothing about go last
+					(lhs, rhs) => rhs.is_none().cmp(&lhs.is_none()),
+				}
+			),
+		}
+	}
+}
+
+impl Display for SortOrder
+{
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result
+	{
+		match self
+		{
+			Self::Library => write!(f, "library order"),
+			Self::Title => write!(f, "title"),
+			Self::Artist => write!(f, "artist"),
+			Self::Album => write!(f, "album"),
+			Self::Year => write!(f, "year"),
+			Self::Duration => write!(f, "duration"),
+			Self::DateAdded => write!(f, "date added"),
+			Self::PlayCount => write!(f, "play count"),
+			Self::LastPlayed => write!(f, "last played"),
+			Self::Rating => write!(f, "rating"),
+			Self::Random => write!(f, "random"),
+		}
+	}
+}
+
+impl SmartPlaylist
+{
+	pub fn new(name: String, query: String) -> Self
+	{
+		Self
+		{
+			name,
+			query,
+			sortBy: SortOrder::default(),
+			descending: false,
+			limit: None,
+			contents: emptyContents(),
+			error: None,
+			generations: None,
+			shuffleSeed: 0,
+			ticket: None,
+		}
+	}
+
+	pub fn name(&self) -> &str
+	{
+		self.name.as_str()
+	}
+
+	pub fn rename(&mut self, name: String)
+	{
+		self.name = name;
+		self.invalidate();
+	}
+
+	pub fn query(&self) -> &str
+	{
+		self.query.as_str()
+	}
+
+	/// Change the query picking out the tracks, to take effect the next time the contents are worked out
+	pub fn setQuery(&mut self, query: String)
+	{
+		self.query = query;
+		self.invalidate();
+	}
+
+	/// Switch to the next sort order along, going back to ascending order
+	pub fn cycleSortOrder(&mut self)
+	{
+		self.sortBy = self.sortBy.next();
+		self.descending = false;
+		self.invalidate();
+	}
+
+	pub fn toggleDescending(&mut self)
+	{
+		self.descending = !self.descending;
+		self.invalidate();
+	}
+
+	pub const fn limit(&self) -> Option<usize>
+	{
+		self.limit
+	}
+
+	pub fn setLimit(&mut self, limit: Option<usize>)
+	{
+		self.limit = limit;
+		self.invalidate();
+	}
+
+	/// The tracks the query matched, the last time the contents were worked out
+	pub const fn contents(&self) -> &Playlist
+	{
+		&self.contents
+	}
+
+	/// Describe how the playlist is put together, for displaying along with its contents
+	pub fn description(&self) -> String
+	{
+		if let Some(error) = &self.error
+		{
+			return format!("{} - {error}", self.name);
+		}
+		let direction = if self.descending { ", descending" } else { "" };
+		let limit = self.limit.map(|limit| format!(", first {limit}")).unwrap_or_default();
+		format!("{} - {} (by {}{direction}{limit})", self.name, self.query, self.sortBy)
+	}
+
+	/// Forget the contents were worked out, so they will be again
+	pub const fn invalidate(&mut self)
+	{
+		self.generations = None;
+	}
+
+	/// If the library, play history or ratings have changed since the contents were last worked out (or something
+	/// about the playlist has), set them to be worked out again under the ticket given
+	fn stale(&mut self, generations: (u64, u64, u64), ticket: u64) -> Option<SmartQuery>
+	{
+		if self.generations == Some(generations)
+		{
+			return None;
+		}
+		// Only shuffle again if the library or the playlist itself have changed, not just the history or ratings
+		if self.generations.is_none_or(|(libraryGeneration, ..)| libraryGeneration != generations.0)
+		{
+			self.shuffleSeed = fastrand::u64(..);
+		}
+		self.generations = Some(generations);
+		self.ticket = Some(ticket);
+		Some(SmartQuery
+		{
+			query: self.query.clone(),
+			sortBy: self.sortBy,
+			descending: self.descending,
+			limit: self.limit,
+			shuffleSeed: self.shuffleSeed,
+		})
+	}
+
+	/// Whether some contents that were worked out are for this playlist - if the playlist's been changed since they
+	/// were set to be worked out, they're out of date and so aren't
+	pub fn isFor(&self, contents: &SmartContents) -> bool
+	{
+		self.generations.is_some() && self.ticket == Some(contents.ticket)
+	}
+
+	/// Take on contents that were worked out for the playlist
+	pub fn refreshed(&mut self, contents: SmartContents)
+	{
+		self.ticket = None;
+		self.contents.rename(self.name.clone());
+		match contents.tracks
+		{
+			Ok(tracks) =>
+			{
+				self.error = None;
+				self.contents.replaceWith(&tracks);
+			},
+			Err(error) =>
+			{
+				self.error = Some(error);
+				self.contents.replaceWith(&[]);
+			},
+		}
+	}
+}
+
+impl SmartRefresh
+{
+	/// Set up working out the contents of whichever of the smart playlists are out of date, if any are. Each is
+	/// given a ticket from the counter, so the contents can be matched back up to it
+	pub fn new(playlists: &mut [SmartPlaylist], library: Arc<RwLock<MusicLibrary>>, history: &History,
+		ratings: &Ratings, savedQueries: &BTreeMap<String, String>, tickets: &mut u64) -> Option<Self>
+	{
+		let libraryGeneration = library.read().ok()?.generation();
+		let generations = (libraryGeneration, history.generation(), ratings.generation());
+		let playlists: Vec<_> = playlists
+			.iter_mut()
+			.filter_map
+			(
+				|playlist|
+				{
+					let query = playlist.stale(generations, *tickets + 1)?;
+					*tickets += 1;
+					Some((*tickets, query))
+				}
+			)
+			.collect();
+		if playlists.is_empty()
+		{
+			return None;
+		}
+		Some(Self
+		{
+			library,
+			statistics: history.statistics(),
+			ratings: ratings.ratings(),
+			savedQueries: savedQueries.clone(),
+			playlists,
+		})
+	}
+
+	/// Work out the contents of each of the playlists
+	pub fn run(self) -> Vec<SmartContents>
+	{
+		let Ok(library) = self.library.read()
+			else { return Vec::new(); };
+		self.playlists
+			.into_iter()
+			.map
+			(
+				|(ticket, query)|
+				{
+					let tracks = query.run(&library, &self.statistics, &self.ratings, &self.savedQueries);
+					SmartContents { ticket, tracks }
+				}
+			)
+			.collect()
+	}
+}
+
+impl SmartQuery
+{
+	/// Find the tracks in the library matching the query, in the order asked for and only as many as are wanted
+	fn run(&self, library: &MusicLibrary, statistics: &TrackStatistics, ratings: &TrackRatings,
+		savedQueries: &BTreeMap<String, String>) -> Result<Vec<PathBuf>, String>
+	{
+		let query = Query::parse(&self.query, savedQueries)
+			.map_err(|error| error.to_string())?;
+		let mut tracks = library.tracksMatching(&query, statistics, ratings);
+		self.sortBy.sort(&mut tracks, library, statistics, ratings, self.shuffleSeed);
+		if self.descending
+		{
+			tracks.reverse();
+		}
+		if let Some(limit) = self.limit
+		{
+			tracks.truncate(limit);
+		}
+		Ok(tracks)
+	}
+}
+
+/// The contents of a smart playlist aren't saved, so they start out empty till they're worked out
+fn emptyContents() -> Playlist
+{
+	Playlist::new(String::new())
+}
@@ -0,0 +1,296 @@
+// SPDX-License-Identifier: BSD-3-Clause
+use std::collections::BTreeMap;
+use std::fmt::Display;
+use std::path::Path;
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, SystemTime};
+
+use crossterm::event::{KeyCode, KeyEvent, KeyEventKind};
+use ratatui::buffer::Buffer;
+use ratatui::layout::{Alignment, Constraint, Layout, Rect};
+use ratatui::style::{Style, Stylize};
+use ratatui::text::{Line, Span};
+use ratatui::widgets::{Block, BorderType, List, ListItem, Padding, Widget};
+
+use crate::history::{History, PlayEventKind};
+use crate::library::{MusicLibrary, UNKNOWN_ALBUM, UNKNOWN_ARTIST};
+use crate::metadata::formatDuration;
+use crate::window::Operation;
+
+/// How many of each of the top artists, albums and tracks to list
+const TOP_COUNT: usize = 50;
+
+/// View of what's been listened to most over some period, from the play history
+pub struct Statistics
+{
+	activeEntry: Style,
+	period: Period,
+	summary: Summary,
+	/// The history and library generations and period the summary was worked out for
+	summarised: Option<(u64, u64, Period)>,
+
+	library: Arc<RwLock<MusicLibrary>>,
+}
+
+/// How far back the statistics look
+#[derive(Clone, Copy, Default, PartialEq, Eq)]
+enum Period
+{
+	Day,
+	Week,
+	#[default]
+	Month,
+	Year,
+	AllTime,
+}
+
+/// Totals for one period of the play history
+#[derive(Default)]
+struct Summary
+{
+	plays: u32,
+	listened: Duration,
+	artists: Vec<(String, Tally)>,
+	albums: Vec<(String, Tally)>,
+	tracks: Vec<(String, Tally)>,
+}
+
+/// How much something was listened to
+#[derive(Clone, Copy, Default)]
+struct Tally
+{
+	plays: u32,
+	listened: Duration,
+}
+
+impl Period
+{
+	const fn next(self) -> Self
+	{
+		match self
+		{
+			Self::Day => Self::Week,
+			Self::Week => Self::Month,
+			Self::Month => Self::Year,
+			Self::Year => Self::AllTime,
+			Self::AllTime => Self::Day,
+		}
+	}
+
+	const fn previous(self) -> Self
+	{
+		match self
+		{
+			Self::Day => Self::AllTime,
+			Self::Week => Self::Day,
+			Self::Month => Self::Week,
+			Self::Year => Self::Month,
+			Self::AllTime => Self::Year,
+		}
+	}
+
+	/// Get how long the period goes back, or None if it goes back to the start of the history
+	const fn length(self) -> Option<Duration>
+	{
+		const DAY: u64 = 24 * 60 * 60;
+		match self
+		{
+			Self::Day => Some(Duration::from_secs(DAY)),
+			Self::Week => Some(Duration::from_secs(7 * DAY)),
+			Self::Month => Some(Duration::from_secs(30 * DAY)),
+			Self::Year => Some(Duration::from_secs(365 * DAY)),
+			Self::AllTime => None,
+		}
+	}
+}
+
+impl Display for Period
+{
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result
+	{
+		match self
+		{
+			Self::Day => write!(f, "past day"),
+			Self::Week => write!(f, "past week"),
+			Self::Month => write!(f, "past 30 days"),
+			Self::Year => write!(f, "past year"),
+			Self::AllTime => write!(f, "all time"),
+		}
+	}
+}
+
+impl Tally
+{
+	fn add(&mut self, completed: bool, listened: Duration)
+	{
+		self.plays += u32::from(completed);
+		self.listened += listened;
+	}
+}
+
+impl Summary
+{
+	/// Total up the play history from the given time on, looking up who and what each track is in the library
+	fn from(history: &History, library: &MusicLibrary, since: Option<SystemTime>) -> Self
+	{
+		let mut summary = Self::default();
+		let mut artists = BTreeMap::<String, Tally>::new();
+		let mut albums = BTreeMap::<String, Tally>::new();
+		let mut tracks = BTreeMap::<&Path, Tally>::new();
+
+		for (event, listened) in history.listening()
+		{
+			if since.is_some_and(|since| event.time < since)
+			{
+				continue;
+			}
+			let completed = event.kind == PlayEventKind::Completed;
+			summary.plays += u32::from(completed);
+			summary.listened += listened;
+			// Only tracks that actually got listened to count towards the top lists
+			if !completed && listened.is_zero()
+			{
+				continue;
+			}
+
+			let metadata = library.trackMetadata(&event.file);
+			let artist = metadata.and_then(|metadata| metadata.filedArtist()).unwrap_or(UNKNOWN_ARTIST);
+			let album = metadata.and_then(|metadata| metadata.album.as_deref()).unwrap_or(UNKNOWN_ALBUM);
+			artists.entry(artist.to_string()).or_default().add(completed, listened);
+			albums.entry(format!("{album} - {artist}")).or_default().add(completed, listened);
+			tracks.entry(&event.file).or_default().add(completed, listened);
+		}
+
+		summary.artists = topOf(artists);
+		summary.albums = topOf(albums);
+		summary.tracks = topOf
+		(
+			tracks
+				.into_iter()
+				.map(|(track, tally)| (library.trackName(track), tally))
+		);
+		summary
+	}
+}
+
+/// Rank what's been listened to, most played first (and the longest listened to first where that's even)
+fn topOf(tallies: impl IntoIterator<Item = (String, Tally)>) -> Vec<(String, Tally)>
+{
+	let mut tallies: Vec<_> = tallies.into_iter().collect();
+	tallies.sort_by(|(_, lhs), (_, rhs)| rhs.plays.cmp(&lhs.plays).then(rhs.listened.cmp(&lhs.listened)));
+	tallies.truncate(TOP_COUNT);
+	tallies
+}
+
+impl Statistics
+{
+	pub fn new(activeEntry: Style, library: Arc<RwLock<MusicLibrary>>) -> Self
+	{
+		Self
+		{
+			activeEntry,
+			period: Period::default(),
+			summary: Summary::default(),
+			summarised: None,
+
+			library,
+		}
+	}
+
+	pub fn handleKeyEvent(&mut self, key: &KeyEvent) -> Operation
+	{
+		if key.kind == KeyEventKind::Press || key.kind == KeyEventKind::Repeat
+		{
+			match key.code
+			{
+				KeyCode::Left => self.period = self.period.previous(),
+				KeyCode::Right => self.period = self.period.next(),
+				_ => {},
+			}
+		}
+		Operation::None
+	}
+
+	/// Total the play history up again if it, the library or the period looked at have changed since it last was
+	pub fn refresh(&mut self, history: &History)
+	{
+		let Ok(library) = self.library.read()
+			else { return; };
+		let current = (history.generation(), library.generation(), self.period);
+		if self.summarised == Some(current)
+		{
+			return;
+		}
+
+		let since = self.period.length().and_then(|length| SystemTime::now().checked_sub(length));
+		self.summary = Summary::from(history, &library, since);
+		self.summarised = Some(current);
+	}
+}
+
+/// Build a list of the top artists, albums or tracks in a bordered block for presentation
+fn topList<'a>(title: &'a str, tallies: &'a [(String, Tally)]) -> List<'a>
+{
+	let items = tallies
+		.iter()
+		.enumerate()
+		.map
+		(
+			|(rank, (name, tally))|
+			{
+				let plays = if tally.plays == 1 { "1 play".to_string() } else { format!("{} plays", tally.plays) };
+				ListItem::new
+				(
+					Line::from_iter
+					([
+						Span::from(format!("{:>3}. ", rank + 1)).dim(),
+						Span::from(name.as_str()),
+						Span::from(format!(" ({plays}, {})", formatDuration(tally.listened))).dim(),
+					])
+				)
+			}
+		);
+	List::new(items)
+		.block
+		(
+			Block::bordered()
+				.title(title)
+				.title_alignment(Alignment::Left)
+				.border_type(BorderType::Rounded)
+				.padding(Padding::horizontal(1))
+		)
+}
+
+impl Widget for &mut Statistics
+{
+	fn render(self, area: Rect, buf: &mut Buffer)
+		where Self: Sized
+	{
+		// Put everything in a block saying what period's being looked at, with the totals for it along the top
+		let block = Block::bordered()
+			.title(format!(" Statistics ({}) ", self.period))
+			.title_alignment(Alignment::Left)
+			.title_style(self.activeEntry)
+			.border_type(BorderType::Rounded)
+			.padding(Padding::horizontal(1));
+		let inner = block.inner(area);
+		block.render(area, buf);
+
+		let [totals, lists] = Layout::vertical([Constraint::Length(1), Constraint::Fill(1)]).areas(inner);
+		let plays = if self.summary.plays == 1 { "play" } else { "plays" };
+		Line::from_iter
+		([
+			Span::from(format!("{} {plays}, ", self.summary.plays)),
+			Span::from(format!("{} listened", formatDuration(self.summary.listened))),
+			Span::from(" - ←/→ to change period").dim(),
+		])
+			.render(totals, buf);
+
+		// Split the rest up to list the top artists, albums and tracks side by side
+		let layout = Layout::horizontal([Constraint::Fill(1), Constraint::Fill(1), Constraint::Fill(1)])
+			.split(lists);
+		topList(" Top artists ", &self.summary.artists).render(layout[0], buf);
+		topList(" Top albums ", &self.summary.albums).render(layout[1], buf);
+		topList(" Top tracks ", &self.summary.tracks).render(layout[2], buf);
+	}
+}
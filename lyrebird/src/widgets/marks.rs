@@ -0,0 +1,141 @@
+// SPDX-License-Identifier: BSD-3-Clause
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::PathBuf;
+
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+use ratatui::style::{Color, Style};
+use ratatui::widgets::{ListItem, ListState};
+
+/// Style marked items in a list are displayed with
+const MARKED_STYLE: Style = Style::new().fg(Color::LightYellow);
+
+/// The items marked in a list, so that operations can act on several at once rather than just the selected one
+#[derive(Default)]
+pub struct Marks
+{
+	marked: BTreeSet<usize>,
+	/// The item last marked or unmarked, which range marking starts from
+	anchor: Option<usize>,
+	/// Generation of the list the marks were made on
+	generation: Option<u64>,
+	/// The items of the list the marks were made on, for lists that are followed by what's in them
+	items: Vec<PathBuf>,
+}
+
+impl Marks
+{
+	/// Handle the marking keys for a list with the given state and number of items, returning whether the key was
+	/// one of them. `m` toggles the selected item's mark and moves on to the next, `M` marks everything from the
+	/// last item marked to the selected one, Ctrl+A marks everything, `v` inverts the marks, and Esc clears them
+	pub fn handleKeyEvent(&mut self, key: &KeyEvent, state: &mut ListState, count: usize) -> bool
+	{
+		match key.code
+		{
+			KeyCode::Char('m') =>
+			{
+				if let Some(index) = state.selected().filter(|&index| index < count)
+				{
+					self.toggle(index);
+					state.select_next();
+				}
+			},
+			KeyCode::Char('M') =>
+			{
+				if let Some(index) = state.selected().filter(|&index| index < count)
+				{
+					self.markRange(index);
+				}
+			},
+			KeyCode::Char('a') if key.modifiers.contains(KeyModifiers::CONTROL) => self.markAll(count),
+			KeyCode::Char('v') => self.invert(count),
+			KeyCode::Esc => self.clear(),
+			_ => return false,
+		}
+		true
+	}
+
+	pub fn toggle(&mut self, index: usize)
+	{
+		if !self.marked.remove(&index)
+		{
+			self.marked.insert(index);
+		}
+		self.anchor = Some(index);
+	}
+
+	/// Mark everything between the last item marked and the given one, inclusive
+	pub fn markRange(&mut self, index: usize)
+	{
+		let anchor = self.anchor.unwrap_or(index);
+		self.marked.extend(anchor.min(index)..=anchor.max(index));
+		self.anchor = Some(index);
+	}
+
+	pub fn markAll(&mut self, count: usize)
+	{
+		self.marked = (0..count).collect();
+	}
+
+	pub fn invert(&mut self, count: usize)
+	{
+		self.marked = (0..count).filter(|index| !self.marked.contains(index)).collect();
+	}
+
+	pub fn clear(&mut self)
+	{
+		self.marked.clear();
+		self.anchor = None;
+	}
+
+	/// Forget the marks if the list they were made on has changed since, going by the generation given for it, as
+	/// they're kept by position and would otherwise pick out the wrong items
+	pub fn follow(&mut self, generation: u64)
+	{
+		if self.generation != Some(generation)
+		{
+			self.clear();
+			self.generation = Some(generation);
+		}
+	}
+
+	/// Carry the marks over to where their items are in the list given, if it's changed since they were made,
+	/// dropping those whose items are no longer in it - for lists of files that can change underneath the marks
+	/// (eg, as the library's rescanned)
+	pub fn followItems(&mut self, items: &[PathBuf])
+	{
+		if self.items == items
+		{
+			return;
+		}
+		let positions: BTreeMap<_, _> = items.iter().enumerate().map(|(index, item)| (item, index)).collect();
+		let moved = |index: usize| self.items.get(index).and_then(|item| positions.get(item)).copied();
+		let marked = self.marked.iter().filter_map(|&index| moved(index)).collect();
+		let anchor = self.anchor.and_then(moved);
+		(self.marked, self.anchor) = (marked, anchor);
+		self.items = items.to_vec();
+	}
+
+	pub fn isEmpty(&self) -> bool
+	{
+		self.marked.is_empty()
+	}
+
+	/// Get the indices of the marked items in order, or if there are none, the selected item
+	pub fn orSelected(&self, selected: Option<usize>) -> Vec<usize>
+	{
+		if self.marked.is_empty()
+		{
+			return selected.into_iter().collect();
+		}
+		self.marked.iter().copied().collect()
+	}
+
+	/// Display the marked items in the list items given distinctly from the rest
+	pub fn apply<'a, 'b, Items>(&'b self, items: Items) -> impl Iterator<Item = ListItem<'a>> + 'b
+		where Items: Iterator<Item = ListItem<'a>> + 'b
+	{
+		items
+			.enumerate()
+			.map(move |(index, item)| if self.marked.contains(&index) { item.style(MARKED_STYLE) } else { item })
+	}
+}
@@ -1,2 +1,48 @@
 // SPDX-License-Identifier: BSD-3-Clause
+use ratatui::buffer::Buffer;
+use ratatui::layout::{Margin, Rect};
+use ratatui::symbols::scrollbar;
+use ratatui::widgets::{List, ListDirection, ListState, Scrollbar, ScrollbarOrientation, ScrollbarState, StatefulWidget};
+
+pub mod marks;
+pub mod prompt;
 pub mod tabBar;
+
+/// Compute a rectangle of the requested size centred in the given area, clamped to fit within it
+pub fn centredArea(area: Rect, width: u16, height: u16) -> Rect
+{
+	let width = width.min(area.width);
+	let height = height.min(area.height);
+	Rect
+	{
+		x: area.x + (area.width - width) / 2,
+		y: area.y + (area.height - height) / 2,
+		width,
+		height,
+	}
+}
+
+/// Render a list along with a scroll bar showing where the user is scrolled to in it
+pub fn renderScrolledList
+(
+	list: List<'_>, count: usize, viewportHeight: usize, area: Rect, buf: &mut Buffer,
+	(state, scrollbarState): (&mut ListState, &mut ScrollbarState)
+)
+{
+	StatefulWidget::render(list.direction(ListDirection::TopToBottom), area, buf, state);
+
+	// Rebuild the scroll bar to take into account any changes to the list that occured since last redraw
+	*scrollbarState = scrollbarState
+		.content_length(count.saturating_sub(viewportHeight))
+		.position(state.selected().unwrap_or_default().saturating_sub(viewportHeight));
+	StatefulWidget::render
+	(
+		Scrollbar::new(ScrollbarOrientation::VerticalRight)
+			.symbols(scrollbar::VERTICAL)
+			.begin_symbol(None)
+			.end_symbol(None),
+		area.inner(Margin::new(0, 1)),
+		buf,
+		scrollbarState,
+	);
+}
@@ -0,0 +1,197 @@
+// SPDX-License-Identifier: BSD-3-Clause
+use crossterm::event::{KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
+use ratatui::buffer::Buffer;
+use ratatui::layout::{Alignment, Rect};
+use ratatui::style::{Style, Stylize};
+use ratatui::text::{Line, Span};
+use ratatui::widgets::{Block, BorderType, Clear, List, ListDirection, ListState, Padding, StatefulWidget, Widget};
+
+use super::centredArea;
+
+/// A popup that asks the user for some input - either a line of text, a yes/no confirmation, or a choice from a list
+pub struct Prompt
+{
+	/// Title to display on the popup's border
+	title: String,
+	/// What kind of input is being asked for, and the state of it
+	kind: PromptKind,
+	/// Style to apply to the selected item in a choice
+	highlightedStyle: Style,
+}
+
+enum PromptKind
+{
+	/// Asking for a line of text, which is the text entered so far
+	Text(String),
+	/// Asking the user to confirm the question held
+	Confirm(String),
+	/// Asking the user to pick one of the options held
+	Choice(Vec<String>, ListState),
+}
+
+/// What happened as a result of a key event on a prompt
+pub enum PromptResult
+{
+	/// The user has not finished with the prompt yet
+	Pending,
+	/// The user dismissed the prompt without giving an answer
+	Cancelled,
+	/// The user entered the text held
+	Text(String),
+	/// The user said yes to the confirmation
+	Confirmed,
+	/// The user picked the option at the index held
+	Chosen(usize),
+}
+
+impl Prompt
+{
+	/// Construct a prompt asking for a line of text, starting with the initial text given
+	pub fn text(title: &str, initial: &str) -> Self
+	{
+		Self::new(title, PromptKind::Text(initial.to_string()))
+	}
+
+	/// Construct a prompt asking the user to confirm the question given
+	pub fn confirm(title: &str, question: &str) -> Self
+	{
+		Self::new(title, PromptKind::Confirm(question.to_string()))
+	}
+
+	/// Construct a prompt asking the user to pick one of the options given
+	pub fn choice(title: &str, options: Vec<String>) -> Self
+	{
+		let selected = if options.is_empty() { None } else { Some(0) };
+		Self::new(title, PromptKind::Choice(options, ListState::default().with_selected(selected)))
+	}
+
+	fn new(title: &str, kind: PromptKind) -> Self
+	{
+		Self
+		{
+			title: format!(" {title} "),
+			kind,
+			highlightedStyle: Style::default(),
+		}
+	}
+
+	/// Sets the style to use for the selected option of a choice
+	#[must_use = "method moves the value of self and returns the modified value"]
+	pub fn highlightedStyle<S: Into<Style>>(mut self, style: S) -> Self
+	{
+		self.highlightedStyle = style.into();
+		self
+	}
+
+	pub fn handleKeyEvent(&mut self, key: &KeyEvent) -> PromptResult
+	{
+		if key.kind != KeyEventKind::Press && key.kind != KeyEventKind::Repeat
+		{
+			return PromptResult::Pending;
+		}
+
+		// Escape always gets the user out of the prompt
+		if key.code == KeyCode::Esc
+		{
+			return PromptResult::Cancelled;
+		}
+
+		match &mut self.kind
+		{
+			PromptKind::Text(text) =>
+			{
+				match key.code
+				{
+					KeyCode::Enter => { return PromptResult::Text(text.clone()); },
+					KeyCode::Backspace => { text.pop(); },
+					// Only take characters that weren't typed as part of a shortcut
+					KeyCode::Char(character)
+						if !key.modifiers.intersects(KeyModifiers::CONTROL | KeyModifiers::ALT) =>
+						{ text.push(character); },
+					_ => {},
+				}
+			},
+			PromptKind::Confirm(_) =>
+			{
+				match key.code
+				{
+					KeyCode::Enter | KeyCode::Char('y' | 'Y') => { return PromptResult::Confirmed; },
+					KeyCode::Char('n' | 'N') => { return PromptResult::Cancelled; },
+					_ => {},
+				}
+			},
+			PromptKind::Choice(_, state) =>
+			{
+				match key.code
+				{
+					KeyCode::Up => state.select_previous(),
+					KeyCode::Down => state.select_next(),
+					KeyCode::Enter =>
+					{
+						if let Some(index) = state.selected()
+						{
+							return PromptResult::Chosen(index);
+						}
+					},
+					_ => {},
+				}
+			},
+		}
+		PromptResult::Pending
+	}
+}
+
+impl Widget for &mut Prompt
+{
+	fn render(self, area: Rect, buf: &mut Buffer)
+		where Self: Sized
+	{
+		// Work out how much room the prompt needs - wide enough for the title and a reasonable amount of text,
+		// and tall enough for the contents plus the border
+		let contentHeight = match &self.kind
+		{
+			PromptKind::Text(_) | PromptKind::Confirm(_) => 1,
+			PromptKind::Choice(options, _) => options.len().max(1),
+		};
+		let width = u16::try_from(self.title.len() + 4).unwrap_or(u16::MAX).max(40);
+		let height = u16::try_from(contentHeight + 2).unwrap_or(u16::MAX);
+		let area = centredArea(area, width, height);
+
+		// Blank out whatever is under the popup and draw its border
+		Clear.render(area, buf);
+		let block = Block::bordered()
+			.title(self.title.as_str())
+			.title_alignment(Alignment::Left)
+			.border_type(BorderType::Rounded)
+			.padding(Padding::horizontal(1));
+		let inner = block.inner(area);
+		block.render(area, buf);
+
+		// Now draw the contents of the prompt
+		match &mut self.kind
+		{
+			PromptKind::Text(text) =>
+			{
+				// Display the text with a block cursor on the end of it
+				Line::from_iter([Span::raw(text.as_str()), Span::raw(" ").reversed()])
+					.render(inner, buf);
+			},
+			PromptKind::Confirm(question) =>
+			{
+				Line::from_iter([question.as_str(), " (y/n)"]).render(inner, buf);
+			},
+			PromptKind::Choice(options, state) =>
+			{
+				StatefulWidget::render
+				(
+					List::new(options.iter().map(String::as_str))
+						.highlight_style(self.highlightedStyle)
+						.direction(ListDirection::TopToBottom),
+					inner,
+					buf,
+					state
+				);
+			},
+		}
+	}
+}
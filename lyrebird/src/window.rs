@@ -1,9 +1,11 @@
 // SPDX-License-Identifier: BSD-3-Clause
 use std::path::{Path, PathBuf};
+use std::thread;
 use std::time::Duration;
 
+use color_eyre::eyre::eyre;
 use color_eyre::Result;
-use crossterm::event::{Event, EventStream, KeyCode, KeyEventKind};
+use crossterm::event::{Event, EventStream, KeyCode, KeyEvent, KeyEventKind, MediaKeyCode};
 use directories::ProjectDirs;
 use ratatui::buffer::Buffer;
 use ratatui::layout::{Constraint, Flex, Layout, Rect, Size};
@@ -12,14 +14,33 @@ use ratatui::text::{Line, Span};
 use ratatui::widgets::Widget;
 use ratatui::{DefaultTerminal, Frame};
 use tokio::sync::mpsc::{channel, Receiver};
+use tokio::task::{spawn_blocking, JoinError, JoinHandle};
 use tokio_stream::StreamExt;
+use tokio_util::sync::CancellationToken;
+use tracing::error;
 
+use crate::albums::Albums;
+use crate::artists::Artists;
+use crate::history::{History, PlayEventKind};
+use crate::library::MusicLibrary;
+use crate::libraryWatcher::LibraryWatcher;
 use crate::options::OptionsPanel;
 use crate::playback::{PlaybackState, Song};
+use crate::playlist::Playlist;
+use crate::playlistFile::importPlaylist;
 use crate::playlists::Playlists;
+use crate::ratings::{Fingerprint, RatingChange, Ratings};
+use crate::search::{Search, SearchOutcome};
+use crate::signals::ExitSignals;
+use crate::smartPlaylist::SmartContents;
+use crate::statistics::Statistics;
+use crate::widgets::prompt::{Prompt, PromptResult};
 use crate::widgets::tabBar::TabBar;
 use crate::{config::Config, libraryTree::LibraryTree};
 
+/// How far into a song playback has to be for skipping back to restart it rather than go to the previous one
+const RESTART_THRESHOLD: Duration = Duration::from_secs(3);
+
 /// Represents the main window of Lyrebird
 pub struct MainWindow
 {
@@ -29,23 +50,55 @@ pub struct MainWindow
 	activeEntry: Style,
 	footer: Style,
 
+	paths: ProjectDirs,
+	config: Config,
+
 	exit: bool,
 	activeTab: Tab,
 
 	libraryTree: LibraryTree,
+	artists: Artists,
+	albums: Albums,
 	optionsPanel: OptionsPanel,
 	playlists: Playlists,
+	statistics: Statistics,
+	/// Watcher feeding changes to the library in as they happen, if the user wants that
+	libraryWatcher: Option<LibraryWatcher>,
 
 	currentlyPlaying: Option<(Song, Receiver<PlaybackState>)>,
-	errorState: Option<String>
+	/// Record of what's been played, for working out play counts and listening statistics from
+	history: History,
+	/// The user's star ratings and favourites for tracks
+	ratings: Ratings,
+	/// Background task looking for where rated tracks that have gone missing from the library have moved to, and
+	/// the moves it finds
+	relinking: Option<JoinHandle<Vec<(PathBuf, PathBuf)>>>,
+	/// Background task taking the fingerprints of tracks that have just been rated
+	fingerprinting: Option<JoinHandle<Vec<(PathBuf, Fingerprint)>>>,
+	/// Background task working out the contents of smart playlists that are out of date
+	smartRefreshing: Option<JoinHandle<Vec<SmartContents>>>,
+	/// Background task importing a playlist file
+	importing: Option<JoinHandle<Result<Playlist>>>,
+	/// Used to ask the relinking and fingerprinting tasks to stop early when we exit
+	ratingsCancellation: CancellationToken,
+	errorState: Option<String>,
+	/// Prompt asking the user which playlist to add some files to, and the files to add
+	playlistChoice: Option<(Prompt, Vec<PathBuf>)>,
+	/// Search over the library, while the user has it open
+	search: Option<Search>,
+	/// Prompt asking the user what to save a library query as, and the query to save
+	queryName: Option<(Prompt, String)>,
 }
 
 #[derive(Clone, Copy)]
 enum Tab
 {
 	LibraryTree = 0,
+	Artists = 1,
+	Albums = 2,
 	Options = 3,
 	Playlists = 4,
+	Statistics = 5,
 }
 
 impl Tab
@@ -60,33 +113,89 @@ pub enum Operation
 {
 	/// Processing event determined there's nothing needs to be done
 	None,
-	/// Play a file, replacing the Now Playing playlist
-	Play(PathBuf),
+	/// Play some files, replacing the Now Playing playlist with them
+	Play(Vec<PathBuf>),
 	/// Play a file already in the Now Playing playlist as if the current reached `PlaybackState::Complete`
 	PlayNext(PathBuf),
-	/// Add a file to the Now Playing playlist
-	Playlist(PathBuf),
+	/// Add some files to the Now Playing playlist
+	Playlist(Vec<PathBuf>),
+	/// Insert some files into the Now Playing playlist to play straight after the current entry
+	InsertNext(Vec<PathBuf>),
+	/// Add some files to a playlist the user is to choose
+	AddToPlaylist(Vec<PathBuf>),
+	/// Import a playlist file as a new saved playlist
+	ImportPlaylist(PathBuf),
+	/// Export the playlist at the index held in the playlists listing to a file
+	ExportPlaylist(usize, PathBuf),
+	/// Show where a file is in the library's directory tree
+	Reveal(PathBuf),
+	/// Save a library query under a name the user is to choose
+	SaveQuery(String),
+	/// Change the rating of some files
+	Rate(Vec<PathBuf>, RatingChange),
+	/// Something the user asked for couldn't be done, for the reason held
+	Error(String),
 }
 
 impl Operation
 {
-	pub fn playlist(song: Option<PathBuf>) -> Self
+	pub fn play(songs: impl IntoIterator<Item = PathBuf>) -> Self
+	{
+		Self::withSongs(songs, Operation::Play)
+	}
+
+	pub fn playlist(songs: impl IntoIterator<Item = PathBuf>) -> Self
+	{
+		Self::withSongs(songs, Operation::Playlist)
+	}
+
+	pub fn insertNext(songs: impl IntoIterator<Item = PathBuf>) -> Self
+	{
+		Self::withSongs(songs, Operation::InsertNext)
+	}
+
+	pub fn addToPlaylist(songs: impl IntoIterator<Item = PathBuf>) -> Self
+	{
+		Self::withSongs(songs, Operation::AddToPlaylist)
+	}
+
+	pub fn rate(songs: impl IntoIterator<Item = PathBuf>, change: RatingChange) -> Self
+	{
+		Self::withSongs(songs, |songs| Operation::Rate(songs, change))
+	}
+
+	/// Build an operation on the songs given, so long as there are some to operate on
+	fn withSongs(songs: impl IntoIterator<Item = PathBuf>, operation: impl FnOnce(Vec<PathBuf>) -> Self) -> Self
 	{
-		match song
+		let songs: Vec<_> = songs.into_iter().collect();
+		if songs.is_empty()
 		{
-			Some(song) => Operation::Playlist(song),
-			None => Operation::None,
+			return Operation::None;
 		}
+		operation(songs)
 	}
 }
 
 impl MainWindow
 {
 	/// Set up a new main window, building the style pallet needed
-	pub fn new(paths: &ProjectDirs, config: &mut Config, initialSize: Size) -> Result<Self>
+	pub fn new(paths: &ProjectDirs, config: Config, initialSize: Size) -> Result<Self>
 	{
 		let activeEntry = Style::new().light_blue();
 
+		let library = MusicLibrary::new(paths.cache_dir(), &config)?;
+		let viewportSize = Size::new(initialSize.width, initialSize.height.saturating_sub(2));
+		let ratings = Ratings::read(paths)?;
+		let libraryTree = LibraryTree::new(activeEntry, library.clone(), ratings.ratings(), viewportSize);
+		let artists = Artists::new(activeEntry, library.clone(), viewportSize);
+		let albums = Albums::new(activeEntry, library.clone(), viewportSize);
+		let statistics = Statistics::new(activeEntry, library.clone());
+		// Not being able to watch the library isn't fatal, it just means changes won't show up till a rescan
+		let libraryWatcher = LibraryWatcher::new(library)
+			.inspect_err(|error| error!("Failed to watch library for changes: {}", error))
+			.ok()
+			.flatten();
+
 		Ok(Self
 		{
 			header: Style::new().blue().on_black(),
@@ -95,21 +204,32 @@ impl MainWindow
 			activeEntry,
 			footer: Style::new().blue().on_black(),
 
+			paths: paths.clone(),
+			config,
+
 			exit: false,
 			activeTab: Tab::LibraryTree,
 
-			libraryTree: LibraryTree::new
-			(
-				activeEntry,
-				&paths.cache_dir().join("library.json"),
-				&config.libraryPath,
-				Size::new(initialSize.width, initialSize.height.saturating_sub(2)),
-			)?,
+			libraryTree,
+			artists,
+			albums,
 			optionsPanel: OptionsPanel::new(),
-			playlists: Playlists::new(activeEntry),
+			playlists: Playlists::read(paths, activeEntry)?,
+			statistics,
+			libraryWatcher,
 
 			currentlyPlaying: None,
+			history: History::read(paths)?,
+			ratings,
+			relinking: None,
+			fingerprinting: None,
+			smartRefreshing: None,
+			importing: None,
+			ratingsCancellation: CancellationToken::new(),
 			errorState: None,
+			playlistChoice: None,
+			search: None,
+			queryName: None,
 		})
 	}
 
@@ -118,17 +238,38 @@ impl MainWindow
 	{
 		// Set up an events stream for console events happening
 		let mut events = EventStream::new();
+		// Set up listening for the OS asking us to exit
+		let mut exitSignals = ExitSignals::new()?;
 		// Set up a redraw timer
 		let mut frameTimer = tokio::time::interval(Duration::from_secs(1).div_f32(50.0));
+		// Set up a timer for keeping the played time of the current song up to date
+		let mut playbackTimer = tokio::time::interval(Duration::from_millis(250));
+
+		// If the user wants playback picked back up from where they left off last time, do so
+		if self.config.resumePlayback
+		{
+			self.resumePlayback();
+		}
 
 		// Until the user's asked us to exit
 		while !self.exit
 		{
+			// Tracks that have just been rated need their fingerprints taking, which can happen any time
+			self.maybeFingerprintRatings();
+			// Smart playlists follow the library, play history and ratings, so bring them up to date with those
+			self.maybeRefreshSmartPlaylists();
 			// If we're not discovering the library tree any more, check if we don't need to join the background
 			// thread for discovery
 			if !self.libraryTree.isDiscovering()
 			{
 				self.libraryTree.maybeJoinDiscovery().await?;
+				// Now the library's settled, rated tracks that have gone missing from it might be found moved
+				self.maybeRelinkRatings()?;
+				// And any search that's open needs to find what it matches in the library as it is now
+				if let Some(search) = &mut self.search
+				{
+					search.followLibrary();
+				}
 				// Redraw the terminal before trying to process an event
 				terminal.draw(|frame| self.draw(frame))?;
 			}
@@ -140,9 +281,63 @@ impl MainWindow
 					{ terminal.draw(|frame| self.draw(frame))?; },
 				// Ask if there are more events to handle
 				Some(Ok(event)) = events.next() => { self.handleEvent(&event)?; },
+				// If the OS asked us to exit (terminal closed, being shut down, etc), treat it like the user quitting
+				() = exitSignals.recv() => { self.quit(); },
 				// If there is a file playing, check to see if it's giving us any notifications
-				Some(notification) = self.playbackNotification(), if self.currentlyPlaying.is_some() =>
+				Some(notification) = Self::playbackNotification(&mut self.currentlyPlaying),
+					if self.currentlyPlaying.is_some() =>
 					{ self.handlePlaybackNotification(&notification)? },
+				// While a file is playing, wake up periodically so the played time displayed gets redrawn
+				_ = playbackTimer.tick(), if self.currentlyPlaying.is_some() => {},
+				// If the library changed underneath us, wake up so the changes get drawn
+				Some(()) = Self::libraryChanged(&mut self.libraryWatcher), if self.libraryWatcher.is_some() => {},
+				// If we were looking for where rated tracks have moved to, carry their ratings over
+				moves = Self::taskFinished(&mut self.relinking), if self.relinking.is_some() =>
+				{
+					self.relinking = None;
+					match moves
+					{
+						Ok(moves) =>
+						{
+							if self.ratings.relinked(moves)
+							{
+								self.libraryTree.useRatings(self.ratings.ratings());
+							}
+						},
+						Err(error) => error!("Failed to look for where rated tracks moved to: {}", error),
+					}
+				},
+				// If we were taking the fingerprints of newly rated tracks, store them with their ratings
+				fingerprints = Self::taskFinished(&mut self.fingerprinting), if self.fingerprinting.is_some() =>
+				{
+					self.fingerprinting = None;
+					match fingerprints
+					{
+						Ok(fingerprints) => self.ratings.fingerprinted(fingerprints),
+						Err(error) => error!("Failed to fingerprint rated tracks: {}", error),
+					}
+				},
+				// If we were importing a playlist, add it to the others
+				playlist = Self::taskFinished(&mut self.importing), if self.importing.is_some() =>
+				{
+					self.importing = None;
+					match playlist
+					{
+						Ok(Ok(playlist)) => { self.playlists.addImported(playlist); },
+						Ok(Err(error)) => self.errorState = Some(error.to_string()),
+						Err(error) => error!("Failed to import playlist: {}", error),
+					}
+				},
+				// If we were working out what's in smart playlists, show what's in them now
+				contents = Self::taskFinished(&mut self.smartRefreshing), if self.smartRefreshing.is_some() =>
+				{
+					self.smartRefreshing = None;
+					match contents
+					{
+						Ok(contents) => self.playlists.smartPlaylistsRefreshed(contents),
+						Err(error) => error!("Failed to work out smart playlists: {}", error),
+					}
+				},
 			}
 		}
 		Ok(())
@@ -156,125 +351,510 @@ impl MainWindow
 			// Key change event?
 			Event::Key(key) =>
 			{
-				// Key press?
-				if key.kind == KeyEventKind::Press
+				// If we're asking the user which playlist to add to, that gets all the key events till it's dealt with
+				if self.playlistChoice.is_some()
 				{
-					// Check to see if the event is for quitting
-					match key.code
-					{
-						KeyCode::Char('q' | 'Q') => { return self.quit(); },
-						KeyCode::Char(' ') => { self.togglePlayback(); },
-						KeyCode::Char('1') => { self.activeTab = Tab::LibraryTree; }
-						KeyCode::Char('4') => { self.activeTab = Tab::Options; }
-						KeyCode::Char('5') => { self.activeTab = Tab::Playlists; }
-						_ => {}
-					}
+					return self.handlePlaylistChoice(key);
+				}
+				// Or what to save a query as
+				if self.queryName.is_some()
+				{
+					self.handleQueryName(key);
+					return Ok(());
+				}
+				// Likewise if the user's searching the library
+				if self.search.is_some()
+				{
+					return self.handleSearch(key);
+				}
+				// Key press? (so long as the active tab isn't taking all key input, eg for text entry)
+				if key.kind == KeyEventKind::Press && !self.activeTabCapturesInput() && self.handleGlobalKey(key)?
+				{
+					return Ok(());
 				}
 				// It's some other kind of event, so figure out which is the active
 				// tab and ask it what it thinks of this
 				let operation = match self.activeTab
 				{
 					Tab::LibraryTree => self.libraryTree.handleKeyEvent(key),
+					Tab::Artists => self.artists.handleKeyEvent(key),
+					Tab::Albums => self.albums.handleKeyEvent(key),
 					Tab::Options => self.optionsPanel.handleKeyEvent(key),
 					Tab::Playlists => self.playlists.handleKeyEvent(key),
+					Tab::Statistics => self.statistics.handleKeyEvent(key),
 				};
 				// If that key event resulted in a new file to play, process that
-				match operation
-				{
-					Operation::Play(fileName) =>
-					{
-						let song = fileName.as_path();
-						self.playlists.nowPlaying().replaceWith(song);
-						self.playSong(song)?;
-					},
-					Operation::PlayNext(fileName) => self.playSong(fileName.as_path())?,
-					Operation::Playlist(song) => self.playlistSong(song.as_path())?,
-					Operation::None => {},
-				}
+				self.handleOperation(operation)?;
 			},
 			Event::Resize(width, height) =>
 			{
 				self.libraryTree.handleResize(Size::new(*width, *height));
+				self.artists.handleResize(Size::new(*width, *height));
+				self.albums.handleResize(Size::new(*width, *height));
 			},
 			_ => {}
 		}
 		Ok(())
 	}
 
-	fn quit(&mut self) -> Result<()>
+	/// Handle the keys that do the same thing whichever tab is active, returning whether the key was one of them.
+	/// These keys never reach the tabs, so the tabs must not use them for anything of their own:
+	/// q/Q (quit), space (play/pause), > and < (next/previous track), s (stop), z/Z (shuffle mode/reshuffle),
+	/// x (repeat mode), 1-6 (switch tab) and / (search)
+	fn handleGlobalKey(&mut self, key: &KeyEvent) -> Result<bool>
+	{
+		match key.code
+		{
+			KeyCode::Char('q' | 'Q') => self.quit(),
+			KeyCode::Char(' ') | KeyCode::Media(MediaKeyCode::PlayPause) => self.togglePlayback(),
+			KeyCode::Char('>') | KeyCode::Media(MediaKeyCode::TrackNext) => self.nextTrack()?,
+			KeyCode::Char('<') | KeyCode::Media(MediaKeyCode::TrackPrevious) => self.previousTrack()?,
+			KeyCode::Char('s') | KeyCode::Media(MediaKeyCode::Stop) => self.stopPlayback()?,
+			KeyCode::Char('z') => self.playlists.nowPlaying().cycleShuffle(),
+			KeyCode::Char('Z') => self.playlists.nowPlaying().reshuffle(),
+			KeyCode::Char('x') => self.playlists.nowPlaying().cycleRepeat(),
+			KeyCode::Char('1') => self.activeTab = Tab::LibraryTree,
+			KeyCode::Char('2') => self.activeTab = Tab::Artists,
+			KeyCode::Char('3') => self.activeTab = Tab::Albums,
+			KeyCode::Char('4') => self.activeTab = Tab::Options,
+			KeyCode::Char('5') => self.activeTab = Tab::Playlists,
+			KeyCode::Char('6') => self.activeTab = Tab::Statistics,
+			KeyCode::Char('/') =>
+			{
+				let library = self.libraryTree.libraryHandle();
+				let savedQueries = self.config.savedQueries.clone();
+				let (statistics, ratings) = (self.history.statistics(), self.ratings.ratings());
+				self.search = Some(Search::new(self.activeEntry, library, savedQueries, statistics, ratings));
+			},
+			// Anything else is for the active tab to deal with
+			_ => return Ok(false),
+		}
+		Ok(true)
+	}
+
+	/// Carry out an operation a tab (or the search) asked for
+	fn handleOperation(&mut self, operation: Operation) -> Result<()>
+	{
+		match operation
+		{
+		Operation::Play(songs) =>
+		{
+			let nowPlaying = self.playlists.nowPlaying();
+			nowPlaying.replaceWith(&songs);
+			if let Some(song) = nowPlaying.current().map(Path::to_path_buf)
+			{
+				self.playSong(song.as_path())?;
+			}
+		},
+		Operation::PlayNext(fileName) => self.playSong(fileName.as_path())?,
+		Operation::Playlist(songs) => self.playlistSongs(&songs)?,
+		Operation::InsertNext(songs) => self.insertSongsNext(&songs)?,
+		Operation::AddToPlaylist(songs) =>
+		{
+			let prompt = Prompt::choice("Add to playlist", self.playlists.names())
+				.highlightedStyle(self.activeEntry);
+			self.playlistChoice = Some((prompt, songs));
+		},
+		Operation::ImportPlaylist(fileName) =>
+		{
+			if self.importing.is_some()
+			{
+				self.errorState = Some("Already importing a playlist".to_string());
+				return Ok(());
+			}
+			// Entries that can't be found where the playlist says get looked for in the library, which means going
+			// through all of it, so that's done in the background
+			let library = self.libraryTree.libraryHandle();
+			self.importing = Some
+			(
+				spawn_blocking
+				(
+					move ||
+					{
+						let library = library.read().map_err(|error| eyre!("While importing playlist: {}", error))?;
+						importPlaylist(&fileName, Some(&library))
+					}
+				)
+			);
+		},
+		Operation::ExportPlaylist(index, fileName) =>
+		{
+			let library = self.libraryTree.library()?;
+			if let Err(error) = self.playlists.exportAt(index, &fileName, &library)
+			{
+				self.errorState = Some(error.to_string());
+			}
+		},
+		Operation::Reveal(fileName) =>
+		{
+			self.libraryTree.reveal(&fileName);
+			self.activeTab = Tab::LibraryTree;
+		},
+		Operation::SaveQuery(query) =>
+			{ self.queryName = Some((Prompt::text("Save query as", ""), query)); },
+		Operation::Rate(songs, change) =>
+		{
+			self.ratings.change(&songs, change);
+			self.libraryTree.useRatings(self.ratings.ratings());
+		},
+		Operation::Error(error) => { self.errorState = Some(error); },
+		Operation::None => {},
+		}
+		Ok(())
+	}
+
+	const fn activeTabCapturesInput(&self) -> bool
+	{
+		match self.activeTab
+		{
+			Tab::Playlists => self.playlists.capturesInput(),
+			Tab::LibraryTree | Tab::Artists | Tab::Albums | Tab::Options | Tab::Statistics => false,
+		}
+	}
+
+	fn handlePlaylistChoice(&mut self, key: &KeyEvent) -> Result<()>
+	{
+		let Some((prompt, _)) = &mut self.playlistChoice
+			else { return Ok(()); };
+
+		match prompt.handleKeyEvent(key)
+		{
+			PromptResult::Pending => {},
+			PromptResult::Chosen(index) =>
+			{
+				let songs = self.playlistChoice.take().map(|(_, songs)| songs).unwrap_or_default();
+				// Adding to Now Playing (the first choice) works the same as enqueuing the files normally
+				if index == 0
+				{
+					return self.playlistSongs(&songs);
+				}
+				self.playlists.addTo(index, &songs);
+			},
+			_ => { self.playlistChoice = None; },
+		}
+		Ok(())
+	}
+
+	fn handleSearch(&mut self, key: &KeyEvent) -> Result<()>
+	{
+		let Some(search) = &mut self.search
+			else { return Ok(()); };
+
+		match search.handleKeyEvent(key)
+		{
+			SearchOutcome::Pending => Ok(()),
+			SearchOutcome::Closed =>
+			{
+				self.search = None;
+				Ok(())
+			},
+			SearchOutcome::Act(operation) => self.handleOperation(operation),
+			SearchOutcome::Finish(operation) =>
+			{
+				self.search = None;
+				self.handleOperation(operation)
+			},
+		}
+	}
+
+	fn handleQueryName(&mut self, key: &KeyEvent)
+	{
+		let Some((prompt, _)) = &mut self.queryName
+			else { return; };
+
+		match prompt.handleKeyEvent(key)
+		{
+			PromptResult::Pending => {},
+			PromptResult::Text(name) =>
+			{
+				let query = self.queryName.take().map(|(_, query)| query).unwrap_or_default();
+				if let Err(error) = self.config.saveQuery(&name, &query)
+				{
+					self.errorState = Some(error.to_string());
+				}
+				// Let the search use the newly saved query straight away
+				if let Some(search) = &mut self.search
+				{
+					search.useSavedQueries(self.config.savedQueries.clone());
+				}
+				// Likewise any smart playlists built on it
+				self.playlists.invalidateSmartPlaylists();
+			},
+			_ => { self.queryName = None; },
+		}
+	}
+
+	const fn quit(&mut self)
+		{ self.exit = true; }
+
+	/// Write out all the user's state - configuration, library cache, playlists, etc. Every part is attempted
+	/// even if an earlier one fails so as much as possible gets saved, and the first failure is returned
+	pub fn writeState(&mut self) -> Result<()>
 	{
-		self.exit = true;
-		self.libraryTree.writeCache()
+		// Make sure the Now Playing playlist and the play history know how far into the current song we got
+		if let Some((song, _)) = &self.currentlyPlaying
+		{
+			let (fileName, position) = (song.fileName().to_path_buf(), song.playedDuration());
+			self.playlists.nowPlaying().setPosition(position);
+			self.recordPlay(&fileName, PlayEventKind::Stopped, position);
+		}
+
+		// Don't keep the exit waiting on looking for moved tracks or fingerprinting rated ones
+		self.ratingsCancellation.cancel();
+		let config = self.config.write(&self.paths);
+		let libraryCache = self.libraryTree.writeCache();
+		let playlists = self.playlists.write(&self.paths);
+		let ratings = self.ratings.write(&self.paths);
+		config.and(libraryCache).and(playlists).and(ratings)
 	}
 
 	// Draw the program window to the terminal
 	fn draw(&mut self, frame: &mut Frame)
 	{
+		// The statistics follow the play history, but only need totalling up again while they're shown
+		if let Tab::Statistics = self.activeTab
+		{
+			self.statistics.refresh(&self.history);
+		}
 		frame.render_widget(self, frame.area());
 	}
 
+	/// Note something happening to the playback of a song in the play history
+	fn recordPlay(&mut self, fileName: &Path, kind: PlayEventKind, position: Duration)
+	{
+		if let Err(error) = self.history.record(fileName, kind, position)
+		{
+			self.errorState = Some(error.to_string());
+		}
+	}
+
 	fn playSong(&mut self, fileName: &Path) -> Result<()>
+	{
+		self.playSongFrom(fileName, Duration::ZERO)
+	}
+
+	fn playSongFrom(&mut self, fileName: &Path, position: Duration) -> Result<()>
 	{
 		// Make a new channel for the new playback thread to communicate back to us with
 		let (sender, receiver) = channel(1);
 		let mut song = Song::from(fileName, sender)?;
+		// Skip to where we've been asked to start playing from, and note that as the Now Playing position
+		if !position.is_zero()
+		{
+			song.skipTo(position);
+		}
+		self.playlists.nowPlaying().setPosition(position);
 		let currentlyPlaying = self.currentlyPlaying.take();
-		// If we already have a song playing, stop it
+		// If we already have a song playing, stop it - unless it already finished, that means it got skipped
+		// (or restarted, if it's the same song again)
 		if let Some((mut currentSong, _)) = currentlyPlaying
 		{
+			if currentSong.state() != PlaybackState::Complete
+			{
+				let kind = if currentSong.fileName() == fileName
+					{ PlayEventKind::Stopped } else { PlayEventKind::Skipped };
+				self.recordPlay(currentSong.fileName(), kind, currentSong.playedDuration());
+			}
 			currentSong.stop()?;
 		}
 		// Now replace the current playing state with the new one having asked this new one to start
+		self.recordPlay(fileName, PlayEventKind::Started, position);
 		song.play();
 		self.currentlyPlaying = Some((song, receiver));
 		Ok(())
 	}
 
-	fn playlistSong(&mut self, fileName: &Path) -> Result<()>
+	fn playlistSongs(&mut self, fileNames: &[PathBuf]) -> Result<()>
 	{
 		let nowPlaying = self.playlists.nowPlaying();
-		nowPlaying.add(fileName);
-		match &self.currentlyPlaying
+		let firstNewEntry = nowPlaying.entryCount();
+		for fileName in fileNames
+		{
+			nowPlaying.add(fileName);
+		}
+		if self.currentlyPlaying.is_some() || fileNames.is_empty()
 		{
-			Some(_) => Ok(()),
-			None => self.playSong(fileName),
+			return Ok(());
+		}
+		// Nothing is playing, so the first new entry becomes the current one and gets played
+		nowPlaying.nextEntry(firstNewEntry);
+		self.playSong(&fileNames[0])
+	}
+
+	fn insertSongsNext(&mut self, fileNames: &[PathBuf]) -> Result<()>
+	{
+		let nowPlaying = self.playlists.nowPlaying();
+		let firstNewEntry = nowPlaying.insertNext(fileNames);
+		if self.currentlyPlaying.is_some() || fileNames.is_empty()
+		{
+			return Ok(());
+		}
+		// Nothing is playing, so the first new entry becomes the current one and gets played
+		nowPlaying.nextEntry(firstNewEntry);
+		self.playSong(&fileNames[0])
+	}
+
+	/// Pick playback of the Now Playing playlist back up from its current entry and position
+	fn resumePlayback(&mut self)
+	{
+		let nowPlaying = self.playlists.nowPlaying();
+		let position = nowPlaying.position();
+		let Some(fileName) = nowPlaying.current().map(Path::to_path_buf)
+			else { return; };
+
+		if let Err(error) = self.playSongFrom(fileName.as_path(), position)
+		{
+			self.errorState = Some(error.to_string());
 		}
 	}
 
 	fn togglePlayback(&mut self)
 	{
-		if let Some((song, _)) = &mut self.currentlyPlaying
+		// If nothing is playing, see if we can pick the Now Playing playlist back up
+		let Some((song, _)) = &mut self.currentlyPlaying
+			else { return self.resumePlayback(); };
+
+		match song.state()
 		{
-			match song.state()
+			PlaybackState::Playing =>
 			{
-				PlaybackState::Playing =>
+				let result = song.pause();
+				if let Err(error) = result
 				{
-					let result = song.pause();
-					if let Err(error) = result
-					{
-						self.errorState = Some(error.to_string());
-					}
-				},
-				PlaybackState::Paused |
-				PlaybackState::Stopped |
-				PlaybackState::NotStarted =>
-					{ song.play(); }
-				PlaybackState::Complete => {}
-				PlaybackState::Unknown(error) =>
-					{ self.errorState = Some(error); }
-			}
+					self.errorState = Some(error.to_string());
+				}
+			},
+			PlaybackState::Paused |
+			PlaybackState::Stopped |
+			PlaybackState::NotStarted =>
+				{ song.play(); }
+			PlaybackState::Complete => {}
+			PlaybackState::Unknown(error) =>
+				{ self.errorState = Some(error); }
 		}
 	}
 
+	/// Skip forward to the next entry in the Now Playing playlist
+	fn nextTrack(&mut self) -> Result<()>
+	{
+		// If there's nothing to skip forward to, just stop playback, leaving the current entry where it is
+		if self.playlists.nowPlaying().peek().is_none()
+		{
+			return self.stopPlayback();
+		}
+		match self.playlists.nowPlaying().skip()
+		{
+			Some(fileName) => self.playSong(fileName.as_path()),
+			None => Ok(()),
+		}
+	}
+
+	/// Skip back to the start of the current song if we're far enough into it, otherwise to the previous entry
+	/// in the Now Playing playlist
+	fn previousTrack(&mut self) -> Result<()>
+	{
+		let played = self.currentlyPlaying.as_ref()
+			.map_or_else(Duration::default, |(song, _)| song.playedDuration());
+		let nowPlaying = self.playlists.nowPlaying();
+		let fileName = if played > RESTART_THRESHOLD
+		{
+			nowPlaying.current().map(Path::to_path_buf)
+		}
+		else
+		{
+			nowPlaying.previous()
+		};
+
+		match fileName
+		{
+			Some(fileName) => self.playSong(fileName.as_path()),
+			None => Ok(()),
+		}
+	}
+
+	/// Stop playback entirely - resuming playback then starts the current entry over from the beginning
+	fn stopPlayback(&mut self) -> Result<()>
+	{
+		if let Some((mut song, _)) = self.currentlyPlaying.take()
+		{
+			self.recordPlay(song.fileName(), PlayEventKind::Stopped, song.playedDuration());
+			song.stop()?;
+		}
+		self.playlists.nowPlaying().setPosition(Duration::ZERO);
+		Ok(())
+	}
+
 	// Wait for a playback notification from the currently playing song - note, it is an
 	// error to call this function if self.currentlyPlaying is None!
-	async fn playbackNotification(&mut self) -> Option<PlaybackState>
+	// These take the fields they wait on rather than self so they can be waited on together
+	async fn playbackNotification(currentlyPlaying: &mut Option<(Song, Receiver<PlaybackState>)>)
+		-> Option<PlaybackState>
 	{
 		#[expect(clippy::unwrap_used, reason = "impossible in context")]
-		let (_, channel) = self.currentlyPlaying.as_mut().unwrap();
+		let (_, channel) = currentlyPlaying.as_mut().unwrap();
 		channel.recv().await
 	}
 
+	async fn libraryChanged(libraryWatcher: &mut Option<LibraryWatcher>) -> Option<()>
+	{
+		#[expect(clippy::unwrap_used, reason = "impossible in context")]
+		libraryWatcher.as_mut().unwrap().changed().await
+	}
+
+	async fn taskFinished<T>(task: &mut Option<JoinHandle<T>>) -> Result<T, JoinError>
+	{
+		#[expect(clippy::unwrap_used, reason = "impossible in context")]
+		task.as_mut().unwrap().await
+	}
+
+	/// Start looking for where rated tracks that have gone missing from the library have moved to in the
+	/// background, if the library's changed since that was last done and we're not already looking
+	fn maybeRelinkRatings(&mut self) -> Result<()>
+	{
+		if self.relinking.is_some()
+		{
+			return Ok(());
+		}
+		let library = self.libraryTree.library()?;
+		if let Some(relink) = self.ratings.relink(&library)
+		{
+			let cancellation = self.ratingsCancellation.clone();
+			self.relinking = Some(spawn_blocking(move || relink.run(&cancellation)));
+		}
+		Ok(())
+	}
+
+	/// Start working out the contents of smart playlists that are out of date in the background, if there are any
+	/// and we're not already doing so
+	fn maybeRefreshSmartPlaylists(&mut self)
+	{
+		if self.smartRefreshing.is_some()
+		{
+			return;
+		}
+		let library = self.libraryTree.libraryHandle();
+		if let Some(refresh) =
+			self.playlists.refreshSmartPlaylists(library, &self.history, &self.ratings, &self.config.savedQueries)
+		{
+			self.smartRefreshing = Some(spawn_blocking(move || refresh.run()));
+		}
+	}
+
+	/// Start taking the fingerprints of tracks that have been rated in the background, if there are any and we're
+	/// not already doing so
+	fn maybeFingerprintRatings(&mut self)
+	{
+		if self.fingerprinting.is_some()
+		{
+			return;
+		}
+		if let Some(fingerprinting) = self.ratings.fingerprint()
+		{
+			let cancellation = self.ratingsCancellation.clone();
+			self.fingerprinting = Some(spawn_blocking(move || fingerprinting.run(&cancellation)));
+		}
+	}
+
 	fn handlePlaybackNotification(&mut self, notification: &PlaybackState) -> Result<()>
 	{
 		match notification
@@ -283,12 +863,22 @@ impl MainWindow
 			// to play in the now playing playlist, and set it going if there is
 			PlaybackState::Complete =>
 			{
+				if let Some((song, _)) = &self.currentlyPlaying
+				{
+					let (fileName, position) = (song.fileName().to_path_buf(), song.playedDuration());
+					self.recordPlay(&fileName, PlayEventKind::Completed, position);
+				}
 				let nowPlaying = self.playlists.nowPlaying();
 				let nextEntry = nowPlaying.next();
-				match nextEntry
+				if let Some(fileName) = nextEntry
+				{
+					self.playSong(fileName.as_path())?;
+				}
+				else
 				{
-					Some(fileName) => self.playSong(fileName.as_path())?,
-					None => self.currentlyPlaying = None,
+					// We ran off the end, so there's nothing left to resume
+					nowPlaying.setPosition(Duration::ZERO);
+					self.currentlyPlaying = None;
 				}
 			},
 			_ => {},
@@ -297,6 +887,21 @@ impl MainWindow
 	}
 }
 
+impl Drop for MainWindow
+{
+	fn drop(&mut self)
+	{
+		// If we're being torn down because of a panic, make a best effort at saving the user's state
+		if thread::panicking()
+		{
+			if let Err(error) = self.writeState()
+			{
+				error!("Failed to save state while handling a panic: {}", error);
+			}
+		}
+	}
+}
+
 fn durationAsString(duration: Duration) -> String
 {
 	if duration.is_zero()
@@ -325,7 +930,7 @@ impl Widget for &mut MainWindow
 		).split(area);
 
 		// Make the header tab titles
-		let headerTabs = ["Tree", "Artists", "Albums", "Options", "Playlist"]
+		let headerTabs = ["Tree", "Artists", "Albums", "Options", "Playlist", "Stats"]
 			.map(ToString::to_string)
 			.into_iter()
 			.enumerate()
@@ -356,14 +961,32 @@ impl Widget for &mut MainWindow
 		match self.activeTab
 		{
 			Tab::LibraryTree => self.libraryTree.render(areas[1], buf),
+			Tab::Artists => self.artists.render(areas[1], buf),
+			Tab::Albums => self.albums.render(areas[1], buf),
 			Tab::Options => self.optionsPanel.render(areas[1], buf),
 			Tab::Playlists => self.playlists.render(areas[1], buf),
+			Tab::Statistics => self.statistics.render(areas[1], buf),
+		}
+		// If the user's searching the library, draw that over the top of the active tab
+		if let Some(search) = &mut self.search
+		{
+			search.render(areas[1], buf);
+		}
+		// If we're asking what to save a query as, draw that over the top of the search
+		if let Some((prompt, _)) = &mut self.queryName
+		{
+			prompt.render(areas[1], buf);
+		}
+		// If we're asking which playlist to add to, draw that over the top of the active tab (and search)
+		if let Some((prompt, _)) = &mut self.playlistChoice
+		{
+			prompt.render(areas[1], buf);
 		}
 
 		// Build a layout for the footer line
 		let (footerLayout, footerSpacers ) = Layout::horizontal
 		(
-			[Constraint::Percentage(50), Constraint::Fill(1), Constraint::Fill(3)]
+			[Constraint::Percentage(50), Constraint::Fill(1), Constraint::Length(26), Constraint::Fill(3)]
 		)
 			.flex(Flex::SpaceBetween)
 			.spacing(1)
@@ -384,19 +1007,25 @@ impl Widget for &mut MainWindow
 				|| String::from("--:--"),
 				|(song, _)| durationAsString(song.playedDuration())
 			);
+		let nowPlaying = self.playlists.nowPlaying();
+		let playbackModes = format!("{}, {}", nowPlaying.shuffle(), nowPlaying.repeat());
 		let errorState = self.errorState.as_ref().map_or_else
 		(
 			|| String::from("No errors"), Clone::clone
 		);
 
-		// Display the program footer - which song is currently playing, song runtime, and whether errors have occured
+		// Display the program footer - which song is currently playing, song runtime, the shuffle and repeat modes,
+		// and whether errors have occured
 		Line::from_iter([String::from(" "), currentlyPlaying])
 			.style(self.footer)
 			.render(footerLayout[0], buf);
 		Line::styled(format!("{playedDuration}/{songDuration}"), self.footer)
 			.centered()
 			.render(footerLayout[1], buf);
-		Line::styled(errorState, self.footer).render(footerLayout[2], buf);
+		Line::styled(playbackModes, self.footer)
+			.centered()
+			.render(footerLayout[2], buf);
+		Line::styled(errorState, self.footer).render(footerLayout[3], buf);
 
 		// Render the spacers for all the components of the footer
 		for spacerRect in footerSpacers.iter()